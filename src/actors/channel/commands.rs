@@ -0,0 +1,773 @@
+use std::fmt;
+
+use once_cell::sync::Lazy;
+
+use crate::actors::gpt::ChatMessage;
+
+use super::ChannelState;
+
+static REGISTRY: Lazy<CommandRegistry> = Lazy::new(CommandRegistry::new);
+
+/// The commands every channel understands, see [`CommandRegistry::new`].
+pub fn registry() -> &'static CommandRegistry {
+    &REGISTRY
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Text,
+    /// An `http(s)://` url.
+    Url,
+}
+
+/// A positional argument of a command.
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+    /// Takes every remaining positional argument, only valid for the last one.
+    pub repeated: bool,
+}
+
+/// A `--name` or `--name=<value>` option of a command.
+pub struct Flag {
+    pub name: &'static str,
+    /// Placeholder of the value in the usage text, `None` for switches.
+    pub value: Option<&'static str>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UsageError {
+    MissingArgument(&'static str),
+    UnexpectedArgument(String),
+    InvalidUrl(String),
+    UnknownFlag(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsageError::MissingArgument(name) => write!(f, "missing <{name}>"),
+            UsageError::UnexpectedArgument(arg) => write!(f, "unexpected argument {arg}"),
+            UsageError::InvalidUrl(arg) => write!(f, "{arg} is not a url"),
+            UsageError::UnknownFlag(flag) => write!(f, "unknown option --{flag}"),
+            UsageError::MissingValue(flag) => write!(f, "--{flag} needs a value"),
+            UsageError::UnexpectedValue(flag) => write!(f, "--{flag} does not take a value"),
+        }
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Arguments of a command invocation that passed its schema.
+#[derive(Debug, Default, PartialEq)]
+pub struct CommandArgs {
+    pub positional: Vec<String>,
+    pub flags: Vec<(String, Option<String>)>,
+}
+
+impl CommandArgs {
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.positional.get(index).map(String::as_str)
+    }
+
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|(flag, _)| flag == name)
+    }
+
+    /// The flags as they were typed, for parsers like `parse_scrape_args`.
+    pub fn flag_line(&self) -> String {
+        self.flags
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("--{name}={value}"),
+                None => format!("--{name}"),
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+
+    fn args(&self) -> &'static [Arg] {
+        &[]
+    }
+
+    fn flags(&self) -> &'static [Flag] {
+        &[]
+    }
+
+    /// Builtin commands can not be disabled through a channel's `tools`.
+    fn builtin(&self) -> bool {
+        false
+    }
+
+    /// Takes no flags, the last argument is the rest of the message exactly as it was typed.
+    fn verbatim(&self) -> bool {
+        false
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage);
+}
+
+/// One line summary like `!github <target>... [--clone] [--max-size=<bytes>]`.
+pub fn usage(command: &dyn Command) -> String {
+    let mut usage = format!("!{}", command.name());
+    for arg in command.args() {
+        let repeated = if arg.repeated { "..." } else { "" };
+        match arg.required {
+            true => usage.push_str(&format!(" <{}>{}", arg.name, repeated)),
+            false => usage.push_str(&format!(" [{}]{}", arg.name, repeated)),
+        }
+    }
+    for flag in command.flags() {
+        match flag.value {
+            Some(value) => usage.push_str(&format!(" [--{}=<{}>]", flag.name, value)),
+            None => usage.push_str(&format!(" [--{}]", flag.name)),
+        }
+    }
+    usage
+}
+
+/// Checks `params` against the schema of `command`.
+pub fn parse_args(command: &dyn Command, params: Option<&str>) -> Result<CommandArgs, UsageError> {
+    let mut args = CommandArgs::default();
+    if command.verbatim() {
+        let mut rest = params.unwrap_or_default().trim();
+        for _ in 1..command.args().len() {
+            if rest.is_empty() {
+                break;
+            }
+            let (word, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            args.positional.push(word.to_owned());
+            rest = remainder.trim_start();
+        }
+        if !rest.is_empty() {
+            args.positional.push(rest.to_owned());
+        }
+    }
+
+    let tokens = match command.verbatim() {
+        true => "",
+        false => params.unwrap_or_default(),
+    };
+    for token in tokens.split_whitespace() {
+        let Some(flag) = token.strip_prefix("--") else {
+            args.positional.push(token.to_owned());
+            continue;
+        };
+
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (flag, None),
+        };
+        let Some(spec) = command.flags().iter().find(|f| f.name == name) else {
+            return Err(UsageError::UnknownFlag(name.to_owned()));
+        };
+        match (spec.value, &value) {
+            (Some(_), None) => return Err(UsageError::MissingValue(name.to_owned())),
+            (None, Some(_)) => return Err(UsageError::UnexpectedValue(name.to_owned())),
+            _ => args.flags.push((name.to_owned(), value)),
+        }
+    }
+
+    let schema = command.args();
+    for (index, arg) in schema.iter().enumerate() {
+        let values = match arg.repeated {
+            true => args.positional.get(index..).unwrap_or_default(),
+            false => args.positional.get(index..=index).unwrap_or_default(),
+        };
+        if values.is_empty() && arg.required {
+            return Err(UsageError::MissingArgument(arg.name));
+        }
+        if let Some(value) = values.iter().find(|value| {
+            arg.kind == ArgKind::Url
+                && !(value.starts_with("https://") || value.starts_with("http://"))
+        }) {
+            return Err(UsageError::InvalidUrl(value.clone()));
+        }
+    }
+
+    let accepted = match schema.last() {
+        Some(arg) if arg.repeated => usize::MAX,
+        _ => schema.len(),
+    };
+    if let Some(extra) = args.positional.get(accepted) {
+        return Err(UsageError::UnexpectedArgument(extra.clone()));
+    }
+
+    Ok(args)
+}
+
+/// Splits `!command params` into the command and its parameters, `None` when the message does
+/// not start with a command word.
+pub fn command_extract(content: &str) -> Option<(String, Option<String>)> {
+    let content = content.trim_start().strip_prefix('!')?;
+    let (command, params) = match content.split_once(char::is_whitespace) {
+        Some((command, params)) => (command, Some(params.trim())),
+        None => (content.trim_end(), None),
+    };
+    if command.is_empty() || !command.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let params = params.filter(|p| !p.is_empty()).map(str::to_owned);
+    Some((command.to_lowercase(), params))
+}
+
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry {
+            commands: vec![
+                Box::new(HelpCommand),
+                Box::new(ToolsCommand),
+                Box::new(ModelCommand),
+                Box::new(DebugCommand),
+                Box::new(PersonaCommand),
+                Box::new(JobsCommand),
+                Box::new(CancelCommand),
+                Box::new(TranscribeCommand),
+                Box::new(GithubCommand),
+                Box::new(ConfluenceCommand),
+                Box::new(KnowledgeBaseCommand),
+            ],
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        self.commands
+            .iter()
+            .find(|command| command.name() == name)
+            .map(Box::as_ref)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.iter().map(Box::as_ref)
+    }
+
+    /// Names of the commands a channel can switch on and off, all enabled for new channels.
+    pub fn tools(&self) -> Vec<String> {
+        self.commands()
+            .filter(|command| !command.builtin())
+            .map(|command| command.name().to_owned())
+            .collect()
+    }
+
+    /// Lists the commands enabled by `tools`, or explains `name` in detail.
+    pub fn help(&self, tools: &[String], name: Option<&str>) -> String {
+        let enabled = |command: &&dyn Command| {
+            command.builtin() || tools.iter().any(|tool| tool == command.name())
+        };
+
+        let Some(name) = name else {
+            let lines: Vec<String> = self
+                .commands()
+                .filter(enabled)
+                .map(|command| format!("!{} - {}", command.name(), command.description()))
+                .collect();
+            return format!(
+                "Available commands:\n{}\nUse !help <command> for details",
+                lines.join("\n")
+            );
+        };
+
+        match self.find(name.trim_start_matches('!')) {
+            Some(command) => {
+                let mut help = format!("{}\nUsage: {}", command.description(), usage(command));
+                if !enabled(&command) {
+                    help.push_str(&format!(
+                        "\nDisabled in this channel, enable it with !tools enable {}",
+                        command.name()
+                    ));
+                }
+                help
+            }
+            None => format!("Unknown command {name}"),
+        }
+    }
+}
+
+struct HelpCommand;
+
+#[async_trait::async_trait]
+impl Command for HelpCommand {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the available commands or explains one"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[Arg {
+            name: "command",
+            kind: ArgKind::Text,
+            required: false,
+            repeated: false,
+        }]
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        let help = registry().help(&channel.tools, args.get(0));
+        channel.send_message(message, help);
+    }
+}
+
+struct ToolsCommand;
+
+#[async_trait::async_trait]
+impl Command for ToolsCommand {
+    fn name(&self) -> &'static str {
+        "tools"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the tools of this channel, or enables and disables one"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[
+            Arg {
+                name: "enable|disable",
+                kind: ArgKind::Text,
+                required: false,
+                repeated: false,
+            },
+            Arg {
+                name: "tool",
+                kind: ArgKind::Text,
+                required: false,
+                repeated: false,
+            },
+        ]
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.tools_command(args, message);
+    }
+}
+
+struct ModelCommand;
+
+#[async_trait::async_trait]
+impl Command for ModelCommand {
+    fn name(&self) -> &'static str {
+        "model"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows or switches the model answering in this channel"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[Arg {
+            name: "model",
+            kind: ArgKind::Text,
+            required: false,
+            repeated: false,
+        }]
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.model_command(args, message);
+    }
+}
+
+struct DebugCommand;
+
+#[async_trait::async_trait]
+impl Command for DebugCommand {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows the embeddings used for the last answer"
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, _args: CommandArgs, message: ChatMessage) {
+        channel.debug_command(message).await;
+    }
+}
+
+struct PersonaCommand;
+
+#[async_trait::async_trait]
+impl Command for PersonaCommand {
+    fn name(&self) -> &'static str {
+        "persona"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows, sets or resets the system prompt of this channel, or switches to a preset persona"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[
+            Arg {
+                name: "set|use|reset|list",
+                kind: ArgKind::Text,
+                required: false,
+                repeated: false,
+            },
+            Arg {
+                name: "prompt or persona",
+                kind: ArgKind::Text,
+                required: false,
+                repeated: true,
+            },
+        ]
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    fn verbatim(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.persona_command(args, message);
+    }
+}
+
+struct JobsCommand;
+
+#[async_trait::async_trait]
+impl Command for JobsCommand {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the tools running in the background of this channel"
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, _args: CommandArgs, message: ChatMessage) {
+        channel.jobs_command(message);
+    }
+}
+
+struct CancelCommand;
+
+#[async_trait::async_trait]
+impl Command for CancelCommand {
+    fn name(&self) -> &'static str {
+        "cancel"
+    }
+
+    fn description(&self) -> &'static str {
+        "Stops a background job, whatever it finished so far is kept"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[Arg {
+            name: "id",
+            kind: ArgKind::Text,
+            required: true,
+            repeated: false,
+        }]
+    }
+
+    fn builtin(&self) -> bool {
+        true
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.cancel_command(args, message);
+    }
+}
+
+struct TranscribeCommand;
+
+#[async_trait::async_trait]
+impl Command for TranscribeCommand {
+    fn name(&self) -> &'static str {
+        "transcribe"
+    }
+
+    fn description(&self) -> &'static str {
+        "Transcribes videos, audio files and attachments and adds them to the channel's knowledge"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        // attachments of the message are transcribed as well
+        &[Arg {
+            name: "url",
+            kind: ArgKind::Url,
+            required: false,
+            repeated: true,
+        }]
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.transcribe_command(args, message).await;
+    }
+}
+
+struct GithubCommand;
+
+#[async_trait::async_trait]
+impl Command for GithubCommand {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    fn description(&self) -> &'static str {
+        "Ingests GitHub repositories and organisations, git remotes or local checkouts"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[Arg {
+            name: "url or path",
+            kind: ArgKind::Text,
+            required: true,
+            repeated: true,
+        }]
+    }
+
+    fn flags(&self) -> &'static [Flag] {
+        &[
+            Flag {
+                name: "clone",
+                value: None,
+            },
+            Flag {
+                name: "threads",
+                value: None,
+            },
+            Flag {
+                name: "max-threads",
+                value: Some("count"),
+            },
+            Flag {
+                name: "archived",
+                value: None,
+            },
+            Flag {
+                name: "forks",
+                value: None,
+            },
+            Flag {
+                name: "include",
+                value: Some("glob"),
+            },
+            Flag {
+                name: "exclude",
+                value: Some("glob"),
+            },
+            Flag {
+                name: "language",
+                value: Some("language"),
+            },
+            Flag {
+                name: "path",
+                value: Some("glob"),
+            },
+            Flag {
+                name: "skip",
+                value: Some("glob"),
+            },
+            Flag {
+                name: "max-size",
+                value: Some("bytes"),
+            },
+            Flag {
+                name: "no-default-ignores",
+                value: None,
+            },
+        ]
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.github_command(args, message).await;
+    }
+}
+
+struct ConfluenceCommand;
+
+#[async_trait::async_trait]
+impl Command for ConfluenceCommand {
+    fn name(&self) -> &'static str {
+        "confluence"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the Confluence spaces or ingests one"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[Arg {
+            name: "space",
+            kind: ArgKind::Text,
+            required: false,
+            repeated: false,
+        }]
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.confluence_command(args, message).await;
+    }
+}
+
+struct KnowledgeBaseCommand;
+
+#[async_trait::async_trait]
+impl Command for KnowledgeBaseCommand {
+    fn name(&self) -> &'static str {
+        "kb"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shares embeddings between channels: list, attach, detach or publish a collection"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        &[
+            Arg {
+                name: "list|attach|detach|publish",
+                kind: ArgKind::Text,
+                required: false,
+                repeated: false,
+            },
+            Arg {
+                name: "collection",
+                kind: ArgKind::Text,
+                required: false,
+                repeated: false,
+            },
+        ]
+    }
+
+    async fn execute(&self, channel: &mut ChannelState, args: CommandArgs, message: ChatMessage) {
+        channel.knowledge_base_command(args, message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(name: &str) -> &'static dyn Command {
+        registry().find(name).unwrap()
+    }
+
+    #[test]
+    fn arguments() {
+        let github = command("github");
+        let args = parse_args(
+            github,
+            Some("https://github.com/o/r /srv/repo --clone --path=src/**"),
+        )
+        .unwrap();
+        assert_eq!(args.positional, vec!["https://github.com/o/r", "/srv/repo"]);
+        assert!(args.has_flag("clone"));
+        assert_eq!(args.flag_line(), "--clone --path=src/**");
+
+        assert_eq!(
+            parse_args(github, None),
+            Err(UsageError::MissingArgument("url or path"))
+        );
+        assert_eq!(
+            parse_args(github, Some("/srv/repo --everything")),
+            Err(UsageError::UnknownFlag("everything".to_owned()))
+        );
+        assert_eq!(
+            parse_args(github, Some("/srv/repo --max-size")),
+            Err(UsageError::MissingValue("max-size".to_owned()))
+        );
+        assert_eq!(
+            parse_args(github, Some("/srv/repo --clone=yes")),
+            Err(UsageError::UnexpectedValue("clone".to_owned()))
+        );
+
+        let transcribe = command("transcribe");
+        assert_eq!(
+            parse_args(transcribe, Some("youtube.com/watch?v=1")),
+            Err(UsageError::InvalidUrl("youtube.com/watch?v=1".to_owned()))
+        );
+        assert_eq!(
+            parse_args(command("model"), Some("gpt-4 gpt-3.5-turbo")),
+            Err(UsageError::UnexpectedArgument("gpt-3.5-turbo".to_owned()))
+        );
+        assert_eq!(
+            parse_args(command("debug"), Some("now")),
+            Err(UsageError::UnexpectedArgument("now".to_owned()))
+        );
+
+        let prompt = "You review  code.\nNever suggest --force pushes.";
+        assert_eq!(
+            parse_args(command("persona"), Some(&format!("set {prompt}")))
+                .unwrap()
+                .positional,
+            vec!["set", prompt]
+        );
+        assert_eq!(
+            parse_args(command("persona"), Some("reset"))
+                .unwrap()
+                .positional,
+            vec!["reset"]
+        );
+        assert!(parse_args(command("persona"), None)
+            .unwrap()
+            .positional
+            .is_empty());
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(
+            usage(command("kb")),
+            "!kb [list|attach|detach|publish] [collection]"
+        );
+        assert_eq!(usage(command("transcribe")), "!transcribe [url]...");
+        assert!(usage(command("github")).contains(" [--max-size=<bytes>]"));
+
+        let registry = registry();
+        let help = registry.help(&["github".to_owned()], None);
+        assert!(help.contains("!help - "));
+        assert!(help.contains("!cancel - "));
+        assert!(help.contains("!github - "));
+        assert!(!help.contains("!transcribe"));
+
+        let help = registry.help(&[], Some("!github"));
+        assert!(help.contains("Usage: !github <url or path>..."));
+        assert!(help.contains("!tools enable github"));
+        assert_eq!(
+            registry.tools(),
+            vec!["transcribe", "github", "confluence", "kb"]
+        );
+    }
+}
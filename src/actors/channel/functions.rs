@@ -0,0 +1,430 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use tiktoken_rs::get_bpe_from_model;
+
+use crate::{
+    actors::gpt::ChatMessage,
+    llm::{FunctionCall, FunctionDefinition, FunctionExchange},
+};
+
+use super::{commands::CommandArgs, ChannelState};
+
+static REGISTRY: Lazy<FunctionRegistry> = Lazy::new(FunctionRegistry::new);
+
+/// Rounds of function calls before the model has to answer.
+pub const MAX_FUNCTION_STEPS: usize = 4;
+/// Results are cut to this many characters so a few calls fit into the context.
+const MAX_RESULT_LENGTH: usize = 2000;
+/// How long a call waits for the job it started. The channel handles nothing else meanwhile,
+/// not even `!cancel`, so longer jobs report in the channel once they finish.
+const JOB_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The functions the model can call, see [`FunctionRegistry::new`].
+pub fn registry() -> &'static FunctionRegistry {
+    &REGISTRY
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FunctionError {
+    UnknownFunction(String),
+    Disabled(String),
+    InvalidArguments(String),
+    Failed(String),
+}
+
+impl fmt::Display for FunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionError::UnknownFunction(name) => write!(f, "there is no function {name}"),
+            FunctionError::Disabled(tool) => write!(f, "{tool} is disabled in this channel"),
+            FunctionError::InvalidArguments(e) => write!(f, "invalid arguments: {e}"),
+            FunctionError::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for FunctionError {}
+
+#[async_trait::async_trait]
+pub trait Function: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+
+    /// JSON schema of the arguments object.
+    fn parameters(&self) -> Value;
+
+    /// The channel tool that has to be enabled, see [`super::commands::CommandRegistry::tools`].
+    fn tool(&self) -> Option<&'static str> {
+        None
+    }
+
+    async fn call(
+        &self,
+        channel: &mut ChannelState,
+        arguments: Value,
+        message: &ChatMessage,
+    ) -> Result<String, FunctionError>;
+}
+
+pub struct FunctionRegistry {
+    functions: Vec<Box<dyn Function>>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> FunctionRegistry {
+        FunctionRegistry {
+            functions: vec![
+                Box::new(SearchKnowledgeFunction),
+                Box::new(LookupGraphFunction),
+                Box::new(TranscribeFunction),
+                Box::new(ScrapeGithubFunction),
+            ],
+        }
+    }
+
+    fn enabled<'a>(&'a self, tools: &'a [String]) -> impl Iterator<Item = &'a dyn Function> {
+        self.functions
+            .iter()
+            .map(Box::as_ref)
+            .filter(|function| match function.tool() {
+                Some(tool) => tools.iter().any(|t| t == tool),
+                None => true,
+            })
+    }
+
+    /// Definitions of the functions enabled by `tools`, sent along with completion requests.
+    pub fn definitions(&self, tools: &[String]) -> Vec<FunctionDefinition> {
+        self.enabled(tools)
+            .map(|function| FunctionDefinition {
+                name: function.name().to_owned(),
+                description: function.description().to_owned(),
+                parameters: function.parameters(),
+            })
+            .collect()
+    }
+
+    /// Runs a call of the model, the result is cut to fit into the context.
+    pub async fn call(
+        &self,
+        channel: &mut ChannelState,
+        call: &FunctionCall,
+        message: &ChatMessage,
+    ) -> Result<String, FunctionError> {
+        let Some(function) = self
+            .functions
+            .iter()
+            .find(|function| function.name() == call.name)
+        else {
+            return Err(FunctionError::UnknownFunction(call.name.clone()));
+        };
+        if let Some(tool) = function.tool() {
+            if !channel.tools.iter().any(|t| t == tool) {
+                return Err(FunctionError::Disabled(tool.to_owned()));
+            }
+        }
+
+        let arguments: Value = serde_json::from_str(&call.arguments)
+            .map_err(|e| FunctionError::InvalidArguments(e.to_string()))?;
+        let result = function.call(channel, arguments, message).await?;
+        Ok(truncate(result, MAX_RESULT_LENGTH))
+    }
+}
+
+/// Tokens the function definitions and exchanges add to a request.
+pub fn function_tokens(
+    model: &str,
+    definitions: &[FunctionDefinition],
+    exchanges: &[FunctionExchange],
+) -> usize {
+    let Ok(bpe) = get_bpe_from_model(model) else {
+        return 0;
+    };
+    let definitions = match definitions.is_empty() {
+        true => 0,
+        false => bpe.encode_ordinary(&json!(definitions).to_string()).len(),
+    };
+    // every message carries a few tokens of framing
+    definitions
+        + exchanges
+            .iter()
+            .map(|exchange| {
+                bpe.encode_ordinary(&exchange.call.arguments).len()
+                    + bpe.encode_ordinary(&exchange.result).len()
+                    + 10
+            })
+            .sum::<usize>()
+}
+
+fn truncate(mut text: String, length: usize) -> String {
+    if let Some((index, _)) = text.char_indices().nth(length) {
+        text.truncate(index);
+        text.push_str("\n[truncated]");
+    }
+    text
+}
+
+fn string_argument(arguments: &Value, name: &str) -> Result<String, FunctionError> {
+    arguments[name]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| FunctionError::InvalidArguments(format!("{name} must be a string")))
+}
+
+struct SearchKnowledgeFunction;
+
+#[async_trait::async_trait]
+impl Function for SearchKnowledgeFunction {
+    fn name(&self) -> &'static str {
+        "search_knowledge"
+    }
+
+    fn description(&self) -> &'static str {
+        "Searches the documents, code and transcripts ingested into this channel"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {"type": "string", "description": "What to look for"},
+                "limit": {"type": "integer", "minimum": 1, "maximum": 8},
+            },
+            "required": ["query"],
+        })
+    }
+
+    async fn call(
+        &self,
+        channel: &mut ChannelState,
+        arguments: Value,
+        _message: &ChatMessage,
+    ) -> Result<String, FunctionError> {
+        let query = string_argument(&arguments, "query")?;
+        let limit = arguments["limit"].as_u64().unwrap_or(4).clamp(1, 8) as u8;
+
+        let results = channel.fetch_embeddings(query, limit).await;
+        if results.is_empty() {
+            return Ok("Nothing found".to_owned());
+        }
+        Ok(results
+            .iter()
+            .map(|(embedding, distance)| {
+                format!(
+                    "source: {} (distance {:.2})\n{}",
+                    embedding.graph_vertex, distance, embedding.content
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n"))
+    }
+}
+
+struct LookupGraphFunction;
+
+#[async_trait::async_trait]
+impl Function for LookupGraphFunction {
+    fn name(&self) -> &'static str {
+        "lookup_graph"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows what is known about a url or source in the knowledge graph and how it relates to others"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "id": {"type": "string", "description": "Url or source of a search result"},
+            },
+            "required": ["id"],
+        })
+    }
+
+    async fn call(
+        &self,
+        _channel: &mut ChannelState,
+        arguments: Value,
+        _message: &ChatMessage,
+    ) -> Result<String, FunctionError> {
+        let id = string_argument(&arguments, "id")?;
+        let graph = crate::GRAPH.lock().unwrap();
+
+        let vertex = graph.get_vertex(&id);
+        let outgoing = graph.get_edges_from(&id);
+        let incoming = graph.get_edges_to(&id);
+        if vertex.is_none() && outgoing.is_empty() && incoming.is_empty() {
+            return Err(FunctionError::Failed(format!("{id} is not in the graph")));
+        }
+
+        let mut lines = vec![id.clone()];
+        if let Some(vertex) = vertex {
+            let content: BTreeMap<&String, &String> = vertex.content.iter().collect();
+            for (key, value) in content {
+                lines.push(format!("{key}: {}", truncate(value.clone(), 200)));
+            }
+        }
+        for edge in outgoing {
+            lines.push(format!("{} {}", edge.relation, edge.to));
+        }
+        for edge in incoming {
+            lines.push(format!("{} {} this", edge.from, edge.relation));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+struct TranscribeFunction;
+
+#[async_trait::async_trait]
+impl Function for TranscribeFunction {
+    fn name(&self) -> &'static str {
+        "transcribe_video"
+    }
+
+    fn description(&self) -> &'static str {
+        "Transcribes a video and adds the transcript to this channel's knowledge"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": {"type": "string", "description": "http(s) url of the video"},
+            },
+            "required": ["url"],
+        })
+    }
+
+    fn tool(&self) -> Option<&'static str> {
+        Some("transcribe")
+    }
+
+    async fn call(
+        &self,
+        channel: &mut ChannelState,
+        arguments: Value,
+        message: &ChatMessage,
+    ) -> Result<String, FunctionError> {
+        let url = http_url(&arguments)?;
+        let started = channel.start_transcription(vec![url.clone()], message);
+        let id = started.id;
+        match started.wait(channel, JOB_TIMEOUT).await {
+            Some(Ok(summary)) => Ok(format!(
+                "Transcribed {url} ({summary}), use search_knowledge to read it"
+            )),
+            Some(Err(e)) => Err(FunctionError::Failed(format!(
+                "transcribing {url} failed: {e}"
+            ))),
+            None => Ok(format!(
+                "Started job #{id} transcribing {url}, it reports in this channel when it finishes"
+            )),
+        }
+    }
+}
+
+struct ScrapeGithubFunction;
+
+#[async_trait::async_trait]
+impl Function for ScrapeGithubFunction {
+    fn name(&self) -> &'static str {
+        "scrape_github"
+    }
+
+    fn description(&self) -> &'static str {
+        "Ingests a GitHub repository or organisation into this channel's knowledge"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": {"type": "string", "description": "https url of the repository or organisation"},
+                "threads": {"type": "boolean", "description": "Also ingest issues, pull requests and discussions"},
+            },
+            "required": ["url"],
+        })
+    }
+
+    fn tool(&self) -> Option<&'static str> {
+        Some("github")
+    }
+
+    async fn call(
+        &self,
+        channel: &mut ChannelState,
+        arguments: Value,
+        message: &ChatMessage,
+    ) -> Result<String, FunctionError> {
+        // local paths stay reserved for the !github command, the model acts on chat input
+        let url = http_url(&arguments)?;
+        let mut args = CommandArgs {
+            positional: vec![url.clone()],
+            flags: Vec::new(),
+        };
+        if arguments["threads"].as_bool() == Some(true) {
+            args.flags.push(("threads".to_owned(), None));
+        }
+
+        let started = channel
+            .start_github(args, message)
+            .map_err(FunctionError::Failed)?;
+        let id = started.id;
+        match started.wait(channel, JOB_TIMEOUT).await {
+            Some(Ok(summary)) => Ok(format!(
+                "Ingested {url} ({summary}), use search_knowledge to read it"
+            )),
+            Some(Err(e)) => Err(FunctionError::Failed(format!(
+                "ingesting {url} failed: {e}"
+            ))),
+            None => Ok(format!(
+                "Started job #{id} ingesting {url}, it reports in this channel when it finishes"
+            )),
+        }
+    }
+}
+
+fn http_url(arguments: &Value) -> Result<String, FunctionError> {
+    let url = string_argument(arguments, "url")?;
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err(FunctionError::InvalidArguments(format!(
+            "{url} is not a url"
+        )));
+    }
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions() {
+        let names = |tools: &[String]| -> Vec<String> {
+            registry()
+                .definitions(tools)
+                .into_iter()
+                .map(|definition| definition.name)
+                .collect()
+        };
+        assert_eq!(
+            names(&["github".to_owned()]),
+            vec!["search_knowledge", "lookup_graph", "scrape_github"]
+        );
+        assert_eq!(
+            names(&["transcribe".to_owned(), "github".to_owned()]).len(),
+            4
+        );
+
+        assert_eq!(
+            http_url(&json!({"url": "/etc"})),
+            Err(FunctionError::InvalidArguments(
+                "/etc is not a url".to_owned()
+            ))
+        );
+        assert_eq!(truncate("abcdef".to_owned(), 3), "abc\n[truncated]");
+        assert_eq!(truncate("abc".to_owned(), 3), "abc");
+    }
+}
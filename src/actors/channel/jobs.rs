@@ -0,0 +1,434 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use log::error;
+use ractor::{call, rpc::cast, ActorCell, ActorRef};
+use tokio::{
+    sync::{
+        mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::AbortHandle,
+};
+
+use crate::actors::{
+    gpt::ChatMessage,
+    tools::{
+        embeddings::{
+            embedding_generator, Embedding, EmbeddingGeneratorMessage, EmbeddingProgress,
+            GenerateControl,
+        },
+        github::ScrapeReport,
+    },
+};
+
+use super::{broadcast, ChannelMessage, ChannelState, EMBEDDING_BATCH_SIZE};
+
+pub type JobId = u32;
+
+/// What a job hands to its channel, see [`JobHandle::deliver`].
+type JobOutput = (Vec<Embedding>, Option<ScrapeReport>);
+
+/// A tool run in the background of a channel, see [`Jobs::start`].
+pub struct Job {
+    pub id: JobId,
+    pub description: String,
+    pub started: Instant,
+    /// Last progress report of the job.
+    pub status: String,
+    /// The command message that started the job, results are posted as replies to it.
+    pub message: ChatMessage,
+    abort: AbortHandle,
+}
+
+/// A job that was just started. Dropping it leaves the job to run in the background, its output
+/// then goes through the channel's mailbox.
+pub struct StartedJob {
+    pub id: JobId,
+    channel: ActorRef<ChannelMessage>,
+    output: UnboundedReceiver<JobOutput>,
+    done: oneshot::Receiver<Result<String, String>>,
+}
+
+impl StartedJob {
+    /// Waits up to `timeout` for the job to finish. What it delivers meanwhile is applied to
+    /// `channel` right away, the channel can't handle its mailbox while it waits. `None` when the
+    /// job is still running, it continues in the background then.
+    pub async fn wait(
+        mut self,
+        channel: &mut ChannelState,
+        timeout: Duration,
+    ) -> Option<Result<String, String>> {
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                Some((embeddings, sync)) = self.output.recv() => {
+                    channel.insert_embeddings(embeddings);
+                    if let Some(report) = sync {
+                        channel.apply_sync(&report);
+                    }
+                }
+                result = &mut self.done => {
+                    // everything was delivered before the job ended
+                    while let Ok((embeddings, sync)) = self.output.try_recv() {
+                        channel.insert_embeddings(embeddings);
+                        if let Some(report) = sync {
+                            channel.apply_sync(&report);
+                        }
+                    }
+                    return Some(result.unwrap_or(Err("crashed".to_owned())));
+                }
+                _ = &mut deadline => return None,
+            }
+        }
+    }
+}
+
+impl Drop for StartedJob {
+    fn drop(&mut self) {
+        // later deliveries fail and fall back to the mailbox, earlier ones are forwarded to it
+        self.output.close();
+        while let Ok((embeddings, sync)) = self.output.try_recv() {
+            let _ = cast(
+                &self.channel,
+                ChannelMessage::JobOutput(self.id, embeddings, sync),
+            );
+        }
+    }
+}
+
+/// The jobs running for a channel. Jobs never touch the channel state themselves, they send
+/// their results back as [`ChannelMessage`]s which the channel applies in its own mailbox.
+pub struct Jobs {
+    next_id: JobId,
+    running: BTreeMap<JobId, Job>,
+}
+
+impl Jobs {
+    pub fn new() -> Jobs {
+        Jobs {
+            next_id: 1,
+            running: BTreeMap::new(),
+        }
+    }
+
+    /// Spawns `work` and reports its outcome to `channel` as [`ChannelMessage::JobFinished`],
+    /// also when it panics or is cancelled.
+    pub fn start<F, Fut>(
+        &mut self,
+        channel: ActorRef<ChannelMessage>,
+        reply: ChatMessage,
+        description: String,
+        work: F,
+    ) -> StartedJob
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (output_sender, output) = unbounded_channel();
+        let (done_sender, done) = oneshot::channel();
+        let handle = JobHandle {
+            id,
+            channel: channel.clone(),
+            reply: reply.clone(),
+            output: output_sender,
+        };
+        let task = tokio::spawn(work(handle));
+        let abort = task.abort_handle();
+        let finished = channel.clone();
+        tokio::spawn(async move {
+            let result = match task.await {
+                Ok(result) => result,
+                Err(e) if e.is_cancelled() => Err("cancelled".to_owned()),
+                Err(e) => {
+                    error!("Job {} crashed: {}", id, e);
+                    Err("crashed".to_owned())
+                }
+            };
+            let _ = done_sender.send(result.clone());
+            // the channel is gone when it was stopped, nobody is left to tell
+            let _ = cast(&finished, ChannelMessage::JobFinished(id, result));
+        });
+
+        self.running.insert(
+            id,
+            Job {
+                id,
+                description,
+                started: Instant::now(),
+                status: "starting".to_owned(),
+                message: reply,
+                abort,
+            },
+        );
+        StartedJob {
+            id,
+            channel,
+            output,
+            done,
+        }
+    }
+
+    pub fn is_running(&self, id: JobId) -> bool {
+        self.running.contains_key(&id)
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = &Job> {
+        self.running.values()
+    }
+
+    pub fn progress(&mut self, id: JobId, status: String) {
+        if let Some(job) = self.running.get_mut(&id) {
+            job.status = status;
+        }
+    }
+
+    pub fn finish(&mut self, id: JobId) -> Option<Job> {
+        self.running.remove(&id)
+    }
+
+    /// Aborts the job, results it sends afterwards are ignored.
+    pub fn cancel(&mut self, id: JobId) -> Option<Job> {
+        let job = self.running.remove(&id)?;
+        job.abort.abort();
+        Some(job)
+    }
+
+    pub fn cancel_all(&mut self) {
+        for (_, job) in std::mem::take(&mut self.running) {
+            job.abort.abort();
+        }
+    }
+}
+
+/// Given to a running job to talk to its channel.
+#[derive(Clone)]
+pub struct JobHandle {
+    pub id: JobId,
+    channel: ActorRef<ChannelMessage>,
+    reply: ChatMessage,
+    output: UnboundedSender<JobOutput>,
+}
+
+impl JobHandle {
+    /// Posts `content` to the chat and shows it as the job's status in `!jobs`.
+    pub fn say(&self, content: String) {
+        self.report(content.clone());
+        let mut message = self.reply.clone();
+        message.content = content;
+        broadcast(&message);
+    }
+
+    /// Updates the job's status without posting to the chat.
+    pub fn report(&self, status: String) {
+        let _ = cast(&self.channel, ChannelMessage::JobProgress(self.id, status));
+    }
+
+    /// Hands embeddings to the channel, the sync report is recorded after they were inserted.
+    pub fn deliver(&self, embeddings: Vec<Embedding>, sync: Option<ScrapeReport>) {
+        // a caller waiting for the job applies it, see [`StartedJob::wait`]
+        let (embeddings, sync) = match self.output.send((embeddings, sync)) {
+            Ok(()) => return,
+            Err(SendError(output)) => output,
+        };
+        let _ = cast(
+            &self.channel,
+            ChannelMessage::JobOutput(self.id, embeddings, sync),
+        );
+    }
+
+    pub async fn generate_embeddings(&self, chunks: Vec<Embedding>) -> Vec<Embedding> {
+        let (progress_sender, mut progress_receiver) = unbounded_channel::<EmbeddingProgress>();
+        let job = self.clone();
+        tokio::spawn(async move {
+            // report every quarter of the way so big ingestions don't look stuck
+            let mut reported = 0;
+            while let Some(progress) = progress_receiver.recv().await {
+                let status = format!("Embedded {}/{} chunks", progress.done, progress.total);
+                let percent = progress.done * 100 / progress.total.max(1);
+                if percent >= reported + 25 && progress.done < progress.total {
+                    reported = percent - percent % 25;
+                    job.say(status);
+                } else {
+                    job.report(status);
+                }
+            }
+        });
+
+        let control = GenerateControl::with_progress(progress_sender);
+        // the generator keeps encoding for a cancelled job otherwise
+        let _guard = GenerateGuard(control.clone());
+        let embeddings = match embedding_generator() {
+            Some(embed_actor) => call!(
+                embed_actor,
+                EmbeddingGeneratorMessage::Generate,
+                chunks,
+                EMBEDDING_BATCH_SIZE,
+                control
+            )
+            .map_err(|e| e.to_string())
+            .and_then(|result| result.map_err(|e| e.to_string())),
+            None => Err("embedding generator is not running".to_owned()),
+        };
+
+        embeddings.unwrap_or_else(|e| {
+            error!("Failed to generate embeddings: {}", e);
+            self.say(format!("Failed to generate embeddings: {e}"));
+            Vec::new()
+        })
+    }
+}
+
+/// Stops a tool actor spawned by a job once the job ends, also when it is cancelled.
+pub struct ToolGuard(pub ActorCell);
+
+impl Drop for ToolGuard {
+    fn drop(&mut self) {
+        self.0.stop(None);
+    }
+}
+
+/// Cancels embedding generation once the job stops waiting for it.
+struct GenerateGuard(GenerateControl);
+
+impl Drop for GenerateGuard {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use ractor::{Actor, ActorProcessingErr};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::*;
+
+    struct Recorder;
+
+    #[async_trait::async_trait]
+    impl Actor for Recorder {
+        type Msg = ChannelMessage;
+        type State = UnboundedSender<ChannelMessage>;
+        type Arguments = UnboundedSender<ChannelMessage>;
+
+        async fn pre_start(
+            &self,
+            _myself: ActorRef<Self::Msg>,
+            sender: Self::Arguments,
+        ) -> Result<Self::State, ActorProcessingErr> {
+            Ok(sender)
+        }
+
+        async fn handle(
+            &self,
+            _myself: ActorRef<Self::Msg>,
+            message: Self::Msg,
+            sender: &mut Self::State,
+        ) -> Result<(), ActorProcessingErr> {
+            let _ = sender.send(message);
+            Ok(())
+        }
+    }
+
+    fn reply() -> ChatMessage {
+        ChatMessage {
+            content: String::new(),
+            channel: 1,
+            author: "Lovelace".to_owned(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn run_and_cancel() {
+        let (sender, mut receiver) = unbounded_channel();
+        let (channel, _) = Actor::spawn(None, Recorder, sender).await.unwrap();
+        let mut jobs = Jobs::new();
+
+        let id = jobs
+            .start(
+                channel.clone(),
+                reply(),
+                "quick".to_owned(),
+                |job| async move {
+                    job.report("halfway".to_owned());
+                    Ok("done".to_owned())
+                },
+            )
+            .id;
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ChannelMessage::JobProgress(progress_id, status)) if progress_id == id && status == "halfway"
+        ));
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ChannelMessage::JobFinished(finished, Ok(summary))) if finished == id && summary == "done"
+        ));
+
+        let id = jobs
+            .start(
+                channel.clone(),
+                reply(),
+                "slow".to_owned(),
+                |_| async move {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok(String::new())
+                },
+            )
+            .id;
+        assert_eq!(jobs.list().count(), 2);
+        assert_eq!(jobs.cancel(id).unwrap().description, "slow");
+        assert!(!jobs.is_running(id));
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ChannelMessage::JobFinished(finished, Err(e))) if finished == id && e == "cancelled"
+        ));
+        channel.stop(None);
+    }
+
+    #[tokio::test]
+    async fn output_without_waiter() {
+        let (sender, mut receiver) = unbounded_channel();
+        let (channel, _) = Actor::spawn(None, Recorder, sender).await.unwrap();
+        let mut jobs = Jobs::new();
+
+        // nobody waits for the job, its output goes through the mailbox
+        let started = jobs.start(
+            channel.clone(),
+            reply(),
+            "deliver".to_owned(),
+            |job| async move {
+                job.deliver(Vec::new(), None);
+                Ok("done".to_owned())
+            },
+        );
+        let id = started.id;
+        drop(started);
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ChannelMessage::JobOutput(output_id, _, None)) if output_id == id
+        ));
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ChannelMessage::JobFinished(finished, Ok(_))) if finished == id
+        ));
+        channel.stop(None);
+    }
+
+    #[test]
+    fn cancels_generation() {
+        let control = GenerateControl::default();
+        drop(GenerateGuard(control.clone()));
+        assert!(control.is_cancelled());
+    }
+}
@@ -0,0 +1,1609 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+};
+
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionRequestArgs};
+use log::{debug, error, info, warn};
+use ractor::{call, rpc::cast, Actor, ActorProcessingErr, ActorRef, Message, RpcReplyPort};
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::{
+    actors::{
+        communication::{
+            discord::{ChatActorMessage, StreamDelta, STREAM_METADATA},
+            typing::TypingMessage,
+        },
+        tools::{
+            embeddings::{embedding_generator, EmbeddingGeneratorMessage},
+            transcribe::{
+                media_extension, time_anchored, TranscribeTool, TranscribeToolMessage,
+                TranscriptionResult, TRANSCRIPT_SOURCE_METADATA,
+            },
+        },
+    },
+    ai_context::GptContext,
+    llm::{self, Completion, CompletionBackend, CompletionError},
+    personas,
+    store::Store,
+};
+
+use self::{
+    commands::{command_extract, parse_args, registry, usage, CommandArgs},
+    functions::{function_tokens, MAX_FUNCTION_STEPS},
+    jobs::{JobHandle, JobId, Jobs, StartedJob, ToolGuard},
+};
+use super::{
+    gpt::ChatMessage,
+    knowledge_base::{knowledge_base, KnowledgeBaseMessage},
+    tools::{
+        confluence::{ConfluenceConfig, ConfluenceTool, ConfluenceToolMessage},
+        embeddings::Embedding,
+        github::{
+            local, parse_github_url, parse_scrape_args, threads::ThreadOptions, GithubScraperActor,
+            GithubScraperMessage, GithubTarget, RepoManifest, ScrapeArgs, ScrapeReport,
+        },
+    },
+};
+
+mod commands;
+mod functions;
+mod jobs;
+
+#[derive(Debug)]
+pub enum ChannelMessage {
+    Register(ChatMessage),
+    GetHistory(RpcReplyPort<Vec<(String, String)>>),
+    ClearContext,
+    SetWakeword(String),
+    SetModel(String),
+    GetSystemPrompt(RpcReplyPort<Option<String>>),
+    /// `None` removes the system prompt.
+    SetSystemPrompt(Option<String>),
+    JobProgress(JobId, String),
+    /// Embeddings from a job, with the sync report to record once they are stored.
+    JobOutput(JobId, Vec<Embedding>, Option<ScrapeReport>),
+    JobFinished(JobId, Result<String, String>),
+}
+
+impl Message for ChannelMessage {}
+
+impl From<ChatMessage> for ChannelMessage {
+    fn from(msg: ChatMessage) -> Self {
+        Self::Register(msg)
+    }
+}
+
+pub struct ChannelState {
+    pub id: u64,
+    pub wakeword: Option<String>,
+    pub model: String,
+    backend: Arc<dyn CompletionBackend>,
+    context: GptContext,
+    pub tools: Vec<String>,
+    /// Knowledge base collections this channel searches next to its own embeddings.
+    pub collections: Vec<String>,
+    store: Store,
+    embedding_store: Store,
+    /// What was ingested from each GitHub repository, keyed by `{channel}/{owner}/{repo}`.
+    manifest_store: Store,
+    myself: ActorRef<ChannelMessage>,
+    jobs: Jobs,
+}
+
+/// A GitHub target of a `!github` job with what was ingested from it before.
+struct GithubJobTarget {
+    url: String,
+    target: GithubTarget,
+    /// Owner and name when the target is a GitHub repository, used to fetch its threads.
+    github_repo: Option<(String, String)>,
+    previous: Vec<RepoManifest>,
+}
+
+/// The part of a channel's state that survives a restart, embeddings are stored separately
+/// because they are large and change far less often than the history.
+#[derive(Serialize, Deserialize)]
+struct ChannelSnapshot {
+    wakeword: Option<String>,
+    model: String,
+    /// Enabled tools as older versions stored them, only read to migrate them.
+    #[serde(default, skip_serializing)]
+    tools: Option<Vec<String>>,
+    /// Stored instead of the enabled tools so that tools added later are enabled by default.
+    #[serde(default)]
+    disabled_tools: Vec<String>,
+    static_context: Vec<String>,
+    history: Vec<(String, String)>,
+    #[serde(default)]
+    collections: Vec<String>,
+    #[serde(default)]
+    system_prompt: Option<String>,
+}
+
+pub struct ChannelActor;
+
+/// The tools that existed while snapshots stored enabled tools.
+const LEGACY_TOOLS: [&str; 2] = ["transcribe", "github"];
+
+/// Number of chunks encoded per forward pass when ingesting documents.
+const EMBEDDING_BATCH_SIZE: usize = 32;
+/// Functions are no longer offered once fewer tokens than this are left for the answer.
+const MIN_ANSWER_TOKENS: usize = 300;
+
+fn broadcast(message: &ChatMessage) {
+    let subscribers = ractor::pg::get_members(&"messages_send".to_owned());
+    for subscriber in subscribers {
+        cast(&subscriber, ChatActorMessage::Send(message.clone())).unwrap();
+    }
+}
+
+fn broadcast_delta(delta: &StreamDelta) {
+    let subscribers = ractor::pg::get_members(&"messages_send".to_owned());
+    for subscriber in subscribers {
+        cast(&subscriber, ChatActorMessage::Delta(delta.clone())).unwrap();
+    }
+}
+
+/// Every tool not disabled in `snapshot`.
+fn enabled_tools(snapshot: &ChannelSnapshot) -> Vec<String> {
+    let disabled: Vec<String> = match &snapshot.tools {
+        Some(enabled) => LEGACY_TOOLS
+            .iter()
+            .filter(|tool| !enabled.iter().any(|enabled| enabled == *tool))
+            .map(|tool| tool.to_string())
+            .collect(),
+        None => snapshot.disabled_tools.clone(),
+    };
+    registry()
+        .tools()
+        .into_iter()
+        .filter(|tool| !disabled.contains(tool))
+        .collect()
+}
+
+impl ChannelState {
+    fn snapshot(&self) -> ChannelSnapshot {
+        ChannelSnapshot {
+            wakeword: self.wakeword.clone(),
+            model: self.model.clone(),
+            tools: None,
+            disabled_tools: registry()
+                .tools()
+                .into_iter()
+                .filter(|tool| !self.tools.contains(tool))
+                .collect(),
+            static_context: self.context.static_context.clone(),
+            history: self.context.history.clone(),
+            collections: self.collections.clone(),
+            system_prompt: self.context.system_prompt.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: ChannelSnapshot) {
+        self.wakeword = snapshot.wakeword;
+        self.set_model(snapshot.model);
+        self.tools = enabled_tools(&snapshot);
+        self.context.static_context = snapshot.static_context;
+        self.context.history = snapshot.history;
+        self.collections = snapshot.collections;
+        self.context.system_prompt = snapshot.system_prompt;
+    }
+
+    fn persist(&self) {
+        if let Err(e) = self.store.insert(self.id.to_be_bytes(), &self.snapshot()) {
+            error!("Failed to persist channel {}: {}", self.id, e);
+        }
+    }
+
+    fn persist_embeddings(&self) {
+        if let Err(e) = self
+            .embedding_store
+            .insert(self.id.to_be_bytes(), &self.context.embeddings.embeddings())
+        {
+            error!(
+                "Failed to persist embeddings for channel {}: {}",
+                self.id, e
+            );
+        }
+    }
+
+    fn set_model(&mut self, model: String) {
+        self.backend = llm::backend_for_model(&model);
+        self.model = model;
+    }
+
+    fn insert_message(&mut self, msg: ChatMessage) {
+        self.context.push_history((msg.author, msg.content));
+    }
+
+    fn clear_context(&mut self) {
+        self.context.history.clear();
+    }
+
+    fn has_knowledge(&self) -> bool {
+        !self.context.embeddings.is_empty() || !self.collections.is_empty()
+    }
+
+    async fn fetch_embeddings(&self, query: String, limit: u8) -> Vec<(Embedding, f32)> {
+        if !self.has_knowledge() {
+            return Vec::new();
+        }
+
+        let Some(embed_actor) = embedding_generator() else {
+            warn!("Embedding generator not running, skipping embedding lookup");
+            return Vec::new();
+        };
+        let mut query = query;
+
+        // remove wakeword from query to improve embedding accuracy
+        if self.wakeword.is_some() {
+            query = query.replace(&self.wakeword.clone().unwrap(), "");
+        }
+
+        let query_embedding: Vec<f32> =
+            match call!(embed_actor, EmbeddingGeneratorMessage::Query, query) {
+                Ok(Ok(vector)) => vector,
+                Ok(Err(e)) => {
+                    error!("Failed to embed query: {}", e);
+                    return Vec::new();
+                }
+                Err(e) => {
+                    error!("Failed to embed query: {}", e);
+                    return Vec::new();
+                }
+            };
+
+        let mut results: Vec<(Embedding, f32)> = self
+            .context
+            .embeddings
+            .query(&query_embedding, limit.into())
+            .into_iter()
+            .map(|(e, distance)| (e.clone(), distance))
+            .collect();
+
+        if !self.collections.is_empty() {
+            match knowledge_base() {
+                Some(kb) => match call!(
+                    kb,
+                    KnowledgeBaseMessage::Query,
+                    self.collections.clone(),
+                    query_embedding,
+                    limit.into()
+                ) {
+                    Ok(shared) => results.extend(shared),
+                    Err(e) => error!("Failed to query knowledge base: {}", e),
+                },
+                None => warn!("Knowledge base not running, skipping attached collections"),
+            }
+        }
+
+        results.sort_by(|a, b| a.1.total_cmp(&b.1));
+        results.truncate(limit.into());
+        results
+    }
+
+    fn create_response_request(&self) -> CreateChatCompletionRequest {
+        debug!("Generating response for channel: {}", self.id);
+        let model = self.model.clone();
+        let tokenizer_model = llm::tokenizer_model(&model);
+
+        // the function definitions go along with the first step, see `complete`
+        let definitions = functions::registry().definitions(&self.tools);
+        let plan = self.context.plan(
+            tokenizer_model,
+            llm::context_window(&model),
+            function_tokens(tokenizer_model, &definitions, &[]),
+            self.has_knowledge(),
+        );
+        debug!(
+            "Planned {} prompt tokens with {} history entries and {} documents, {} tokens left",
+            plan.prompt_tokens,
+            self.context.history.len() - plan.history_start,
+            plan.documents.len(),
+            plan.max_tokens
+        );
+
+        CreateChatCompletionRequestArgs::default()
+            .max_tokens(u16::try_from(plan.max_tokens).unwrap_or(u16::MAX))
+            .model(model)
+            .messages(self.context.to_openai_chat_history(&plan))
+            .build()
+            .expect("Failed to build request")
+    }
+
+    async fn generate_response(&mut self, chat_message: ChatMessage, content: String) {
+        debug!("Changing status to typing");
+        let typing = ractor::registry::where_is("typing".to_owned());
+        if let Some(actor) = &typing {
+            cast(actor, TypingMessage::Start(chat_message.channel)).unwrap();
+        }
+
+        info!("Content: {}", content);
+        self.insert_message(chat_message.clone());
+        let embeddings = self.fetch_embeddings(content.clone(), 4).await;
+        debug!(
+            "Embeddings distances: {:?}",
+            embeddings.iter().map(|e| e.1).collect::<Vec<f32>>()
+        );
+        let mut embeddings: Vec<Embedding> = embeddings
+            .into_iter()
+            .filter(|e| e.1 < 0.35)
+            .map(|(s, _)| s)
+            .collect();
+
+        // reverse so that the most similar item is latest in the context, this improves the quality of the response
+        embeddings.reverse();
+        debug!("Embeddings {}", embeddings.len());
+        self.context.selected_embeddings = embeddings.to_vec();
+
+        // clients show the deltas as they come in and replace them with the final message
+        let stream: u64 = rand::random();
+        let (deltas, mut receiver) = unbounded_channel::<String>();
+        let channel = chat_message.channel;
+        let forward = tokio::spawn(async move {
+            while let Some(content) = receiver.recv().await {
+                broadcast_delta(&StreamDelta {
+                    channel,
+                    stream,
+                    content,
+                });
+            }
+        });
+
+        let request = self.create_response_request();
+        let response = self.complete(request, &chat_message, deltas).await;
+        if let Err(e) = forward.await {
+            error!("Failed to forward response deltas: {}", e);
+        }
+        let response_text = match response {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to generate response: {}", e);
+                format!("Failed to generate response: {e}")
+            }
+        };
+
+        info!("Sending response: {}", response_text);
+
+        if let Some(actor) = &typing {
+            cast(actor, TypingMessage::Stop(chat_message.channel)).unwrap();
+        }
+
+        let mut response_message = self.reply_to(&chat_message, response_text);
+        response_message
+            .metadata
+            .insert(STREAM_METADATA.to_owned(), stream.to_string());
+        broadcast(&response_message);
+        self.insert_message(response_message);
+    }
+
+    /// Completes `request`, the model may call the channel's functions for up to
+    /// [`MAX_FUNCTION_STEPS`] rounds before it has to answer. The answer is streamed to
+    /// `deltas`.
+    async fn complete(
+        &mut self,
+        request: CreateChatCompletionRequest,
+        chat_message: &ChatMessage,
+        deltas: UnboundedSender<String>,
+    ) -> Result<String, CompletionError> {
+        let tokenizer_model = llm::tokenizer_model(&self.model).to_owned();
+        // the request leaves room for the function definitions, exchanges take from the answer
+        let reserved = function_tokens(
+            &tokenizer_model,
+            &functions::registry().definitions(&self.tools),
+            &[],
+        );
+        let max_tokens = usize::from(request.max_tokens.unwrap_or_default()) + reserved;
+        let backend = self.backend.clone();
+        let mut exchanges = Vec::new();
+        loop {
+            let mut definitions = match exchanges.len() < MAX_FUNCTION_STEPS {
+                true => functions::registry().definitions(&self.tools),
+                false => Vec::new(),
+            };
+            let mut used = function_tokens(&tokenizer_model, &definitions, &exchanges);
+            if max_tokens.saturating_sub(used) < MIN_ANSWER_TOKENS {
+                definitions.clear();
+                used = function_tokens(&tokenizer_model, &definitions, &exchanges);
+            }
+
+            let mut step = request.clone();
+            step.max_tokens = u16::try_from(max_tokens.saturating_sub(used).max(1)).ok();
+            match backend
+                .stream_with_functions(step, &definitions, &exchanges, deltas.clone())
+                .await?
+            {
+                Completion::Message(content) => return Ok(content),
+                Completion::FunctionCall(call) => {
+                    info!("Model called {} with {}", call.name, call.arguments);
+                    let result = match functions::registry().call(self, &call, chat_message).await {
+                        Ok(result) => result,
+                        Err(e) => {
+                            warn!("Function {} failed: {}", call.name, e);
+                            format!("error: {e}")
+                        }
+                    };
+                    exchanges.push(llm::FunctionExchange { call, result });
+                }
+            }
+        }
+    }
+
+    fn clear_embeddings(&mut self) {
+        self.context.clear_embeddings();
+        self.persist_embeddings();
+        // without embeddings the next sync has to start from scratch
+        for manifest in self.manifests(format!("{}/", self.id)) {
+            if let Err(e) = self.manifest_store.remove(self.manifest_key(&manifest)) {
+                error!("Failed to remove manifest {}: {}", manifest.key(), e);
+            }
+        }
+    }
+
+    fn manifest_key(&self, manifest: &RepoManifest) -> String {
+        format!("{}/{}", self.id, manifest.key())
+    }
+
+    fn manifests(&self, prefix: String) -> Vec<RepoManifest> {
+        match self.manifest_store.scan_prefix::<RepoManifest>(prefix) {
+            Ok(manifests) => manifests.into_iter().map(|(_, m)| m).collect(),
+            Err(e) => {
+                error!("Failed to load manifests for channel {}: {}", self.id, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Records what was synced and drops embeddings of files that were deleted or left out.
+    fn apply_sync(&mut self, report: &ScrapeReport) {
+        for diff in &report.diffs {
+            for source in &diff.stale_sources {
+                self.context.embeddings.remove_source(source);
+            }
+        }
+        for manifest in &report.manifests {
+            if let Err(e) = self
+                .manifest_store
+                .insert(self.manifest_key(manifest), manifest)
+            {
+                error!("Failed to store manifest {}: {}", manifest.key(), e);
+            }
+        }
+        self.persist_embeddings();
+    }
+
+    /// Adds the embeddings to the channel, replacing whatever was previously ingested from the
+    /// same sources.
+    fn insert_embeddings(&mut self, embeddings: Vec<Embedding>) {
+        let sources: HashSet<String> = embeddings.iter().map(|e| e.source().to_owned()).collect();
+        for source in sources {
+            self.context.embeddings.remove_source(&source);
+        }
+
+        for embedding in embeddings {
+            self.context.embeddings.insert(embedding);
+        }
+        self.persist_embeddings();
+    }
+
+    fn reply_to(&self, message: &ChatMessage, content: String) -> ChatMessage {
+        ChatMessage {
+            channel: message.channel,
+            content,
+            author: self.wakeword.clone().unwrap_or("Computer".to_owned()),
+            metadata: HashMap::new(),
+        }
+    }
+
+    fn send_message(&self, message: ChatMessage, content: String) -> ChatMessage {
+        let response_message = self.reply_to(&message, content);
+        broadcast(&response_message);
+        response_message
+    }
+
+    async fn confluence_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        let Some(config) = ConfluenceConfig::from_env() else {
+            self.send_message(chat_message, "Confluence is not configured".to_owned());
+            return;
+        };
+
+        if let Some(space) = args.get(0) {
+            let space = space.trim().to_owned();
+            let started = self.start_job(&chat_message, format!("confluence {space}"), |job| {
+                scrape_confluence(job, config, space)
+            });
+            self.job_started(chat_message, started.id);
+            return;
+        }
+
+        let confluence_actor = match Actor::spawn(None, ConfluenceTool, config).await {
+            Ok((actor, _)) => actor,
+            Err(e) => {
+                error!("Failed to spawn confluence tool: {}", e);
+                self.send_message(chat_message, "Failed to start confluence tool".to_owned());
+                return;
+            }
+        };
+        let response = match call!(confluence_actor, ConfluenceToolMessage::ListSpaces) {
+            Ok(Ok(spaces)) => format!(
+                "Available spaces:\n{}",
+                spaces
+                    .iter()
+                    .map(|(key, name)| format!("{key}: {name}"))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            Ok(Err(e)) => format!("Failed to list confluence spaces: {e}"),
+            Err(e) => format!("Failed to list confluence spaces: {e}"),
+        };
+        self.send_message(chat_message, response);
+        confluence_actor.stop(None);
+    }
+
+    async fn knowledge_base_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        let action = args.get(0);
+        let name = args.get(1).map(|n| n.to_owned());
+
+        let Some(kb) = knowledge_base() else {
+            self.send_message(chat_message, "Knowledge base is not running".to_owned());
+            return;
+        };
+
+        match (action, name) {
+            (Some("list"), _) => {
+                let collections =
+                    call!(kb, KnowledgeBaseMessage::ListCollections).unwrap_or_default();
+                let listing = collections
+                    .iter()
+                    .map(|(name, count)| format!("{name} ({count} embeddings)"))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                self.send_message(
+                    chat_message,
+                    format!("Knowledge base collections:\n{listing}"),
+                );
+            }
+            (Some("attach"), Some(name)) => {
+                let response = match call!(kb, KnowledgeBaseMessage::ListCollections) {
+                    Ok(collections) if collections.iter().any(|(c, _)| c == &name) => {
+                        if !self.collections.contains(&name) {
+                            self.collections.push(name.clone());
+                        }
+                        format!("Attached collection {name}")
+                    }
+                    Ok(collections) => format!(
+                        "Unknown collection {name}, available collections: {}",
+                        collections
+                            .into_iter()
+                            .map(|(name, _)| name)
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                    Err(e) => format!("Failed to list collections: {e}"),
+                };
+                self.send_message(chat_message, response);
+            }
+            (Some("detach"), Some(name)) => {
+                self.collections.retain(|c| c != &name);
+                self.send_message(chat_message, format!("Detached collection {name}"));
+            }
+            (Some("publish"), Some(name)) => {
+                let embeddings: Vec<Embedding> = self
+                    .context
+                    .embeddings
+                    .embeddings()
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                let response =
+                    match call!(kb, KnowledgeBaseMessage::Insert, name.clone(), embeddings) {
+                        Ok(Ok(count)) => {
+                            format!("Published {count} embeddings to collection {name}")
+                        }
+                        Ok(Err(e)) => format!("Failed to publish to collection {name}: {e}"),
+                        Err(e) => format!("Failed to publish to collection {name}: {e}"),
+                    };
+                self.send_message(chat_message, response);
+            }
+            (None, _) => {
+                self.send_message(
+                    chat_message,
+                    format!("Attached collections: {}", self.collections.join(", ")),
+                );
+            }
+            _ => {
+                self.send_message(
+                    chat_message,
+                    format!("Usage: {}", usage(registry().find("kb").unwrap())),
+                );
+            }
+        }
+    }
+
+    /// Looks up the command in the registry, checks that the channel enabled it and that the
+    /// arguments match its schema before running it.
+    async fn execute_command(
+        &mut self,
+        command: String,
+        params: Option<String>,
+        chat_message: ChatMessage,
+    ) {
+        let Some(handler) = registry().find(&command) else {
+            self.send_message(
+                chat_message,
+                format!("Unknown command !{command}, see !help for the available commands"),
+            );
+            return;
+        };
+        if !handler.builtin() && !self.tools.contains(&command) {
+            self.send_message(
+                chat_message,
+                format!("!{command} is disabled in this channel, enable it with !tools enable {command}"),
+            );
+            return;
+        }
+
+        let args = match parse_args(handler, params.as_deref()) {
+            Ok(args) => args,
+            Err(e) => {
+                self.send_message(chat_message, format!("{e}\nUsage: {}", usage(handler)));
+                return;
+            }
+        };
+
+        info!("Executing {} command", command);
+        handler.execute(self, args, chat_message).await;
+    }
+
+    async fn transcribe_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        let mut urls = args.positional;
+        urls.extend(media_attachments(&chat_message));
+        if urls.is_empty() {
+            self.send_message(
+                chat_message,
+                "Nothing to transcribe, give a url or attach an audio or video file".to_owned(),
+            );
+            return;
+        }
+
+        let id = self.start_transcription(urls, &chat_message).id;
+        self.job_started(chat_message, id);
+    }
+
+    /// Transcribes the audio and video attached to a message into the channel's knowledge.
+    fn transcribe_attachments(&mut self, chat_message: &ChatMessage) {
+        if !self.tools.iter().any(|tool| tool == "transcribe") {
+            return;
+        }
+        let urls = media_attachments(chat_message);
+        if !urls.is_empty() {
+            let id = self.start_transcription(urls, chat_message).id;
+            self.job_started(chat_message.clone(), id);
+        }
+    }
+
+    fn start_transcription(&mut self, urls: Vec<String>, chat_message: &ChatMessage) -> StartedJob {
+        let description = format!("transcribe {}", urls.join(" "));
+        self.start_job(chat_message, description, |job| transcribe_urls(job, urls))
+    }
+
+    async fn github_command(&mut self, command_args: CommandArgs, chat_message: ChatMessage) {
+        match self.start_github(command_args, &chat_message) {
+            Ok(started) => self.job_started(chat_message, started.id),
+            Err(e) => {
+                self.send_message(chat_message, e);
+            }
+        }
+    }
+
+    /// Resolves the targets and what was synced from them before, then fetches them in a job.
+    fn start_github(
+        &mut self,
+        command_args: CommandArgs,
+        chat_message: &ChatMessage,
+    ) -> Result<StartedJob, String> {
+        let args = parse_scrape_args(&command_args.flag_line()).map_err(|e| e.to_string())?;
+
+        let mut targets = Vec::new();
+        for url in command_args.positional.iter() {
+            let (target, github_repo) = match parse_github_url(url) {
+                Ok(GithubTarget::Repo(owner, repo)) if args.clone => (
+                    GithubTarget::Git(format!("https://github.com/{owner}/{repo}.git")),
+                    Some((owner, repo)),
+                ),
+                Ok(GithubTarget::Repo(owner, repo)) => (
+                    GithubTarget::Repo(owner.clone(), repo.clone()),
+                    Some((owner, repo)),
+                ),
+                Ok(target) => (target, None),
+                Err(e) => {
+                    self.send_message(chat_message.clone(), format!("Skipping {url}: {e}"));
+                    continue;
+                }
+            };
+            let previous = match &target {
+                GithubTarget::Repo(owner, repo) => self
+                    .manifests(format!("{}/{}/{}", self.id, owner, repo))
+                    .into_iter()
+                    .filter(|m| &m.owner == owner && &m.name == repo)
+                    .collect(),
+                GithubTarget::Org(org) => self.manifests(format!("{}/{}/", self.id, org)),
+                GithubTarget::Git(source) => {
+                    let (owner, name) = local::repo_name(source);
+                    self.manifests(format!("{}/{}/{}", self.id, owner, name))
+                        .into_iter()
+                        .filter(|m| m.owner == owner && m.name == name)
+                        .collect()
+                }
+            };
+            targets.push(GithubJobTarget {
+                url: url.clone(),
+                target,
+                github_repo,
+                previous,
+            });
+        }
+        if targets.is_empty() {
+            return Err("Nothing to fetch".to_owned());
+        }
+
+        // plain git sources are read without the API
+        let github_token = env::var("GH_ACCESS_TOKEN").ok();
+        let needs_api = targets.iter().any(|t| {
+            !matches!(t.target, GithubTarget::Git(_))
+                || (args.threads.is_some() && t.github_repo.is_some())
+        });
+        if needs_api && github_token.is_none() {
+            return Err("GitHub access is not configured, set GH_ACCESS_TOKEN".to_owned());
+        }
+
+        let description = format!("github {}", command_args.positional.join(" "));
+        Ok(self.start_job(chat_message, description, |job| {
+            scrape_github(job, targets, args, github_token.unwrap_or_default())
+        }))
+    }
+
+    async fn debug_command(&mut self, chat_message: ChatMessage) {
+        self.send_message(chat_message.clone(), "Utilized embeddings:".to_owned());
+        for embed in &self.context.selected_embeddings {
+            self.send_message(
+                chat_message.clone(),
+                format!("source: {}\ncontent {}", embed.graph_vertex, embed.content),
+            );
+        }
+
+        self.send_message(
+            chat_message.clone(),
+            format!(
+                "Current embeddings available for channel: {}",
+                self.context.embeddings.len()
+            ),
+        );
+
+        let ready = match embedding_generator() {
+            Some(embed_actor) => {
+                call!(embed_actor, EmbeddingGeneratorMessage::Ready).unwrap_or(false)
+            }
+            None => false,
+        };
+        self.send_message(chat_message, format!("Embedding generator ready: {ready}"));
+    }
+
+    fn model_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        if let Some(model) = args.get(0).map(str::to_owned) {
+            if !llm::is_supported_model(&model) {
+                self.send_message(
+                    chat_message,
+                    format!(
+                        "Unknown model {}\nAvailable models: {}, {}, {}<name>",
+                        model,
+                        llm::OPENAI_MODELS.join(", "),
+                        llm::MOCK_MODEL,
+                        llm::LOCAL_MODEL_PREFIX
+                    ),
+                );
+                return;
+            }
+            self.set_model(model);
+            self.send_message(chat_message, format!("Set model to {}", self.model));
+        } else {
+            self.send_message(chat_message, format!("Current model is {}", self.model));
+        }
+    }
+
+    fn persona_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        let value = args.positional.get(1..).unwrap_or_default().join(" ");
+        let response = match (args.get(0), value.is_empty()) {
+            (None, _) => match &self.context.system_prompt {
+                Some(prompt) => format!("Current system prompt:\n{prompt}"),
+                None => "No system prompt is set".to_owned(),
+            },
+            (Some("set"), false) => {
+                self.context.system_prompt = Some(value);
+                "Set the system prompt".to_owned()
+            }
+            (Some("use"), false) => match personas::preset(&value) {
+                Ok(persona) => {
+                    self.context.system_prompt = Some(persona.prompt);
+                    format!("Switched to persona {}", persona.name)
+                }
+                Err(e) => format!("{e}, see !persona list"),
+            },
+            (Some("reset"), true) => {
+                self.context.system_prompt = None;
+                "Removed the system prompt".to_owned()
+            }
+            (Some("list"), true) => match personas::presets() {
+                Ok(presets) if presets.is_empty() => "No personas are configured".to_owned(),
+                Ok(presets) => presets
+                    .iter()
+                    .map(|persona| format!("{}: {}", persona.name, persona.description))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                Err(e) => format!("Failed to load personas: {e}"),
+            },
+            _ => format!("Usage: {}", usage(registry().find("persona").unwrap())),
+        };
+        self.send_message(chat_message, response);
+    }
+
+    fn tools_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        let tools = registry().tools();
+        let response = match (args.get(0), args.get(1)) {
+            (None, _) => tools
+                .iter()
+                .map(|tool| match self.tools.contains(tool) {
+                    true => format!("{tool}: enabled"),
+                    false => format!("{tool}: disabled"),
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            (Some(action @ ("enable" | "disable")), Some(tool))
+                if tools.iter().any(|t| t == tool) =>
+            {
+                self.tools.retain(|t| t != tool);
+                if action == "enable" {
+                    self.tools.push(tool.to_owned());
+                }
+                format!("{action}d {tool}")
+            }
+            (Some("enable" | "disable"), Some(tool)) => {
+                format!("Unknown tool {tool}, available tools: {}", tools.join(", "))
+            }
+            _ => format!("Usage: {}", usage(registry().find("tools").unwrap())),
+        };
+        self.send_message(chat_message, response);
+    }
+
+    /// Runs `work` in the background, see [`Jobs`].
+    fn start_job<F, Fut>(
+        &mut self,
+        chat_message: &ChatMessage,
+        description: String,
+        work: F,
+    ) -> StartedJob
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: std::future::Future<Output = Result<String, String>> + Send + 'static,
+    {
+        let reply = self.reply_to(chat_message, String::new());
+        self.jobs
+            .start(self.myself.clone(), reply, description, work)
+    }
+
+    fn job_started(&self, chat_message: ChatMessage, id: JobId) {
+        self.send_message(
+            chat_message,
+            format!("Started job #{id}, !jobs shows its progress and !cancel {id} stops it"),
+        );
+    }
+
+    fn jobs_command(&self, chat_message: ChatMessage) {
+        let jobs: Vec<String> = self
+            .jobs
+            .list()
+            .map(|job| {
+                format!(
+                    "#{} {} ({}s): {}",
+                    job.id,
+                    job.description,
+                    job.started.elapsed().as_secs(),
+                    job.status
+                )
+            })
+            .collect();
+        let response = match jobs.is_empty() {
+            true => "No jobs are running".to_owned(),
+            false => jobs.join("\n"),
+        };
+        self.send_message(chat_message, response);
+    }
+
+    fn cancel_command(&mut self, args: CommandArgs, chat_message: ChatMessage) {
+        let id = args.get(0).unwrap_or_default();
+        let response = match id.trim_start_matches('#').parse::<JobId>() {
+            Ok(id) => match self.jobs.cancel(id) {
+                Some(job) => format!("Cancelled job #{id} {}", job.description),
+                None => format!("No job #{id} is running"),
+            },
+            Err(_) => format!("{id} is not a job id"),
+        };
+        self.send_message(chat_message, response);
+    }
+}
+
+/// Urls of the audio and video files attached to the message.
+fn media_attachments(chat_message: &ChatMessage) -> Vec<String> {
+    chat_message
+        .attachments()
+        .into_iter()
+        .filter(|attachment| {
+            let content_type = attachment.content_type.as_deref().unwrap_or_default();
+            content_type.starts_with("audio/")
+                || content_type.starts_with("video/")
+                || media_extension(&attachment.url).is_some()
+        })
+        .map(|attachment| attachment.url)
+        .collect()
+}
+
+async fn transcribe_urls(job: JobHandle, urls: Vec<String>) -> Result<String, String> {
+    let trans_actor = match Actor::spawn(None, TranscribeTool, ()).await {
+        Ok((actor, _)) => actor,
+        Err(e) => {
+            error!("Failed to start transcribe tool: {}", e);
+            return Err("transcription is currently unavailable".to_owned());
+        }
+    };
+    let _guard = ToolGuard(trans_actor.get_cell());
+
+    let mut transcribed = 0;
+    for url in &urls {
+        match transcribe(&job, &trans_actor, url).await {
+            Ok(embeddings) => {
+                transcribed += 1;
+                job.deliver(embeddings, None);
+            }
+            Err(e) => job.say(e),
+        }
+    }
+    Ok(format!("transcribed {transcribed} of {} urls", urls.len()))
+}
+
+async fn transcribe(
+    job: &JobHandle,
+    trans_actor: &ActorRef<TranscribeToolMessage>,
+    url: &str,
+) -> Result<Vec<Embedding>, String> {
+    job.say("Transcribing url".to_string());
+
+    let response = match call!(
+        trans_actor,
+        TranscribeToolMessage::Transcribe,
+        url.to_owned()
+    ) {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => return Err(format!("Failed to transcribe {url}: {e}")),
+        Err(e) => {
+            error!("Transcribe tool did not reply: {}", e);
+            return Err(format!("Failed to transcribe {url}"));
+        }
+    };
+
+    // metadata is optional, the transcription is still useful without it
+    let mut metadata = match call!(trans_actor, TranscribeToolMessage::Metadata, url.to_owned()) {
+        Ok(Ok(metadata)) => metadata,
+        Ok(Err(e)) => {
+            info!("Transcription metadata failed: {}", e);
+            HashMap::new()
+        }
+        Err(e) => {
+            error!("Transcribe tool did not reply: {}", e);
+            HashMap::new()
+        }
+    };
+
+    metadata.insert(
+        TRANSCRIPT_SOURCE_METADATA.to_owned(),
+        response.source.to_string(),
+    );
+    job.say(format!(
+        "Finished transcribing url from {}",
+        response.source
+    ));
+
+    let tr = TranscriptionResult {
+        metadata,
+        segments: response.segments,
+        url: url.to_owned(),
+    };
+    info!("Transcription response: {}", tr.text());
+
+    // each chunk links to the moment it starts at
+    let chunks: Vec<Embedding> = tr
+        .timed_chunks(300)
+        .into_iter()
+        .map(|chunk| Embedding {
+            graph_vertex: time_anchored(url, chunk.start),
+            content: chunk.content,
+            vector: vec![],
+        })
+        .collect();
+
+    Ok(job.generate_embeddings(chunks).await)
+}
+
+async fn scrape_github(
+    job: JobHandle,
+    targets: Vec<GithubJobTarget>,
+    args: ScrapeArgs,
+    github_token: String,
+) -> Result<String, String> {
+    let github_actor = match Actor::spawn(None, GithubScraperActor, github_token).await {
+        Ok((actor, _)) => actor,
+        Err(e) => {
+            error!("Failed to start github scraper: {}", e);
+            return Err("GitHub is currently unavailable".to_owned());
+        }
+    };
+    let _guard = ToolGuard(github_actor.get_cell());
+
+    let total = targets.len();
+    let mut fetched = 0;
+    for GithubJobTarget {
+        url,
+        target,
+        github_repo,
+        previous,
+    } in targets
+    {
+        job.say("Fetching github url".to_string());
+
+        let response = match target {
+            GithubTarget::Repo(owner, repo) => call!(
+                &github_actor,
+                GithubScraperMessage::ScrapeRepo,
+                owner,
+                repo,
+                "default".to_owned(),
+                args.files.clone(),
+                previous.into_iter().next()
+            ),
+            GithubTarget::Org(org) => call!(
+                &github_actor,
+                GithubScraperMessage::ScrapeOrg,
+                org,
+                args.org.clone(),
+                args.files.clone(),
+                previous
+            ),
+            GithubTarget::Git(source) => call!(
+                &github_actor,
+                GithubScraperMessage::ScrapeGit,
+                source,
+                args.files.clone(),
+                previous.into_iter().next()
+            ),
+        };
+        let response = match response {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                job.say(format!("Failed to fetch {url}: {e}"));
+                continue;
+            }
+            Err(e) => {
+                error!("Github scraper did not reply: {}", e);
+                job.say(format!("Failed to fetch {url}"));
+                continue;
+            }
+        };
+
+        for diff in &response.diffs {
+            job.say(diff.to_string());
+        }
+        if let Some(summary) = response.skipped_summary() {
+            job.say(summary);
+        }
+
+        let files: Vec<Embedding> = response
+            .files
+            .iter()
+            .flat_map(|file| file.embeddings())
+            .collect();
+
+        job.say(format!(
+            "Fetched {} files, processing them",
+            response.files.len()
+        ));
+
+        let expected = files.len();
+        let embeddings = job.generate_embeddings(files).await;
+        // only remember the sync when the files actually made it into the index
+        let complete = embeddings.len() == expected;
+        job.deliver(embeddings, complete.then_some(response));
+
+        if let (Some(options), Some((owner, repo))) = (&args.threads, github_repo) {
+            github_threads(&job, &github_actor, owner, repo, options.clone()).await;
+        }
+
+        fetched += 1;
+        job.say("Finished fetching github url".to_string());
+    }
+    Ok(format!("fetched {fetched} of {total} targets"))
+}
+
+/// Adds the issues, pull requests and discussions of a repository to the graph and the
+/// channel embeddings.
+async fn github_threads(
+    job: &JobHandle,
+    github_actor: &ActorRef<GithubScraperMessage>,
+    owner: String,
+    repo: String,
+    options: ThreadOptions,
+) {
+    let threads = match call!(
+        github_actor,
+        GithubScraperMessage::ScrapeThreads,
+        owner.clone(),
+        repo.clone(),
+        options
+    ) {
+        Ok(Ok(threads)) => threads,
+        Ok(Err(e)) => {
+            job.say(format!("Failed to fetch threads of {owner}/{repo}: {e}"));
+            return;
+        }
+        Err(e) => {
+            error!("Github scraper did not reply: {}", e);
+            job.say(format!("Failed to fetch threads of {owner}/{repo}"));
+            return;
+        }
+    };
+
+    {
+        let mut graph = crate::GRAPH.lock().unwrap();
+        for thread in &threads {
+            thread.add_to_graph(&mut graph);
+        }
+    }
+
+    let chunks: Vec<Embedding> = threads.iter().flat_map(|t| t.embeddings(300)).collect();
+    job.say(format!(
+        "Fetched {} issues, pull requests and discussions, processing {} chunks",
+        threads.len(),
+        chunks.len()
+    ));
+
+    let embeddings = job.generate_embeddings(chunks).await;
+    job.deliver(embeddings, None);
+}
+
+async fn scrape_confluence(
+    job: JobHandle,
+    config: ConfluenceConfig,
+    space: String,
+) -> Result<String, String> {
+    let confluence_actor = match Actor::spawn(None, ConfluenceTool, config).await {
+        Ok((actor, _)) => actor,
+        Err(e) => {
+            error!("Failed to spawn confluence tool: {}", e);
+            return Err("Failed to start confluence tool".to_owned());
+        }
+    };
+    let _guard = ToolGuard(confluence_actor.get_cell());
+
+    job.say(format!("Fetching confluence space {space}"));
+    let pages = match call!(
+        confluence_actor,
+        ConfluenceToolMessage::ScrapeSpace,
+        space.clone()
+    ) {
+        Ok(Ok(pages)) => pages,
+        Ok(Err(e)) => return Err(format!("Failed to fetch confluence space {space}: {e}")),
+        Err(e) => {
+            error!("Confluence tool failed: {}", e);
+            return Err(format!("Failed to fetch confluence space {space}"));
+        }
+    };
+
+    {
+        let mut graph = crate::GRAPH.lock().unwrap();
+        for page in &pages {
+            page.add_to_graph(&mut graph);
+        }
+    }
+
+    let chunks: Vec<Embedding> = pages.iter().flat_map(|p| p.embeddings(300)).collect();
+    job.say(format!(
+        "Fetched {} pages, processing {} chunks",
+        pages.len(),
+        chunks.len()
+    ));
+
+    let embeddings = job.generate_embeddings(chunks).await;
+    job.deliver(embeddings, None);
+    Ok(format!("fetched confluence space {space}"))
+}
+
+#[async_trait::async_trait]
+impl Actor for ChannelActor {
+    type Msg = ChannelMessage;
+    type State = ChannelState;
+    type Arguments = Option<u64>;
+
+    async fn pre_start(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        id: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let mut id = id;
+        if id.is_none() {
+            id = Some(rand::random());
+        }
+        let id = id.unwrap();
+        let model = "gpt-3.5-turbo".to_owned();
+        let context = GptContext::new();
+
+        let mut state = ChannelState {
+            id,
+            wakeword: Some("Lovelace".to_owned()),
+            backend: llm::backend_for_model(&model),
+            model,
+            context,
+            tools: registry().tools(),
+            collections: Vec::new(),
+            store: Store::open("channels")?,
+            embedding_store: Store::open("channel_embeddings")?,
+            manifest_store: Store::open("github_manifests")?,
+            myself,
+            jobs: Jobs::new(),
+        };
+
+        if let Some(snapshot) = state.store.get::<ChannelSnapshot>(id.to_be_bytes())? {
+            info!("Restoring persisted state for channel {}", id);
+            state.restore(snapshot);
+        }
+
+        if let Some(embeddings) = state
+            .embedding_store
+            .get::<Vec<Embedding>>(id.to_be_bytes())?
+        {
+            info!(
+                "Restored {} embeddings for channel {}",
+                embeddings.len(),
+                id
+            );
+            for embedding in embeddings {
+                state.context.embeddings.insert(embedding);
+            }
+        }
+
+        Ok(state)
+    }
+
+    async fn post_stop(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        state.jobs.cancel_all();
+        state.persist();
+        state.store.flush().await?;
+        state.embedding_store.flush().await?;
+        state.manifest_store.flush().await?;
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            ChannelMessage::Register(chat_message) => {
+                info!("Received message: {:?}", chat_message);
+                let content = chat_message.content.clone();
+                let name = state
+                    .wakeword
+                    .clone()
+                    .unwrap_or("Computer".to_owned())
+                    .clone();
+
+                if let Some((command, params)) = command_extract(&content) {
+                    debug!("command invoked with {} and {:?}", command, params);
+                    state
+                        .execute_command(command, params, chat_message.clone())
+                        .await;
+                    state.insert_message(chat_message);
+                    state.persist();
+                    return Ok(());
+                }
+
+                if state.wakeword.is_some()
+                    && chat_message.author.to_lowercase()
+                        == state.wakeword.clone().unwrap().to_lowercase()
+                {
+                    state.insert_message(chat_message.clone());
+                    state.persist();
+                    return Ok(());
+                }
+
+                state.transcribe_attachments(&chat_message);
+
+                if !content.to_lowercase().contains(&name.to_lowercase())
+                    && chat_message.metadata.get("provider") == Some(&"discord".to_owned())
+                {
+                    state.insert_message(chat_message.clone());
+                    state.persist();
+
+                    return Ok(());
+                }
+
+                state.generate_response(chat_message, content).await;
+            }
+            ChannelMessage::ClearContext => {
+                state.clear_context();
+            }
+            ChannelMessage::SetWakeword(wakeword) => {
+                state.wakeword = Some(wakeword);
+            }
+            ChannelMessage::SetModel(model) => {
+                state.set_model(model);
+            }
+            ChannelMessage::GetHistory(port) => {
+                port.send(state.context.history.clone()).unwrap();
+                return Ok(());
+            }
+            ChannelMessage::GetSystemPrompt(port) => {
+                port.send(state.context.system_prompt.clone()).unwrap();
+                return Ok(());
+            }
+            ChannelMessage::SetSystemPrompt(prompt) => {
+                state.context.system_prompt = prompt;
+            }
+            ChannelMessage::JobProgress(id, status) => {
+                state.jobs.progress(id, status);
+                return Ok(());
+            }
+            ChannelMessage::JobOutput(id, embeddings, sync) => {
+                if !state.jobs.is_running(id) {
+                    debug!("Dropping output of cancelled job {}", id);
+                    return Ok(());
+                }
+                state.insert_embeddings(embeddings);
+                if let Some(report) = sync {
+                    state.apply_sync(&report);
+                }
+                debug!(
+                    "Job {} delivered, new embedding count: {}",
+                    id,
+                    state.context.embeddings.len()
+                );
+                return Ok(());
+            }
+            ChannelMessage::JobFinished(id, result) => {
+                if let Some(job) = state.jobs.finish(id) {
+                    let response = match result {
+                        Ok(summary) => format!("Job #{id} {} finished: {summary}", job.description),
+                        Err(e) => format!("Job #{id} {} failed: {e}", job.description),
+                    };
+                    state.send_message(job.message, response);
+                }
+                return Ok(());
+            }
+        }
+
+        state.persist();
+        Ok(())
+    }
+}
+
+// unit tests
+#[cfg(test)]
+mod tests {
+    use crate::actors::gpt::{Attachment, ATTACHMENTS_METADATA};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn start() {
+        assert!(Actor::spawn(None, ChannelActor, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn restores_persisted_state() {
+        let id = rand::random();
+        let (channel, handle) = Actor::spawn(None, ChannelActor, Some(id)).await.unwrap();
+        channel
+            .send_message(ChannelMessage::Register(ChatMessage {
+                content: "Hello there".to_owned(),
+                channel: id,
+                author: "Lovelace".to_owned(),
+                metadata: HashMap::new(),
+            }))
+            .unwrap();
+        channel
+            .send_message(ChannelMessage::SetModel("gpt-4".to_owned()))
+            .unwrap();
+        // wait for the queued messages to be processed before stopping
+        call!(channel, ChannelMessage::GetHistory).unwrap();
+        channel.stop(None);
+        handle.await.unwrap();
+
+        let (channel, _) = Actor::spawn(None, ChannelActor, Some(id)).await.unwrap();
+        let history = call!(channel, ChannelMessage::GetHistory).unwrap();
+        assert_eq!(
+            history,
+            vec![("Lovelace".to_owned(), "Hello there".to_owned())]
+        );
+        channel.stop(None);
+    }
+
+    #[test]
+    fn migrates_enabled_tools() {
+        let snapshot = |tools: serde_json::Value| -> ChannelSnapshot {
+            let mut json = serde_json::json!({
+                "wakeword": null,
+                "model": "gpt-4",
+                "static_context": [],
+                "history": [],
+            });
+            json.as_object_mut()
+                .unwrap()
+                .extend(tools.as_object().unwrap().clone());
+            serde_json::from_value(json).unwrap()
+        };
+        let all = registry().tools();
+        assert!(all.len() > LEGACY_TOOLS.len());
+
+        let old = snapshot(serde_json::json!({ "tools": ["transcribe", "github"] }));
+        assert_eq!(enabled_tools(&old), all);
+
+        let old = snapshot(serde_json::json!({ "tools": ["github"] }));
+        assert!(!enabled_tools(&old).contains(&"transcribe".to_owned()));
+        assert_eq!(enabled_tools(&old).len(), all.len() - 1);
+
+        let new = snapshot(serde_json::json!({ "disabled_tools": ["github"] }));
+        assert!(!enabled_tools(&new).contains(&"github".to_owned()));
+        assert!(enabled_tools(&new).contains(&"transcribe".to_owned()));
+        assert!(serde_json::to_value(new).unwrap().get("tools").is_none());
+    }
+
+    #[tokio::test]
+    async fn responds_with_backend() {
+        let id = rand::random();
+        let (channel, _) = Actor::spawn(None, ChannelActor, Some(id)).await.unwrap();
+        channel
+            .send_message(ChannelMessage::SetModel(llm::MOCK_MODEL.to_owned()))
+            .unwrap();
+        channel
+            .send_message(ChannelMessage::Register(ChatMessage {
+                content: "Hello Lovelace".to_owned(),
+                channel: id,
+                author: "user".to_owned(),
+                metadata: HashMap::new(),
+            }))
+            .unwrap();
+
+        let history = call!(channel, ChannelMessage::GetHistory).unwrap();
+        assert_eq!(
+            history.last().unwrap(),
+            &("Lovelace".to_owned(), "echo: Hello Lovelace".to_owned())
+        );
+        channel.stop(None);
+    }
+
+    #[tokio::test]
+    async fn model_calls_functions() {
+        let vertex = format!("https://example.com/{}", rand::random::<u32>());
+        crate::GRAPH.lock().unwrap().add_or_replace_vertex(
+            vertex.clone(),
+            HashMap::from([("title".to_owned(), "Example".to_owned())]),
+        );
+        let call = llm::FunctionCall {
+            name: "lookup_graph".to_owned(),
+            arguments: serde_json::json!({ "id": vertex }).to_string(),
+        };
+
+        let (myself, _) = Actor::spawn(None, ChannelActor, None).await.unwrap();
+        let mut state = ChannelState {
+            id: rand::random(),
+            wakeword: Some("Lovelace".to_owned()),
+            model: llm::MOCK_MODEL.to_owned(),
+            backend: Arc::new(llm::MockBackend::with_completions(vec![
+                Completion::FunctionCall(call),
+            ])),
+            context: GptContext::new(),
+            tools: registry().tools(),
+            collections: Vec::new(),
+            store: Store::open("channels").unwrap(),
+            embedding_store: Store::open("channel_embeddings").unwrap(),
+            manifest_store: Store::open("github_manifests").unwrap(),
+            myself,
+            jobs: Jobs::new(),
+        };
+        let message = ChatMessage {
+            content: "Lovelace, what is example.com?".to_owned(),
+            channel: state.id,
+            author: "user".to_owned(),
+            metadata: HashMap::new(),
+        };
+        state.insert_message(message.clone());
+
+        let request = state.create_response_request();
+        let (deltas, mut receiver) = unbounded_channel();
+        let answer = state.complete(request, &message, deltas).await.unwrap();
+        // the mock echoes the last function result
+        assert!(answer.starts_with(&format!("echo: {vertex}")));
+        assert!(answer.contains("title: Example"));
+        let mut streamed = String::new();
+        while let Some(delta) = receiver.recv().await {
+            streamed.push_str(&delta);
+        }
+        assert_eq!(streamed, answer);
+        state.myself.stop(None);
+    }
+
+    #[test]
+    fn attachments() {
+        let attachment = |url: &str, content_type: Option<&str>| Attachment {
+            url: url.to_owned(),
+            filename: url.rsplit('/').next().unwrap().to_owned(),
+            content_type: content_type.map(str::to_owned),
+        };
+        let attachments = serde_json::to_string(&[
+            attachment(
+                "https://cdn.discordapp.com/1/2/voice-message.ogg",
+                Some("audio/ogg"),
+            ),
+            attachment(
+                "https://cdn.discordapp.com/1/3/recording",
+                Some("video/mp4"),
+            ),
+            attachment("https://example.com/meeting.m4a", None),
+            attachment("https://cdn.discordapp.com/1/4/cat.png", Some("image/png")),
+        ])
+        .unwrap();
+        let message = ChatMessage {
+            content: "listen to this".to_owned(),
+            channel: 1,
+            author: "user".to_owned(),
+            metadata: HashMap::from([(ATTACHMENTS_METADATA.to_owned(), attachments)]),
+        };
+        assert_eq!(
+            media_attachments(&message),
+            vec![
+                "https://cdn.discordapp.com/1/2/voice-message.ogg",
+                "https://cdn.discordapp.com/1/3/recording",
+                "https://example.com/meeting.m4a",
+            ]
+        );
+    }
+
+    #[test]
+    fn command_test() {
+        let (command, params) = command_extract("!github https://github.com").unwrap();
+
+        assert_eq!(command, "github");
+        assert_eq!(params.unwrap(), "https://github.com");
+
+        let (command, params) = command_extract("!transcribe https://youtube.be").unwrap();
+
+        assert_eq!(command, "transcribe");
+        assert_eq!(params.unwrap(), "https://youtube.be");
+
+        let (command, params) = command_extract("!empty").unwrap();
+
+        assert_eq!(command, "empty");
+        assert!(params.is_none());
+
+        // a lone `!` or `!!!` is not a command
+        assert!(command_extract("!").is_none());
+        assert!(command_extract("!!!").is_none());
+        assert!(command_extract("! github").is_none())
+    }
+}
@@ -1,9 +1,16 @@
 use std::collections::HashMap;
 
-use log::{info, warn};
-use ractor::{Actor, ActorProcessingErr, ActorRef, Message, RpcReplyPort, SupervisionEvent};
-
-use super::channel::{ChannelActor, ChannelMessage, ChannelState};
+use log::{error, info, warn};
+use ractor::{
+    Actor, ActorCell, ActorProcessingErr, ActorRef, Message, RpcReplyPort, SupervisionEvent,
+};
+
+use super::{
+    channel::{ChannelActor, ChannelMessage, ChannelState},
+    knowledge_base::{KnowledgeBaseActor, KNOWLEDGE_BASE},
+    tools::embeddings::{EmbeddingGenerator, EMBEDDING_GENERATOR},
+};
+use crate::store::Store;
 
 pub struct ChannelSupervisorState {
     pub channels: HashMap<u64, ActorRef<ChannelMessage>>,
@@ -20,6 +27,53 @@ impl Message for ChannelSupervisorMessage {}
 
 pub struct ChannelSupervisor;
 
+impl ChannelSupervisor {
+    /// Starts the embedding generator shared by all channels, unless one is already running.
+    async fn spawn_embedding_generator(supervisor: ActorCell) {
+        if ractor::registry::where_is(EMBEDDING_GENERATOR.to_owned()).is_some() {
+            return;
+        }
+
+        info!("Starting shared embedding generator");
+        if let Err(e) = Actor::spawn_linked(
+            Some(EMBEDDING_GENERATOR.to_owned()),
+            EmbeddingGenerator,
+            (),
+            supervisor,
+        )
+        .await
+        {
+            error!("Failed to start embedding generator: {}", e);
+        }
+    }
+
+    /// Starts the knowledge base shared by all channels, unless one is already running.
+    async fn spawn_knowledge_base(supervisor: ActorCell) {
+        if ractor::registry::where_is(KNOWLEDGE_BASE.to_owned()).is_some() {
+            return;
+        }
+
+        let store = match Store::open("knowledge_base") {
+            Ok(store) => store,
+            Err(e) => {
+                error!("Failed to open knowledge base store: {}", e);
+                return;
+            }
+        };
+        info!("Starting shared knowledge base");
+        if let Err(e) = Actor::spawn_linked(
+            Some(KNOWLEDGE_BASE.to_owned()),
+            KnowledgeBaseActor,
+            store,
+            supervisor,
+        )
+        .await
+        {
+            error!("Failed to start knowledge base: {}", e);
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl Actor for ChannelSupervisor {
     type Msg = ChannelSupervisorMessage;
@@ -32,6 +86,8 @@ impl Actor for ChannelSupervisor {
         _: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
         ractor::pg::join("channel_sup".to_owned(), vec![myself.get_cell()]);
+        Self::spawn_embedding_generator(myself.get_cell()).await;
+        Self::spawn_knowledge_base(myself.get_cell()).await;
         Ok(ChannelSupervisorState {
             channels: HashMap::new(),
         })
@@ -87,11 +143,25 @@ impl Actor for ChannelSupervisor {
 
     async fn handle_supervisor_evt(
         &self,
-        _myself: ActorRef<Self::Msg>,
+        myself: ActorRef<Self::Msg>,
         message: SupervisionEvent,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         match message {
+            SupervisionEvent::ActorTerminated(cell, _, _)
+            | SupervisionEvent::ActorPanicked(cell, _)
+                if cell.get_name().as_deref() == Some(EMBEDDING_GENERATOR) =>
+            {
+                warn!("Embedding generator stopped, restarting it");
+                Self::spawn_embedding_generator(myself.get_cell()).await;
+            }
+            SupervisionEvent::ActorTerminated(cell, _, _)
+            | SupervisionEvent::ActorPanicked(cell, _)
+                if cell.get_name().as_deref() == Some(KNOWLEDGE_BASE) =>
+            {
+                warn!("Knowledge base stopped, restarting it");
+                Self::spawn_knowledge_base(myself.get_cell()).await;
+            }
             SupervisionEvent::ActorTerminated(_cell, child_state, _reason) => {
                 let child_state: ChannelState = child_state.unwrap().take().unwrap();
                 state.channels.remove(&child_state.id);
@@ -117,14 +187,11 @@ impl Actor for ChannelSupervisor {
 
 #[cfg(test)]
 mod test {
-    use std::env;
-
     use super::*;
     use ractor::{call, Actor};
 
     #[tokio::test]
     async fn create_on_fetch() {
-        env::set_var("OPENAI_API_KEY", "dummy_key");
         let (supervisor, _) = Actor::spawn(None, ChannelSupervisor, ()).await.unwrap();
         call!(supervisor, ChannelSupervisorMessage::FetchChannel, 12345)
             .expect("Failed to fetch channel");
@@ -133,7 +200,6 @@ mod test {
 
     #[tokio::test]
     async fn stop_removes_channel() {
-        env::set_var("OPENAI_API_KEY", "dummy_key");
         let (supervisor, _) = Actor::spawn(None, ChannelSupervisor, ()).await.unwrap();
         let channel = call!(supervisor, ChannelSupervisorMessage::FetchChannel, 1234).unwrap();
 
@@ -160,7 +226,6 @@ mod test {
 
     #[tokio::test]
     async fn create_channel() {
-        env::set_var("OPENAI_API_KEY", "dummy_key");
         let (supervisor, _) = Actor::spawn(None, ChannelSupervisor, ()).await.unwrap();
         let (id, channel_1) =
             call!(supervisor, ChannelSupervisorMessage::CreateChannel, None).unwrap();
@@ -1,4 +1,8 @@
-use std::{collections::HashMap, env};
+use std::{
+    collections::HashMap,
+    env,
+    time::{Duration, Instant},
+};
 
 use log::{error, info, trace};
 use ractor::{call, Actor, ActorProcessingErr, ActorRef, Message};
@@ -6,17 +10,28 @@ use serde::{Deserialize, Serialize};
 use serenity::{
     async_trait,
     http::Http,
-    model::prelude::{ChannelId, Message as DiscordMessage, Ready},
+    model::prelude::{ChannelId, Message as DiscordMessage, MessageId, Ready},
     prelude::{Context, EventHandler, GatewayIntents, TypeMapKey},
     Client,
 };
 
-use crate::actors::{channel_sup::ChannelSupervisorMessage, gpt::ChatMessage};
+use crate::actors::{
+    channel_sup::ChannelSupervisorMessage,
+    gpt::{Attachment, ChatMessage, ATTACHMENTS_METADATA},
+};
 
 extern crate ractor;
 
 pub struct DiscordActor;
 
+/// Metadata key of a final [`ChatMessage`] that replaces the deltas of a stream.
+pub const STREAM_METADATA: &str = "stream";
+
+/// Discord rejects longer messages.
+const MESSAGE_LIMIT: usize = 2000;
+/// Streamed answers are edited at most this often to stay clear of Discord's rate limits.
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ChannelMessage {
     Register(ChatMessage),
@@ -27,9 +42,18 @@ pub enum ChannelMessage {
 
 impl Message for ChannelMessage {}
 
+/// A piece of an answer that is still being generated.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StreamDelta {
+    pub channel: u64,
+    pub stream: u64,
+    pub content: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ChatActorMessage {
     Send(ChatMessage),
+    Delta(StreamDelta),
     Typing(u64),
     Receive(ChatMessage),
 }
@@ -39,6 +63,61 @@ impl Message for ChatActorMessage {}
 pub struct DiscordState {
     http: Http,
     channels: Vec<u64>,
+    streams: HashMap<u64, StreamedMessage>,
+}
+
+/// The Discord messages showing a streamed answer so far.
+struct StreamedMessage {
+    channel: ChannelId,
+    text: String,
+    /// Sent messages with the part of the text they currently show.
+    messages: Vec<(MessageId, String)>,
+    last_edit: Option<Instant>,
+}
+
+impl StreamedMessage {
+    fn new(channel: u64) -> StreamedMessage {
+        StreamedMessage {
+            channel: ChannelId(channel),
+            text: String::new(),
+            messages: Vec::new(),
+            last_edit: None,
+        }
+    }
+
+    fn due(&self) -> bool {
+        self.last_edit.map_or(true, |last_edit| {
+            last_edit.elapsed() >= STREAM_EDIT_INTERVAL
+        })
+    }
+
+    /// Edits the sent messages to show the text, sending more when it outgrew them.
+    async fn render(&mut self, http: &Http) -> serenity::Result<()> {
+        self.last_edit = Some(Instant::now());
+        let parts = split_string(&self.text, MESSAGE_LIMIT);
+        let count = parts.len();
+        for (index, part) in parts.into_iter().enumerate() {
+            match self.messages.get_mut(index) {
+                Some((_, shown)) if *shown == part => {}
+                Some((id, shown)) => {
+                    self.channel
+                        .edit_message(http, *id, |m| m.content(&part))
+                        .await?;
+                    *shown = part;
+                }
+                None => {
+                    let message = self.channel.say(http, &part).await?;
+                    self.messages.push((message.id, part));
+                }
+            }
+        }
+        // the final text can be shorter than what was streamed, e.g. when the completion failed
+        while self.messages.len() > count {
+            let (id, _) = self.messages.pop().unwrap();
+            self.channel.delete_message(http, id).await?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -49,6 +128,21 @@ impl EventHandler for DiscordActor {
         let mut metadata: HashMap<String, String> = HashMap::new();
         metadata.insert("provider".to_owned(), "discord".to_owned());
         metadata.insert("wakeword".to_owned(), data.name.clone());
+        if !message.attachments.is_empty() {
+            let attachments: Vec<Attachment> = message
+                .attachments
+                .iter()
+                .map(|attachment| Attachment {
+                    url: attachment.url.clone(),
+                    filename: attachment.filename.clone(),
+                    content_type: attachment.content_type.clone(),
+                })
+                .collect();
+            metadata.insert(
+                ATTACHMENTS_METADATA.to_owned(),
+                serde_json::to_string(&attachments).unwrap(),
+            );
+        }
         data.myself
             .send_message(ChatActorMessage::Receive(ChatMessage {
                 channel: message.channel_id.0,
@@ -132,6 +226,7 @@ impl Actor for DiscordActor {
         Ok(DiscordState {
             http,
             channels: vec![],
+            streams: HashMap::new(),
         })
     }
 
@@ -143,6 +238,19 @@ impl Actor for DiscordActor {
     ) -> Result<(), ActorProcessingErr> {
         match msg {
             ChatActorMessage::Send(msg) => {
+                let stream = msg
+                    .metadata
+                    .get(STREAM_METADATA)
+                    .and_then(|id| id.parse::<u64>().ok())
+                    .and_then(|id| state.streams.remove(&id));
+                if let Some(mut stream) = stream {
+                    stream.text = msg.content;
+                    if let Err(e) = stream.render(&state.http).await {
+                        error!("Failed to update streamed message: {}", e);
+                    }
+                    return Ok(());
+                }
+
                 if state.channels.contains(&msg.channel) {
                     let messages = split_string(&msg.content, 2000);
                     for message in messages {
@@ -153,6 +261,24 @@ impl Actor for DiscordActor {
                 }
                 Ok(())
             }
+            ChatActorMessage::Delta(delta) => {
+                if !state.channels.contains(&delta.channel) {
+                    return Ok(());
+                }
+
+                let stream = state
+                    .streams
+                    .entry(delta.stream)
+                    .or_insert_with(|| StreamedMessage::new(delta.channel));
+                stream.text.push_str(&delta.content);
+                // the final message renders whatever was skipped here
+                if stream.due() && !stream.text.trim().is_empty() {
+                    if let Err(e) = stream.render(&state.http).await {
+                        error!("Failed to update streamed message: {}", e);
+                    }
+                }
+                Ok(())
+            }
             ChatActorMessage::Typing(channel_id) => {
                 if state.channels.contains(&channel_id) {
                     trace!("Typing in channel: {}", channel_id);
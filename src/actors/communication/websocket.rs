@@ -13,7 +13,7 @@ use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
 use crate::actors::{channel_sup::ChannelSupervisorMessage, gpt::ChatMessage};
 
-use super::discord::ChatActorMessage;
+use super::discord::{ChatActorMessage, StreamDelta};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -108,6 +108,16 @@ impl Actor for WebSocketActor {
                 }
                 Ok(())
             }
+            ChatActorMessage::Delta(delta) => {
+                if state.channels.contains(&delta.channel) {
+                    let string = serde_json::to_string_pretty::<StreamDelta>(&delta).unwrap();
+                    let message = WebSocketMessage { op: 2, d: string };
+                    let message =
+                        serde_json::to_string_pretty::<WebSocketMessage>(&message).unwrap();
+                    state.socket.send(Message::Text(message)).await.unwrap();
+                }
+                Ok(())
+            }
             ChatActorMessage::Typing(channel_id) => {
                 trace!("Sending typing message: {}", channel_id);
                 if state.channels.contains(&channel_id) {
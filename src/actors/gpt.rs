@@ -8,6 +8,9 @@ pub enum RemoteStoreRequestMessage {
 
 impl Message for RemoteStoreRequestMessage {}
 
+/// Metadata key of the files sent along with a message, a JSON array of [`Attachment`]s.
+pub const ATTACHMENTS_METADATA: &str = "attachments";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChatMessage {
     pub content: String,
@@ -16,4 +19,21 @@ pub struct ChatMessage {
     pub metadata: HashMap<String, String>,
 }
 
+impl ChatMessage {
+    pub fn attachments(&self) -> Vec<Attachment> {
+        self.metadata
+            .get(ATTACHMENTS_METADATA)
+            .and_then(|attachments| serde_json::from_str(attachments).ok())
+            .unwrap_or_default()
+    }
+}
+
 impl Message for ChatMessage {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub url: String,
+    pub filename: String,
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use log::{error, info};
+use ractor::{Actor, ActorProcessingErr, ActorRef, Message, RpcReplyPort};
+
+use crate::{
+    actors::tools::embeddings::Embedding,
+    store::{Store, StoreError},
+    vector_index::{HnswIndex, VectorIndex},
+};
+
+/// Registered name of the knowledge base, see [`knowledge_base`].
+pub const KNOWLEDGE_BASE: &str = "knowledge_base";
+
+/// Separates the collection name from the source in store keys.
+const KEY_SEPARATOR: char = '\u{0}';
+
+pub enum KnowledgeBaseMessage {
+    /// Adds embeddings to a collection, creating it when needed. Sources that were already in
+    /// the collection are replaced. Replies with the number of stored embeddings.
+    Insert(
+        String,
+        Vec<Embedding>,
+        RpcReplyPort<Result<usize, StoreError>>,
+    ),
+    RemoveSource(String, String, RpcReplyPort<Result<usize, StoreError>>),
+    /// Finds the closest embeddings over all of the given collections.
+    Query(
+        Vec<String>,
+        Vec<f32>,
+        usize,
+        RpcReplyPort<Vec<(Embedding, f32)>>,
+    ),
+    /// Lists every collection together with the number of embeddings it holds.
+    ListCollections(RpcReplyPort<Vec<(String, usize)>>),
+}
+
+impl Message for KnowledgeBaseMessage {}
+
+/// Looks up the knowledge base started by the channel supervisor.
+pub fn knowledge_base() -> Option<ActorRef<KnowledgeBaseMessage>> {
+    ractor::registry::where_is(KNOWLEDGE_BASE.to_owned()).map(|cell| cell.into())
+}
+
+pub struct KnowledgeBaseState {
+    store: Store,
+    collections: HashMap<String, Box<dyn VectorIndex>>,
+}
+
+fn store_key(collection: &str, source: &str) -> String {
+    format!("{collection}{KEY_SEPARATOR}{source}")
+}
+
+impl KnowledgeBaseState {
+    fn load(store: Store) -> Result<KnowledgeBaseState, StoreError> {
+        let mut collections: HashMap<String, Box<dyn VectorIndex>> = HashMap::new();
+        for (key, embeddings) in store.scan_prefix::<Vec<Embedding>>("")? {
+            let key = String::from_utf8_lossy(&key).into_owned();
+            let Some((collection, _)) = key.split_once(KEY_SEPARATOR) else {
+                continue;
+            };
+
+            let index = collections
+                .entry(collection.to_owned())
+                .or_insert_with(|| Box::new(HnswIndex::new()));
+            for embedding in embeddings {
+                index.insert(embedding);
+            }
+        }
+
+        Ok(KnowledgeBaseState { store, collections })
+    }
+
+    fn insert(
+        &mut self,
+        collection: &str,
+        embeddings: Vec<Embedding>,
+    ) -> Result<usize, StoreError> {
+        let mut by_source: BTreeMap<String, Vec<Embedding>> = BTreeMap::new();
+        for embedding in embeddings {
+            by_source
+                .entry(embedding.source().to_owned())
+                .or_default()
+                .push(embedding);
+        }
+
+        let index = self
+            .collections
+            .entry(collection.to_owned())
+            .or_insert_with(|| Box::new(HnswIndex::new()));
+
+        let mut count = 0;
+        for (source, embeddings) in by_source {
+            self.store
+                .insert(store_key(collection, &source), &embeddings)?;
+            index.remove_source(&source);
+            count += embeddings.len();
+            for embedding in embeddings {
+                index.insert(embedding);
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn remove_source(&mut self, collection: &str, source: &str) -> Result<usize, StoreError> {
+        self.store.remove(store_key(collection, source))?;
+        Ok(self
+            .collections
+            .get_mut(collection)
+            .map(|index| index.remove_source(source))
+            .unwrap_or(0))
+    }
+
+    fn query(&self, collections: &[String], vector: &[f32], limit: usize) -> Vec<(Embedding, f32)> {
+        let collections: HashSet<&String> = collections.iter().collect();
+        let mut results: Vec<(Embedding, f32)> = collections
+            .into_iter()
+            .filter_map(|name| self.collections.get(name))
+            .flat_map(|index| index.query(vector, limit))
+            .map(|(embedding, distance)| (embedding.clone(), distance))
+            .collect();
+
+        results.sort_by(|a, b| a.1.total_cmp(&b.1));
+        results.truncate(limit);
+        results
+    }
+
+    fn list(&self) -> Vec<(String, usize)> {
+        let mut collections: Vec<(String, usize)> = self
+            .collections
+            .iter()
+            .filter(|(_, index)| !index.is_empty())
+            .map(|(name, index)| (name.clone(), index.len()))
+            .collect();
+        collections.sort();
+        collections
+    }
+}
+
+pub struct KnowledgeBaseActor;
+
+#[async_trait::async_trait]
+impl Actor for KnowledgeBaseActor {
+    type Msg = KnowledgeBaseMessage;
+    type State = KnowledgeBaseState;
+    type Arguments = Store;
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        store: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let state = KnowledgeBaseState::load(store)?;
+        info!(
+            "Started knowledge base with {} collections",
+            state.collections.len()
+        );
+        Ok(state)
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            KnowledgeBaseMessage::Insert(collection, embeddings, port) => {
+                info!(
+                    "Adding {} embeddings to collection {}",
+                    embeddings.len(),
+                    collection
+                );
+                let result = state.insert(&collection, embeddings);
+                if let Err(e) = &result {
+                    error!("Failed to store embeddings in {}: {}", collection, e);
+                }
+                let _ = port.send(result);
+            }
+            KnowledgeBaseMessage::RemoveSource(collection, source, port) => {
+                let _ = port.send(state.remove_source(&collection, &source));
+            }
+            KnowledgeBaseMessage::Query(collections, vector, limit, port) => {
+                let _ = port.send(state.query(&collections, &vector, limit));
+            }
+            KnowledgeBaseMessage::ListCollections(port) => {
+                let _ = port.send(state.list());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ractor::call;
+
+    use super::*;
+
+    fn embedding(source: &str, vector: Vec<f32>) -> Embedding {
+        Embedding {
+            vector,
+            graph_vertex: source.to_owned(),
+            content: format!("content of {source}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn insert_query_and_reload() {
+        let store = Store::open("test_knowledge_base").unwrap();
+        let (kb, handle) = Actor::spawn(None, KnowledgeBaseActor, store.clone())
+            .await
+            .unwrap();
+
+        let stored = call!(
+            kb,
+            KnowledgeBaseMessage::Insert,
+            "docs".to_owned(),
+            vec![
+                embedding("a", vec![1.0, 0.0]),
+                embedding("b", vec![0.0, 1.0]),
+            ]
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(stored, 2);
+
+        // re-ingesting a source replaces it
+        call!(
+            kb,
+            KnowledgeBaseMessage::Insert,
+            "docs".to_owned(),
+            vec![embedding("a", vec![0.9, 0.1])]
+        )
+        .unwrap()
+        .unwrap();
+
+        let collections = call!(kb, KnowledgeBaseMessage::ListCollections).unwrap();
+        assert_eq!(collections, vec![("docs".to_owned(), 2)]);
+
+        kb.stop(None);
+        handle.await.unwrap();
+
+        let (kb, _) = Actor::spawn(None, KnowledgeBaseActor, store).await.unwrap();
+        let results = call!(
+            kb,
+            KnowledgeBaseMessage::Query,
+            vec!["docs".to_owned(), "missing".to_owned()],
+            vec![1.0, 0.0],
+            1
+        )
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.graph_vertex, "a");
+        assert_eq!(results[0].0.vector, vec![0.9, 0.1]);
+
+        let removed = call!(
+            kb,
+            KnowledgeBaseMessage::RemoveSource,
+            "docs".to_owned(),
+            "a".to_owned()
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(removed, 1);
+    }
+}
@@ -2,4 +2,5 @@ pub mod channel;
 pub mod channel_sup;
 pub mod communication;
 pub mod gpt;
+pub mod knowledge_base;
 pub mod tools;
@@ -0,0 +1,526 @@
+use std::{collections::HashMap, env, fmt};
+
+use log::{debug, info, warn};
+use ractor::{Actor, ActorProcessingErr, ActorRef, Message, RpcReplyPort};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::graph::Graph;
+
+use super::embeddings::{Embeddable, Embedding};
+
+const PAGE_LIMIT: usize = 25;
+
+#[derive(Debug, Clone)]
+pub struct ConfluenceConfig {
+    /// Wiki root, e.g. `https://example.atlassian.net/wiki`.
+    pub base_url: String,
+    pub user: String,
+    pub token: String,
+    /// Space keys that may be ingested, every space is allowed when empty.
+    pub spaces: Vec<String>,
+}
+
+impl ConfluenceConfig {
+    /// Reads `CONFLUENCE_URL`, `CONFLUENCE_USER`, `CONFLUENCE_TOKEN` and the optional comma
+    /// separated `CONFLUENCE_SPACES`. Returns `None` when Confluence is not configured.
+    pub fn from_env() -> Option<ConfluenceConfig> {
+        let base_url = env::var("CONFLUENCE_URL").ok()?;
+        Some(ConfluenceConfig {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            user: env::var("CONFLUENCE_USER").unwrap_or_default(),
+            token: env::var("CONFLUENCE_TOKEN").unwrap_or_default(),
+            spaces: env::var("CONFLUENCE_SPACES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect(),
+        })
+    }
+
+    fn allows_space(&self, key: &str) -> bool {
+        self.spaces.is_empty() || self.spaces.iter().any(|s| s.eq_ignore_ascii_case(key))
+    }
+
+    /// Graph vertex id of a page, the REST url of its content.
+    pub fn content_url(&self, id: &str) -> String {
+        format!("{}/rest/api/content/{}", self.base_url, id)
+    }
+
+    fn page_link_regex(&self) -> Regex {
+        Regex::new(&format!(
+            r"(?m)\({}/.*/pages/(\d+)/?.*\)",
+            regex::escape(&self.base_url)
+        ))
+        .unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfluenceError {
+    Network(String),
+    Auth,
+    NotFound(String),
+    SpaceNotAllowed(String),
+}
+
+impl fmt::Display for ConfluenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfluenceError::Network(e) => write!(f, "could not reach Confluence: {e}"),
+            ConfluenceError::Auth => write!(f, "Confluence rejected the configured credentials"),
+            ConfluenceError::NotFound(what) => write!(f, "{what} was not found on Confluence"),
+            ConfluenceError::SpaceNotAllowed(key) => {
+                write!(f, "space {key} is not in the configured space filter")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfluenceError {}
+
+#[derive(Deserialize)]
+struct PagedResponse<T> {
+    results: Vec<T>,
+    size: usize,
+}
+
+#[derive(Deserialize)]
+struct SpaceResponse {
+    key: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ContentResponse {
+    id: String,
+    title: String,
+    body: Option<BodyResponse>,
+    children: Option<ChildrenResponse>,
+    #[serde(rename = "_links")]
+    links: Option<LinksResponse>,
+}
+
+#[derive(Deserialize)]
+struct BodyResponse {
+    view: Option<ViewResponse>,
+}
+
+#[derive(Deserialize)]
+struct ViewResponse {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct ChildrenResponse {
+    page: Option<PagedResponse<ChildResponse>>,
+}
+
+#[derive(Deserialize)]
+struct ChildResponse {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct LinksResponse {
+    webui: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfluencePage {
+    pub id: String,
+    pub title: String,
+    pub space_key: String,
+    pub space_name: String,
+    /// Graph vertex id, see [`ConfluenceConfig::content_url`].
+    pub vertex: String,
+    /// Browser url of the page, if Confluence reported one.
+    pub source: Option<String>,
+    pub markdown: String,
+    pub children: Vec<String>,
+    pub links: Vec<String>,
+}
+
+impl Embeddable for ConfluencePage {
+    fn human_readable_source(&self) -> String {
+        self.source.clone().unwrap_or(self.vertex.clone())
+    }
+
+    fn short_description(&self) -> String {
+        format!("{} ({})", self.title, self.space_name)
+    }
+
+    fn long_description(&self) -> String {
+        self.markdown.clone()
+    }
+
+    fn get_chunks(&self, size: usize) -> Vec<String> {
+        let header = format!("{}\nsource: {}\n", self.title, self.human_readable_source());
+        self.markdown
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .chunks(size)
+            .map(|chunk| format!("{}{}", header, chunk.join(" ")))
+            .collect::<Vec<String>>()
+    }
+}
+
+impl ConfluencePage {
+    pub fn embeddings(&self, size: usize) -> Vec<Embedding> {
+        self.get_chunks(size)
+            .into_iter()
+            .map(|content| Embedding {
+                content,
+                vector: vec![],
+                graph_vertex: self.vertex.clone(),
+            })
+            .collect()
+    }
+
+    pub fn add_to_graph(&self, graph: &mut Graph) {
+        for child in &self.children {
+            graph.add_edge(self.vertex.clone(), "child of".to_owned(), child.clone());
+        }
+
+        for link in &self.links {
+            graph.add_edge(self.vertex.clone(), "links to".to_owned(), link.clone());
+        }
+
+        let mut metadata = HashMap::new();
+        metadata.insert("title".to_owned(), self.title.clone());
+        metadata.insert("space".to_owned(), self.space_name.clone());
+        metadata.insert("space_key".to_owned(), self.space_key.clone());
+        metadata.insert("id".to_owned(), self.id.clone());
+        metadata.insert("content".to_owned(), self.markdown.clone());
+        if let Some(source) = &self.source {
+            metadata.insert("source".to_owned(), source.clone());
+        }
+
+        graph.add_or_replace_vertex(self.vertex.clone(), metadata);
+    }
+}
+
+pub struct ConfluenceClient {
+    http: reqwest::Client,
+    config: ConfluenceConfig,
+}
+
+impl ConfluenceClient {
+    pub fn new(config: ConfluenceConfig) -> ConfluenceClient {
+        ConfluenceClient {
+            http: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, ConfluenceError> {
+        let response = self
+            .http
+            .get(format!("{}{}", self.config.base_url, path))
+            .basic_auth(&self.config.user, Some(&self.config.token))
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| ConfluenceError::Network(e.to_string()))?;
+
+        match response.status().as_u16() {
+            401 | 403 => return Err(ConfluenceError::Auth),
+            404 => return Err(ConfluenceError::NotFound(path.to_owned())),
+            _ => {}
+        }
+
+        response
+            .error_for_status()
+            .map_err(|e| ConfluenceError::Network(e.to_string()))?
+            .json::<T>()
+            .await
+            .map_err(|e| ConfluenceError::Network(e.to_string()))
+    }
+
+    /// Fetches every page of a paginated endpoint.
+    async fn get_all<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<Vec<T>, ConfluenceError> {
+        let mut results = Vec::new();
+        let mut start = 0;
+        loop {
+            let mut page_query = query.to_vec();
+            page_query.push(("start", start.to_string()));
+            page_query.push(("limit", PAGE_LIMIT.to_string()));
+
+            let page: PagedResponse<T> = self.get(path, &page_query).await?;
+            start += page.size;
+            let last = page.size < PAGE_LIMIT;
+            results.extend(page.results);
+            if last {
+                return Ok(results);
+            }
+        }
+    }
+
+    /// Lists the `(key, name)` of every space allowed by the configuration.
+    pub async fn spaces(&self) -> Result<Vec<(String, String)>, ConfluenceError> {
+        let spaces: Vec<SpaceResponse> = self.get_all("/rest/api/space", &[]).await?;
+        Ok(spaces
+            .into_iter()
+            .filter(|s| self.config.allows_space(&s.key))
+            .map(|s| (s.key, s.name))
+            .collect())
+    }
+
+    pub async fn pages(&self, space_key: &str) -> Result<Vec<ConfluencePage>, ConfluenceError> {
+        if !self.config.allows_space(space_key) {
+            return Err(ConfluenceError::SpaceNotAllowed(space_key.to_owned()));
+        }
+
+        let space: SpaceResponse = self
+            .get(&format!("/rest/api/space/{space_key}"), &[])
+            .await?;
+
+        let contents: Vec<ContentResponse> = self
+            .get_all(
+                "/rest/api/content",
+                &[
+                    ("spaceKey", space.key.clone()),
+                    ("type", "page".to_owned()),
+                    ("expand", "body.view,children.page".to_owned()),
+                ],
+            )
+            .await?;
+
+        info!(
+            "Space({:?}): {:?} with {} pages",
+            space.key,
+            space.name,
+            contents.len()
+        );
+
+        let link_regex = self.config.page_link_regex();
+        let pages = contents
+            .into_iter()
+            .map(|content| {
+                let html = content
+                    .body
+                    .and_then(|b| b.view)
+                    .map(|v| v.value)
+                    .unwrap_or_default();
+                // replace relative links with absolute links
+                let markdown = html2md::parse_html(&html)
+                    .replace("(/wiki/", &format!("({}/", self.config.base_url));
+
+                let links = link_regex
+                    .captures_iter(&markdown)
+                    .map(|cap| self.config.content_url(&cap[1]))
+                    .collect();
+                let children = content
+                    .children
+                    .and_then(|c| c.page)
+                    .map(|p| p.results)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|child| self.config.content_url(&child.id))
+                    .collect();
+                let source = content
+                    .links
+                    .and_then(|l| l.webui)
+                    .map(|webui| format!("{}{}", self.config.base_url, webui));
+                if source.is_none() {
+                    debug!("No source for page {:?}", content.id);
+                }
+
+                ConfluencePage {
+                    vertex: self.config.content_url(&content.id),
+                    id: content.id,
+                    title: content.title,
+                    space_key: space.key.clone(),
+                    space_name: space.name.clone(),
+                    source,
+                    markdown,
+                    children,
+                    links,
+                }
+            })
+            .collect();
+
+        Ok(pages)
+    }
+}
+
+pub enum ConfluenceToolMessage {
+    ScrapeSpace(
+        String,
+        RpcReplyPort<Result<Vec<ConfluencePage>, ConfluenceError>>,
+    ),
+    ListSpaces(RpcReplyPort<Result<Vec<(String, String)>, ConfluenceError>>),
+}
+
+impl Message for ConfluenceToolMessage {}
+
+pub struct ConfluenceToolState {
+    client: ConfluenceClient,
+}
+
+pub struct ConfluenceTool;
+
+#[async_trait::async_trait]
+impl Actor for ConfluenceTool {
+    type Msg = ConfluenceToolMessage;
+    type State = ConfluenceToolState;
+    type Arguments = ConfluenceConfig;
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        config: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        Ok(ConfluenceToolState {
+            client: ConfluenceClient::new(config),
+        })
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            ConfluenceToolMessage::ScrapeSpace(space_key, port) => {
+                info!("Scraping confluence space {}", space_key);
+                let pages = state.client.pages(&space_key).await;
+                if let Err(e) = &pages {
+                    warn!("Failed to scrape confluence space {}: {}", space_key, e);
+                }
+                let _ = port.send(pages);
+            }
+            ConfluenceToolMessage::ListSpaces(port) => {
+                let _ = port.send(state.client.spaces().await);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ractor::call;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn config(base_url: String, spaces: Vec<String>) -> ConfluenceConfig {
+        ConfluenceConfig {
+            base_url,
+            user: "user@example.com".to_owned(),
+            token: "token".to_owned(),
+            spaces,
+        }
+    }
+
+    async fn mock_confluence() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/wiki/rest/api/space"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "results": [{"key": "DOC", "name": "Documentation"}, {"key": "HR", "name": "HR"}],
+                "size": 2
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/wiki/rest/api/space/DOC"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"key": "DOC", "name": "Documentation"})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/wiki/rest/api/content"))
+            .and(query_param("spaceKey", "DOC"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "results": [{
+                    "id": "1",
+                    "title": "Getting started",
+                    "body": {"view": {"value": "<p>Read <a href=\"/wiki/spaces/DOC/pages/2/Setup\">setup</a> first</p>"}},
+                    "children": {"page": {"results": [{"id": "3"}], "size": 1}},
+                    "_links": {"webui": "/spaces/DOC/pages/1/Getting+started"}
+                }],
+                "size": 1
+            })))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[test]
+    fn match_url() {
+        let config = config("https://example.atlassian.net/wiki".to_owned(), vec![]);
+        let regex = config.page_link_regex();
+        let test = "[](https://example.atlassian.net/wiki/spaces/EP/pages/110985217/Proposed+common+solution+for+public+interface+of+transverse+components)";
+        let mut result = regex.captures_iter(test);
+        assert_eq!(result.next().unwrap().get(1).unwrap().as_str(), "110985217")
+    }
+
+    #[tokio::test]
+    async fn scrape_space() {
+        let server = mock_confluence().await;
+        let base_url = format!("{}/wiki", server.uri());
+        let (actor, _) = Actor::spawn(None, ConfluenceTool, config(base_url.clone(), vec![]))
+            .await
+            .unwrap();
+
+        let pages = call!(actor, ConfluenceToolMessage::ScrapeSpace, "DOC".to_owned())
+            .unwrap()
+            .unwrap();
+        assert_eq!(pages.len(), 1);
+
+        let page = &pages[0];
+        assert_eq!(page.vertex, format!("{base_url}/rest/api/content/1"));
+        assert_eq!(
+            page.source,
+            Some(format!("{base_url}/spaces/DOC/pages/1/Getting+started"))
+        );
+        assert_eq!(page.links, vec![format!("{base_url}/rest/api/content/2")]);
+        assert_eq!(
+            page.children,
+            vec![format!("{base_url}/rest/api/content/3")]
+        );
+
+        let embeddings = page.embeddings(300);
+        assert_eq!(embeddings.len(), 1);
+        assert_eq!(embeddings[0].graph_vertex, page.vertex);
+        assert!(embeddings[0].content.contains("Getting started"));
+
+        let mut graph = Graph::new();
+        page.add_to_graph(&mut graph);
+        assert!(graph.get_vertex(&page.vertex).is_some());
+        assert_eq!(graph.get_edges_from(&page.vertex).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn space_filter() {
+        let server = mock_confluence().await;
+        let config = config(format!("{}/wiki", server.uri()), vec!["doc".to_owned()]);
+        let (actor, _) = Actor::spawn(None, ConfluenceTool, config).await.unwrap();
+
+        let spaces = call!(actor, ConfluenceToolMessage::ListSpaces)
+            .unwrap()
+            .unwrap();
+        assert_eq!(spaces, vec![("DOC".to_owned(), "Documentation".to_owned())]);
+
+        let pages = call!(actor, ConfluenceToolMessage::ScrapeSpace, "HR".to_owned()).unwrap();
+        assert!(matches!(pages, Err(ConfluenceError::SpaceNotAllowed(_))));
+    }
+}
@@ -1,21 +1,50 @@
 use std::{
-    sync::mpsc,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
     thread::{self, JoinHandle},
 };
 
+use log::{info, trace, warn};
 use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
 use rust_bert::pipelines::sentence_embeddings::{
     SentenceEmbeddingsBuilder, SentenceEmbeddingsModelType,
 };
-use tokio::sync::oneshot;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Embedding {
     pub vector: Vec<f32>,
     pub graph_vertex: String,
     pub content: String,
 }
 
+impl Embedding {
+    /// The document the embedding was taken from, `graph_vertex` without its `#fragment` or
+    /// the `t=` time a transcript chunk links to.
+    pub fn source(&self) -> &str {
+        let source = self
+            .graph_vertex
+            .split_once('#')
+            .map_or(self.graph_vertex.as_str(), |(source, _)| source);
+        strip_time_anchor(source)
+    }
+}
+
+/// `url` without a trailing `t=<seconds>` query parameter.
+pub fn strip_time_anchor(url: &str) -> &str {
+    let Some(index) = url.rfind(['?', '&']) else {
+        return url;
+    };
+    match url[index + 1..].strip_prefix("t=") {
+        Some(t) if !t.is_empty() && t.bytes().all(|b| b.is_ascii_digit()) => &url[..index],
+        _ => url,
+    }
+}
+
 pub trait Embeddable {
     fn human_readable_source(&self) -> String;
     fn short_description(&self) -> String;
@@ -23,44 +52,218 @@ pub trait Embeddable {
     fn get_chunks(&self, size: usize) -> Vec<String>;
 }
 
+/// Registered name of the shared embedding generator, see [`embedding_generator`].
+pub const EMBEDDING_GENERATOR: &str = "embedding_generator";
+
+/// Maximum number of sentences the model thread encodes in a single forward pass when it
+/// combines queued requests.
+const MAX_BATCH_SIZE: usize = 64;
+
 pub struct EmbeddingGenerator;
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmbeddingError {
+    Cancelled,
+    ModelUnavailable,
+    Encoding(String),
+}
+
+impl fmt::Display for EmbeddingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbeddingError::Cancelled => write!(f, "embedding generation was cancelled"),
+            EmbeddingError::ModelUnavailable => write!(f, "embedding model is not available"),
+            EmbeddingError::Encoding(e) => write!(f, "failed to encode sentences: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for EmbeddingError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddingProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Lets the caller of [`EmbeddingGeneratorMessage::Generate`] follow and abort a long request.
+#[derive(Clone, Default)]
+pub struct GenerateControl {
+    pub progress: Option<UnboundedSender<EmbeddingProgress>>,
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl GenerateControl {
+    pub fn with_progress(progress: UnboundedSender<EmbeddingProgress>) -> GenerateControl {
+        GenerateControl {
+            progress: Some(progress),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn report(&self, done: usize, total: usize) {
+        if let Some(progress) = &self.progress {
+            // nobody listening anymore is not a reason to stop
+            let _ = progress.send(EmbeddingProgress { done, total });
+        }
+    }
+}
+
 // TODO I'm not smart enough to make this generic over the Embeddable trait
 pub enum EmbeddingGeneratorMessage {
-    Generate(Vec<Embedding>, usize, RpcReplyPort<Vec<Embedding>>),
-    Query(String, RpcReplyPort<Vec<f32>>),
+    /// Embeds the content of every entry, encoding `batch_size` entries per forward pass.
+    Generate(
+        Vec<Embedding>,
+        usize,
+        GenerateControl,
+        RpcReplyPort<Result<Vec<Embedding>, EmbeddingError>>,
+    ),
+    Query(String, RpcReplyPort<Result<Vec<f32>, EmbeddingError>>),
+    Ready(RpcReplyPort<bool>),
 }
 
 impl ractor::Message for EmbeddingGeneratorMessage {}
 
-type SyncEmbeddingMessage = (Vec<String>, oneshot::Sender<Vec<Vec<f32>>>);
+/// Looks up the embedding generator started by the channel supervisor.
+pub fn embedding_generator() -> Option<ActorRef<EmbeddingGeneratorMessage>> {
+    ractor::registry::where_is(EMBEDDING_GENERATOR.to_owned()).map(|cell| cell.into())
+}
+
+type SyncEmbeddingMessage = (
+    Vec<String>,
+    oneshot::Sender<Result<Vec<Vec<f32>>, EmbeddingError>>,
+);
+
+#[derive(Clone)]
 pub struct EmbeddingGeneratorState {
     sender: mpsc::SyncSender<SyncEmbeddingMessage>,
+    ready: Arc<AtomicBool>,
 }
 
 impl EmbeddingGeneratorState {
     pub fn spawn() -> (JoinHandle<()>, Self) {
         let (sender, receiver) = mpsc::sync_channel(100);
-        let handle = thread::spawn(move || Self::runner(&receiver));
+        let ready = Arc::new(AtomicBool::new(false));
+        let runner_ready = ready.clone();
+        let handle = thread::spawn(move || Self::runner(&receiver, &runner_ready));
 
-        (handle, Self { sender })
+        (handle, Self { sender, ready })
     }
 
-    fn runner(receiver: &mpsc::Receiver<SyncEmbeddingMessage>) {
+    fn runner(receiver: &mpsc::Receiver<SyncEmbeddingMessage>, ready: &AtomicBool) {
         let model = SentenceEmbeddingsBuilder::remote(SentenceEmbeddingsModelType::AllMiniLmL12V2)
             .create_model()
             .expect("Could not create model");
+        ready.store(true, Ordering::SeqCst);
+        info!("Embedding model loaded");
+        Self::serve(receiver, |texts| model.encode(texts));
+    }
+
+    /// Answers requests until every sender is gone. A batch that fails to encode fails the
+    /// requests in it, the following ones are still served.
+    fn serve<E: fmt::Display>(
+        receiver: &mpsc::Receiver<SyncEmbeddingMessage>,
+        encode: impl Fn(&[String]) -> Result<Vec<Vec<f32>>, E>,
+    ) {
+        while let Ok(request) = receiver.recv() {
+            // combine whatever is queued into one forward pass
+            let mut queued = request.0.len();
+            let mut requests = vec![request];
+            while queued < MAX_BATCH_SIZE {
+                match receiver.try_recv() {
+                    Ok(request) => {
+                        queued += request.0.len();
+                        requests.push(request);
+                    }
+                    Err(_) => break,
+                }
+            }
 
-        while let Ok((texts, sender)) = receiver.recv() {
-            let embeddings = model.encode(&texts).unwrap();
-            sender.send(embeddings).unwrap();
+            trace!(
+                "Encoding {} sentences from {} requests",
+                queued,
+                requests.len()
+            );
+
+            let mut texts = Vec::with_capacity(queued);
+            let mut senders = Vec::with_capacity(requests.len());
+            for (sentences, sender) in requests {
+                senders.push((sentences.len(), sender));
+                texts.extend(sentences);
+            }
+
+            // the requesters may have given up waiting, that's fine
+            match encode(&texts) {
+                Ok(embeddings) => {
+                    let mut embeddings = embeddings.into_iter();
+                    for (count, sender) in senders {
+                        let _ = sender.send(Ok(embeddings.by_ref().take(count).collect()));
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to encode {} sentences: {}", queued, e);
+                    for (_, sender) in senders {
+                        let _ = sender.send(Err(EmbeddingError::Encoding(e.to_string())));
+                    }
+                }
+            }
         }
     }
 
-    pub async fn predict(&self, sentences: Vec<String>) -> Vec<Vec<f32>> {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub async fn predict(&self, sentences: Vec<String>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
         let (sender, receiver) = oneshot::channel();
-        self.sender.send((sentences, sender)).unwrap();
-        receiver.await.unwrap()
+        self.sender
+            .send((sentences, sender))
+            .map_err(|_| EmbeddingError::ModelUnavailable)?;
+        receiver
+            .await
+            .map_err(|_| EmbeddingError::ModelUnavailable)?
+    }
+
+    async fn generate(
+        &self,
+        embeddables: Vec<Embedding>,
+        batch_size: usize,
+        control: GenerateControl,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        let total = embeddables.len();
+        let mut embeddings = Vec::with_capacity(total);
+        for batch in embeddables.chunks(batch_size.max(1)) {
+            if control.is_cancelled() {
+                info!(
+                    "Embedding generation cancelled after {}/{}",
+                    embeddings.len(),
+                    total
+                );
+                return Err(EmbeddingError::Cancelled);
+            }
+
+            let texts = batch.iter().map(|e| e.content.clone()).collect();
+            let vectors = self.predict(texts).await?;
+            for (embeddable, vector) in batch.iter().zip(vectors) {
+                embeddings.push(Embedding {
+                    vector,
+                    graph_vertex: embeddable.graph_vertex.clone(),
+                    content: embeddable.content.clone(),
+                });
+            }
+
+            control.report(embeddings.len(), total);
+        }
+
+        Ok(embeddings)
     }
 }
 
@@ -85,24 +288,32 @@ impl Actor for EmbeddingGenerator {
         msg: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
+        // requests are answered from their own task so the actor keeps accepting work and the
+        // model thread can batch requests from different channels together
+        let generator = state.clone();
         match msg {
-            EmbeddingGeneratorMessage::Generate(embeddables, _size, reply_port) => {
-                info!("Generating embeddings for {} embeddables", embeddables.len());
-                let mut embeddings = Vec::new();
-                for embeddable in embeddables {
-                    let vectors = state.predict(vec![embeddable.content.clone()]).await;
-                    embeddings.push(Embedding {
-                        vector: vectors[0].clone(),
-                        graph_vertex: embeddable.graph_vertex,
-                        content: embeddable.content,
-                    })
-                }
-
-                reply_port.send(embeddings).unwrap();
+            EmbeddingGeneratorMessage::Generate(embeddables, batch_size, control, reply_port) => {
+                info!(
+                    "Generating embeddings for {} embeddables in batches of {}",
+                    embeddables.len(),
+                    batch_size
+                );
+                tokio::spawn(async move {
+                    let embeddings = generator.generate(embeddables, batch_size, control).await;
+                    let _ = reply_port.send(embeddings);
+                });
             }
             EmbeddingGeneratorMessage::Query(string, reply_port) => {
-                let vectors = state.predict(vec![string]).await;
-                reply_port.send(vectors[0].clone()).unwrap();
+                tokio::spawn(async move {
+                    let vector = generator
+                        .predict(vec![string])
+                        .await
+                        .map(|mut vectors| vectors.remove(0));
+                    let _ = reply_port.send(vector);
+                });
+            }
+            EmbeddingGeneratorMessage::Ready(reply_port) => {
+                let _ = reply_port.send(state.is_ready());
             }
         }
         Ok(())
@@ -119,10 +330,79 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn source_strips_fragment() {
+        let embedding = Embedding {
+            vector: vec![],
+            graph_vertex: "https://github.com/o/r/blob/main/src/lib.rs#L1-L40".to_string(),
+            content: String::new(),
+        };
+        assert_eq!(
+            embedding.source(),
+            "https://github.com/o/r/blob/main/src/lib.rs"
+        );
+
+        let embedding = Embedding {
+            graph_vertex: "https://www.youtube.com/watch?v=CEV_zDWsxGA&t=123".to_string(),
+            ..embedding
+        };
+        assert_eq!(
+            embedding.source(),
+            "https://www.youtube.com/watch?v=CEV_zDWsxGA"
+        );
+        assert_eq!(
+            strip_time_anchor("https://example.com/?t=abc"),
+            "https://example.com/?t=abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn encoding_failure_keeps_serving() {
+        let (sender, receiver) = mpsc::sync_channel(100);
+        let state = EmbeddingGeneratorState {
+            sender,
+            ready: Arc::new(AtomicBool::new(true)),
+        };
+        thread::spawn(move || {
+            EmbeddingGeneratorState::serve(&receiver, |texts| match texts[0].as_str() {
+                "poison" => Err("index out of bounds"),
+                _ => Ok(texts.iter().map(|text| vec![text.len() as f32]).collect()),
+            })
+        });
+
+        assert_eq!(
+            state.predict(vec!["poison".to_owned()]).await,
+            Err(EmbeddingError::Encoding("index out of bounds".to_owned()))
+        );
+        assert_eq!(
+            state.predict(vec!["hello".to_owned()]).await,
+            Ok(vec![vec![5.0]])
+        );
+    }
+
     #[tokio::test]
     async fn state_start() {
         let (_handle, model) = EmbeddingGeneratorState::spawn();
-        assert_eq!(model.predict(vec!["Hello".to_string()]).await.len(), 1);
+        assert_eq!(
+            model
+                .predict(vec!["Hello".to_string()])
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests() {
+        let (_handle, model) = EmbeddingGeneratorState::spawn();
+        let (a, b) = tokio::join!(
+            model.predict(vec!["Hello".to_string(), "World".to_string()]),
+            model.predict(vec!["Goodbye".to_string()])
+        );
+        assert_eq!(a.unwrap().len(), 2);
+        assert_eq!(b.unwrap().len(), 1);
+        assert!(model.is_ready());
     }
 
     #[tokio::test]
@@ -138,7 +418,7 @@ mod tests {
     async fn generate_embedding_from_gh_file() {
         let (actor, _) = Actor::spawn(None, EmbeddingGenerator, ()).await.unwrap();
         let embedding = GitHubFile {
-            path: "src/actors/tools/github.rs".to_string(),
+            path: "docs/device-status.md".to_string(),
             content: FILE_CONTENT.to_string(),
             metadata: HashMap::new(),
             repo: GitHubRepo {
@@ -149,7 +429,7 @@ mod tests {
         };
 
         let embedding = embedding
-            .get_chunks(300)
+            .get_chunks(20)
             .iter()
             .map(|c| Embedding {
                 content: c.clone(),
@@ -158,8 +438,45 @@ mod tests {
             })
             .collect();
 
-        let embedding = call!(actor, EmbeddingGeneratorMessage::Generate, embedding, 300).unwrap();
-        assert_eq!(embedding.len(), 3);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let embedding = call!(
+            actor,
+            EmbeddingGeneratorMessage::Generate,
+            embedding,
+            2,
+            GenerateControl::with_progress(sender)
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(embedding.len(), 4);
+
+        let mut reports = Vec::new();
+        while let Ok(progress) = receiver.try_recv() {
+            reports.push((progress.done, progress.total));
+        }
+        assert_eq!(reports, vec![(2, 4), (4, 4)]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_generate() {
+        let (actor, _) = Actor::spawn(None, EmbeddingGenerator, ()).await.unwrap();
+        let control = GenerateControl::default();
+        control.cancel();
+
+        let chunks = vec![Embedding {
+            content: "Hello".to_string(),
+            graph_vertex: "test".to_string(),
+            vector: vec![],
+        }];
+        let result = call!(
+            actor,
+            EmbeddingGeneratorMessage::Generate,
+            chunks,
+            8,
+            control
+        )
+        .unwrap();
+        assert_eq!(result.unwrap_err(), EmbeddingError::Cancelled);
     }
 
     // static example file content
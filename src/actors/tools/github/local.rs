@@ -0,0 +1,667 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::{debug, warn};
+
+use super::{
+    decode_file, diff_tree, refiltered, FileFilter, GitHubFile, GitHubRepo, GithubError, RepoDiff,
+    RepoManifest, ScrapeReport, SkipReason,
+};
+
+/// Owner recorded for repositories that are read straight from this machine.
+const LOCAL_OWNER: &str = "local";
+/// Shown instead of a commit for directories that are not a git repository.
+const WORKING_TREE: &str = "working tree";
+
+/// Local sources have to be inside this directory, they are refused when it is not set.
+const LOCAL_ROOT_VAR: &str = "LOCAL_REPO_ROOT";
+
+const LOCAL_PREFIXES: &[&str] = &["/", "./", "../", "~/"];
+const REMOTE_PREFIXES: &[&str] = &["file://", "ssh://", "git://", "git@", "git+"];
+/// Remotes fetched over the network, `file://` ones are local paths.
+const NETWORK_SCHEMES: &[&str] = &["https://", "http://", "ssh://", "git://"];
+
+/// Whether `source` should be read with git instead of the GitHub API: local paths, ssh and git
+/// remotes, and http remotes on other hosts like Gitea or GitLab (`https://host/owner/repo.git`).
+pub fn is_git_source(source: &str) -> bool {
+    if LOCAL_PREFIXES
+        .iter()
+        .chain(REMOTE_PREFIXES)
+        .any(|prefix| source.starts_with(prefix))
+    {
+        return true;
+    }
+
+    (source.starts_with("https://") || source.starts_with("http://"))
+        && !source.contains("github.com/")
+        && source.trim_end_matches('/').ends_with(".git")
+}
+
+/// Whether `source` is cloned rather than read from this machine: a network url or an
+/// scp-like `user@host:path`, with or without `git+`.
+fn is_remote(source: &str) -> bool {
+    let remote = source.strip_prefix("git+").unwrap_or(source);
+    if NETWORK_SCHEMES
+        .iter()
+        .any(|scheme| remote.starts_with(scheme))
+    {
+        return true;
+    }
+    if remote.starts_with("file://") {
+        return true;
+    }
+
+    // git reads `host:path` as ssh unless a slash comes before the colon, `transport::address`
+    // is a remote helper
+    match remote.split_once(':') {
+        Some((host, path)) => !host.is_empty() && !host.contains('/') && !path.starts_with(':'),
+        None => false,
+    }
+}
+
+/// Checks a source before anything is read from it. Local paths, `file://` remotes included,
+/// are canonicalized and have to be inside `LOCAL_REPO_ROOT`, relative ones are relative to it.
+pub fn resolve(source: &str) -> Result<String, GithubError> {
+    let root = env::var(LOCAL_ROOT_VAR)
+        .ok()
+        .filter(|root| !root.is_empty())
+        .map(PathBuf::from);
+    resolve_in(source, root.as_deref())
+}
+
+fn resolve_in(source: &str, root: Option<&Path>) -> Result<String, GithubError> {
+    let remote = source.strip_prefix("git+").unwrap_or(source);
+    // git would read it as an option
+    if remote.starts_with('-') {
+        return Err(GithubError::UnsupportedUrl(source.to_owned()));
+    }
+
+    if let Some(path) = remote.strip_prefix("file://") {
+        let path = local_path(path, root)?;
+        return Ok(format!("file://{}", path.display()));
+    }
+    if is_remote(remote) {
+        return Ok(source.to_owned());
+    }
+    Ok(local_path(remote, root)?.to_string_lossy().into_owned())
+}
+
+fn local_path(source: &str, root: Option<&Path>) -> Result<PathBuf, GithubError> {
+    let root = root.ok_or(GithubError::Forbidden(format!(
+        "local repositories are disabled, {LOCAL_ROOT_VAR} is not set"
+    )))?;
+    let root = root
+        .canonicalize()
+        .map_err(|e| GithubError::Io(format!("{LOCAL_ROOT_VAR} {}: {e}", root.display())))?;
+
+    let path = match source.strip_prefix("~/") {
+        Some(relative) => PathBuf::from(env::var("HOME").unwrap_or_default()).join(relative),
+        None => root.join(source),
+    };
+    let outside = || GithubError::Forbidden(format!("{source} is outside {LOCAL_ROOT_VAR}"));
+    // also resolves symlinks and `..` pointing out of the root
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) if !path.starts_with(&root) => return Err(outside()),
+        Err(_) => return Err(GithubError::NotFound(source.to_owned())),
+    };
+    if !path.starts_with(&root) {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+/// Owner and name the manifest of `source` is stored under, local sources have to be
+/// [`resolve`]d first.
+pub fn repo_name(source: &str) -> (String, String) {
+    if !is_remote(source) {
+        return (LOCAL_OWNER.to_owned(), source.to_owned());
+    }
+
+    // https://host/owner/repo.git, git@host:owner/repo.git, ...
+    let mut segments = source
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(|c| c == '/' || c == ':');
+    let name = segments.next().unwrap_or_default().to_owned();
+    let owner = segments.next().unwrap_or_default().to_owned();
+    (owner, name)
+}
+
+/// A working tree to read from, temporary clones are removed when dropped.
+struct Checkout {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        if !self.temporary {
+            return;
+        }
+        match fs::remove_dir_all(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("Failed to remove clone {}: {}", self.path.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<String, GithubError> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command.args(args).output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => GithubError::BinaryMissing("git".to_owned()),
+        _ => GithubError::Io(e.to_string()),
+    })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(GithubError::Git(stderr.trim().to_owned()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
+fn clone(url: &str) -> Result<Checkout, GithubError> {
+    let checkout = Checkout {
+        path: env::temp_dir().join(format!("andrena-clone-{}", rand::random::<u64>())),
+        temporary: true,
+    };
+    let target = checkout.path.to_string_lossy().into_owned();
+    debug!("Cloning {} into {}", url, target);
+    git(
+        None,
+        &["clone", "--quiet", "--depth", "1", "--", url, &target],
+    )?;
+    Ok(checkout)
+}
+
+fn checkout(source: &str) -> Result<Checkout, GithubError> {
+    if is_remote(source) {
+        return clone(source.trim_start_matches("git+"));
+    }
+
+    let path = PathBuf::from(source);
+    if !path.is_dir() {
+        return Err(GithubError::NotFound(source.to_owned()));
+    }
+
+    // bare clones have no working tree to walk
+    let bare = git(Some(&path), &["rev-parse", "--is-bare-repository"]);
+    if bare.map_or(false, |bare| bare == "true") {
+        return clone(&path.to_string_lossy());
+    }
+
+    Ok(Checkout {
+        path,
+        temporary: false,
+    })
+}
+
+/// The checked out commit and the blob SHA of every file in it, `None` when `root` is not the
+/// top of a git repository with at least one commit.
+fn tracked_blobs(root: &Path) -> Option<(String, HashMap<String, String>)> {
+    let toplevel = git(Some(root), &["rev-parse", "--show-toplevel"]).ok()?;
+    if Path::new(&toplevel).canonicalize().ok()? != root.canonicalize().ok()? {
+        return None;
+    }
+
+    let commit = git(Some(root), &["rev-parse", "HEAD"]).ok()?;
+    let listing = git(Some(root), &["ls-tree", "-r", "-z", "HEAD"]).ok()?;
+    let mut blobs = HashMap::new();
+    for entry in listing.split('\0') {
+        // <mode> <type> <sha>\t<path>
+        let Some((object, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let mut object = object.split_whitespace().skip(1);
+        if let (Some("blob"), Some(sha)) = (object.next(), object.next()) {
+            blobs.insert(path.to_owned(), sha.to_owned());
+        }
+    }
+    Some((commit, blobs))
+}
+
+fn repo_info(source: &str, root: &Path, tracked: bool) -> GitHubRepo {
+    let branch = if tracked {
+        git(Some(root), &["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_default()
+    } else {
+        WORKING_TREE.to_owned()
+    };
+    let (owner, name) = repo_name(source);
+    GitHubRepo {
+        owner,
+        name,
+        branch,
+    }
+}
+
+/// Browser url of a file, remotes without a web interface get a `file://` url instead.
+fn file_url(source: &str, branch: &str, path: &str) -> String {
+    let remote = source
+        .trim_start_matches("git+")
+        .trim_end_matches('/')
+        .trim_end_matches(".git");
+
+    let web = if let Some(rest) = remote.strip_prefix("git@") {
+        rest.split_once(':')
+            .map(|(host, repo)| format!("https://{host}/{repo}"))
+    } else if let Some(rest) = remote.strip_prefix("ssh://") {
+        let rest = rest.split_once('@').map_or(rest, |(_, rest)| rest);
+        Some(format!("https://{rest}"))
+    } else if remote.starts_with("https://") || remote.starts_with("http://") {
+        Some(remote.to_owned())
+    } else {
+        None
+    };
+
+    match web {
+        Some(web) => format!("{web}/blob/{branch}/{path}"),
+        None if remote.starts_with("file://") || remote.starts_with("git://") => {
+            format!("{remote}/{path}")
+        }
+        None => format!("file://{remote}/{path}"),
+    }
+}
+
+/// Lists every file below `root` with its size, paths are relative and use `/` separators.
+fn walk(root: &Path) -> Result<HashMap<String, u64>, GithubError> {
+    let mut files = HashMap::new();
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            if entry.file_name() == ".git" {
+                continue;
+            }
+
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                directories.push(entry.path());
+            } else if file_type.is_file() {
+                let path = entry.path();
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                let relative: Vec<String> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                files.insert(relative.join("/"), entry.metadata()?.len());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reads a repository from disk, cloning it first when `source` is a remote or a bare clone.
+///
+/// Git repositories are synced incrementally like the API scraper, plain directories are read
+/// completely every time.
+pub fn scrape(
+    source: &str,
+    filter: &FileFilter,
+    previous: Option<&RepoManifest>,
+) -> Result<ScrapeReport, GithubError> {
+    let source = &resolve(source)?;
+    let checkout = checkout(source)?;
+    let root = checkout.path.as_path();
+    let tracked = tracked_blobs(root);
+    let info = repo_info(source, root, tracked.is_some());
+    let sizes = walk(root)?;
+    debug!("Found {} files in {}", sizes.len(), root.display());
+
+    let (commit, blobs) = match tracked {
+        Some((commit, blobs)) => (Some(commit), blobs),
+        // without git every file counts as new
+        None => (
+            None,
+            sizes
+                .keys()
+                .map(|path| (path.clone(), String::new()))
+                .collect(),
+        ),
+    };
+    let previous = previous.filter(|manifest| commit.is_some() && manifest.branch == info.branch);
+    let (previous, refiltered) = refiltered(previous, filter);
+
+    let mut report = ScrapeReport::default();
+    let mut diff = RepoDiff {
+        repo: format!("{}/{}", info.owner, info.name),
+        from: previous.map(|manifest| manifest.commit.clone()),
+        to: commit.clone().unwrap_or(WORKING_TREE.to_owned()),
+        added: 0,
+        changed: 0,
+        removed: 0,
+        unchanged: 0,
+        stale_sources: Vec::new(),
+    };
+
+    if let Some(previous) = previous.filter(|manifest| Some(&manifest.commit) == commit.as_ref()) {
+        diff.unchanged = previous.files.len();
+        report.diffs.push(diff);
+        report.manifests.push(previous.clone());
+        return Ok(report);
+    }
+
+    let tree_diff = diff_tree(previous, &blobs);
+    diff.added = tree_diff.added.len();
+    diff.changed = tree_diff.changed.len();
+    diff.removed = tree_diff.removed.len();
+    diff.unchanged = tree_diff.unchanged;
+
+    let url = |path: &str| file_url(source, &info.branch, path);
+    diff.stale_sources = tree_diff.removed.iter().map(|path| url(path)).collect();
+
+    let gitattributes = match filter.default_ignores {
+        true => fs::read_to_string(root.join(".gitattributes")).ok(),
+        false => None,
+    };
+    let matcher = filter.matcher(gitattributes.as_deref())?;
+
+    let mut manifest_files: HashMap<String, String> = blobs
+        .iter()
+        .filter(|(path, sha)| previous.and_then(|manifest| manifest.files.get(*path)) == Some(*sha))
+        .map(|(path, sha)| (path.clone(), sha.clone()))
+        .collect();
+
+    for path in tree_diff.added.iter().chain(&tree_diff.changed) {
+        let Some(&size) = sizes.get(path) else {
+            report.skip(
+                path,
+                SkipReason::Failed("missing from the working tree".to_owned()),
+            );
+            continue;
+        };
+
+        let content = match matcher.check(path, size) {
+            Some(reason) => Err(reason),
+            None => match fs::read(root.join(path)) {
+                Ok(bytes) => decode_file(bytes, filter.max_file_size),
+                Err(e) => {
+                    // not recorded in the manifest so the next sync retries it
+                    report.skip(path, SkipReason::Failed(e.to_string()));
+                    continue;
+                }
+            },
+        };
+
+        manifest_files.insert(path.clone(), blobs[path].clone());
+        match content {
+            Ok(content) => {
+                report
+                    .files
+                    .push(GitHubFile::new(&info, path, url(path), "git", content));
+            }
+            Err(reason) => report.skip(path, reason),
+        }
+    }
+
+    // changed files that are now skipped should not keep their old content around
+    diff.stale_sources.extend(
+        tree_diff
+            .changed
+            .iter()
+            .chain(
+                refiltered
+                    .into_iter()
+                    .flat_map(|manifest| manifest.files.keys()),
+            )
+            .filter(|path| !report.files.iter().any(|file| file.path == **path))
+            .map(|path| url(path)),
+    );
+
+    report.diffs.push(diff);
+    if let Some(commit) = commit {
+        report.manifests.push(RepoManifest {
+            owner: info.owner.clone(),
+            name: info.name.clone(),
+            branch: info.branch.clone(),
+            commit,
+            files: manifest_files,
+            filter: filter.fingerprint(),
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        // every test uses the same root, setting it again is harmless
+        env::set_var(LOCAL_ROOT_VAR, env::temp_dir());
+        let root = env::temp_dir().join(format!("andrena-fixture-{}", rand::random::<u64>()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("src/main.rs"),
+            "fn main() {\n    println!(\"hi\");\n}\n",
+        )
+        .unwrap();
+        fs::write(root.join("README.md"), "# Fixture\n\nA test repository.\n").unwrap();
+        fs::write(root.join("Cargo.lock"), "version = 3\n").unwrap();
+        fs::write(root.join("data.txt"), b"header\0\x01\x02").unwrap();
+        root
+    }
+
+    fn commit_all(root: &Path, message: &str) {
+        git(Some(root), &["add", "-A"]).unwrap();
+        git(
+            Some(root),
+            &[
+                "-c",
+                "user.name=andrena",
+                "-c",
+                "user.email=andrena@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                message,
+            ],
+        )
+        .unwrap();
+    }
+
+    fn paths(report: &ScrapeReport) -> Vec<String> {
+        let mut paths: Vec<String> = report.files.iter().map(|f| f.path.clone()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn git_sources() {
+        assert!(is_git_source("/srv/repos/andrena"));
+        assert!(is_git_source("git@gitea.example.com:team/andrena.git"));
+        assert!(is_git_source("https://gitlab.com/team/andrena.git"));
+        assert!(!is_git_source("https://github.com/alvariumhex/andrena"));
+        assert!(!is_git_source("https://gitlab.com/team/andrena"));
+
+        assert_eq!(
+            file_url(
+                "git@gitea.example.com:team/andrena.git",
+                "main",
+                "src/lib.rs"
+            ),
+            "https://gitea.example.com/team/andrena/blob/main/src/lib.rs"
+        );
+        assert_eq!(
+            file_url(
+                "ssh://git@gitlab.com/team/andrena.git",
+                "main",
+                "src/lib.rs"
+            ),
+            "https://gitlab.com/team/andrena/blob/main/src/lib.rs"
+        );
+
+        assert_eq!(
+            repo_name("git@gitea.example.com:team/andrena.git"),
+            ("team".to_owned(), "andrena".to_owned())
+        );
+    }
+
+    #[test]
+    fn local_root() {
+        let root = fixture();
+        let inside = root.canonicalize().unwrap();
+        assert_eq!(
+            resolve_in(&root.to_string_lossy(), Some(&root)).unwrap(),
+            inside.to_string_lossy()
+        );
+        assert_eq!(
+            resolve_in("./src", Some(&root)).unwrap(),
+            inside.join("src").to_string_lossy()
+        );
+        assert_eq!(
+            resolve_in(&format!("file://{}/src", root.display()), Some(&root)).unwrap(),
+            format!("file://{}", inside.join("src").display())
+        );
+
+        let forbidden = |source: &str, root: Option<&Path>| {
+            matches!(resolve_in(source, root), Err(GithubError::Forbidden(_)))
+        };
+        assert!(forbidden("/etc", Some(&root.join("src"))));
+        assert!(forbidden("../README.md", Some(&root.join("src"))));
+        assert!(forbidden("file:///etc", Some(&root)));
+        assert!(forbidden(&root.to_string_lossy(), None));
+        assert!(matches!(
+            resolve_in("git+--upload-pack=touch /tmp/pwned", Some(&root)),
+            Err(GithubError::UnsupportedUrl(_))
+        ));
+        assert!(forbidden("git+/outside", Some(&root)));
+        assert!(forbidden("git+/etc", Some(&root)));
+        assert!(forbidden("git+../README.md", Some(&root.join("src"))));
+        assert!(forbidden("git+ext::sh -c touch% /tmp/pwned", None));
+        assert_eq!(
+            resolve_in("git+./src", Some(&root)).unwrap(),
+            inside.join("src").to_string_lossy()
+        );
+        assert_eq!(
+            resolve_in("https://gitlab.com/team/andrena.git", None).unwrap(),
+            "https://gitlab.com/team/andrena.git"
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn scrape_directory() {
+        let root = fixture();
+        let report = scrape(&root.to_string_lossy(), &FileFilter::default(), None).unwrap();
+
+        assert_eq!(paths(&report), vec!["README.md", "src/main.rs"]);
+        assert!(report.manifests.is_empty());
+        assert_eq!(report.diffs[0].added, 4);
+
+        let mut skipped: Vec<(String, SkipReason)> = report
+            .skipped
+            .iter()
+            .map(|s| (s.path.clone(), s.reason.clone()))
+            .collect();
+        skipped.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            skipped,
+            vec![
+                ("Cargo.lock".to_owned(), SkipReason::Ignored),
+                ("data.txt".to_owned(), SkipReason::Binary),
+            ]
+        );
+
+        let main = report
+            .files
+            .iter()
+            .find(|f| f.path == "src/main.rs")
+            .unwrap();
+        assert_eq!(main.metadata["provider"], "git");
+        assert!(main.metadata["url"].ends_with("/src/main.rs"));
+        assert!(main.content.contains("    println!"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn incremental_git_sync() {
+        let root = fixture();
+        git(Some(&root), &["init", "--quiet", "-b", "main"]).unwrap();
+        commit_all(&root, "initial");
+
+        // read through a clone like a remote
+        let source = format!("file://{}", root.display());
+        let first = scrape(&source, &FileFilter::default(), None).unwrap();
+        assert_eq!(paths(&first), vec!["README.md", "src/main.rs"]);
+        let manifest = first.manifests[0].clone();
+        assert_eq!(manifest.branch, "main");
+        assert_eq!(manifest.files.len(), 4);
+
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn lib() {}\n").unwrap();
+        fs::remove_file(root.join("README.md")).unwrap();
+        commit_all(&root, "update");
+
+        let second = scrape(&source, &FileFilter::default(), Some(&manifest)).unwrap();
+        assert_eq!(paths(&second), vec!["src/lib.rs", "src/main.rs"]);
+        let diff = &second.diffs[0];
+        assert_eq!(diff.from.as_ref(), Some(&manifest.commit));
+        assert_eq!(
+            (diff.added, diff.changed, diff.removed, diff.unchanged),
+            (1, 1, 1, 2)
+        );
+        assert_eq!(diff.stale_sources, vec![format!("{source}/README.md")]);
+
+        let third = scrape(&source, &FileFilter::default(), Some(&second.manifests[0])).unwrap();
+        assert!(third.files.is_empty());
+        assert_eq!(third.manifests[0], second.manifests[0]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn refilter_sync() {
+        let root = fixture();
+        git(Some(&root), &["init", "--quiet", "-b", "main"]).unwrap();
+        commit_all(&root, "initial");
+        let source = format!("file://{}", root.display());
+
+        let first = scrape(&source, &FileFilter::default(), None).unwrap();
+        assert_eq!(paths(&first), vec!["README.md", "src/main.rs"]);
+
+        // same commit, narrower filter: the README is dropped
+        let narrow = FileFilter {
+            include: vec!["src/**".to_owned()],
+            ..FileFilter::default()
+        };
+        let second = scrape(&source, &narrow, Some(&first.manifests[0])).unwrap();
+        assert_eq!(paths(&second), vec!["src/main.rs"]);
+        assert!(second.diffs[0]
+            .stale_sources
+            .contains(&format!("{source}/README.md")));
+
+        // an unchanged filter is up to date
+        let third = scrape(&source, &narrow, Some(&second.manifests[0])).unwrap();
+        assert!(third.files.is_empty());
+
+        // wider again: the README and the lock file come back
+        let wide = FileFilter {
+            default_ignores: false,
+            ..FileFilter::default()
+        };
+        let fourth = scrape(&source, &wide, Some(&third.manifests[0])).unwrap();
+        assert_eq!(
+            paths(&fourth),
+            vec!["Cargo.lock", "README.md", "src/main.rs"]
+        );
+        assert!(fourth.diffs[0]
+            .stale_sources
+            .iter()
+            .all(|source| source.ends_with("data.txt")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,1325 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use hubcaps::{
+    repositories::{OrgRepoListOptions, Repo, Repository},
+    Credentials, Github,
+};
+use log::{debug, error, info, trace, warn};
+use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use self::threads::{GitHubThread, ThreadClient, ThreadOptions, GITHUB_API_URL};
+use super::embeddings::{Embeddable, Embedding};
+use crate::chunking::{chunk_file, CodeChunk, CHUNK_OVERLAP_LINES, MAX_CHUNK_LINES};
+
+pub mod local;
+pub mod threads;
+
+#[derive(Debug, Clone)]
+pub struct GitHubRepo {
+    pub owner: String,
+    pub name: String,
+    pub branch: String,
+}
+
+impl GitHubRepo {
+    /// Browser url of a file on the branch, stays the same across commits.
+    pub fn file_url(&self, path: &str) -> String {
+        format!(
+            "https://github.com/{}/{}/blob/{}/{}",
+            self.owner, self.name, self.branch, path
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GitHubFile {
+    /// Path relative to the repository root.
+    pub path: String,
+    pub content: String,
+    pub metadata: HashMap<String, String>,
+    pub repo: GitHubRepo,
+}
+
+impl Embeddable for GitHubFile {
+    fn human_readable_source(&self) -> String {
+        self.path.clone()
+    }
+
+    fn short_description(&self) -> String {
+        unimplemented!()
+    }
+
+    fn long_description(&self) -> String {
+        unimplemented!()
+    }
+
+    /// `size` is the maximum number of lines per chunk.
+    fn get_chunks(&self, size: usize) -> Vec<String> {
+        chunk_file(&self.path, &self.content, size, CHUNK_OVERLAP_LINES)
+            .iter()
+            .map(CodeChunk::with_header)
+            .collect()
+    }
+}
+
+impl GitHubFile {
+    fn new(info: &GitHubRepo, path: &str, url: String, provider: &str, content: String) -> Self {
+        let mut metadata: HashMap<String, String> = HashMap::new();
+
+        metadata.insert(String::from("provider"), provider.to_owned());
+        metadata.insert(String::from("url"), url);
+        metadata.insert(String::from("repo"), info.name.clone());
+        metadata.insert(String::from("author"), info.owner.clone());
+
+        GitHubFile {
+            content,
+            path: path.to_owned(),
+            metadata,
+            repo: info.clone(),
+        }
+    }
+
+    pub fn chunks(&self) -> Vec<CodeChunk> {
+        chunk_file(
+            &self.path,
+            &self.content,
+            MAX_CHUNK_LINES,
+            CHUNK_OVERLAP_LINES,
+        )
+    }
+
+    /// One embedding per chunk, linking to the chunk's lines like `blob/main/src/lib.rs#L12-L40`.
+    pub fn embeddings(&self) -> Vec<Embedding> {
+        let url = self.metadata.get("url").cloned().unwrap_or_default();
+        self.chunks()
+            .iter()
+            .map(|chunk| Embedding {
+                content: chunk.with_header(),
+                vector: vec![],
+                graph_vertex: format!("{}{}", url, chunk.line_anchor()),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum GithubError {
+    Network(String),
+    Auth,
+    NotFound(String),
+    RateLimited,
+    UnsupportedUrl(String),
+    InvalidFilter(String),
+    Forbidden(String),
+    BinaryMissing(String),
+    Git(String),
+    Io(String),
+}
+
+impl fmt::Display for GithubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GithubError::Network(e) => write!(f, "could not reach GitHub: {e}"),
+            GithubError::Auth => write!(f, "GitHub rejected the access token"),
+            GithubError::NotFound(what) => write!(f, "{what} was not found on GitHub"),
+            GithubError::RateLimited => write!(f, "GitHub rate limit reached, try again later"),
+            GithubError::UnsupportedUrl(url) => write!(f, "{url} is not a GitHub repository url"),
+            GithubError::InvalidFilter(filter) => write!(f, "invalid filter: {filter}"),
+            GithubError::Forbidden(reason) => write!(f, "{reason}"),
+            GithubError::BinaryMissing(binary) => write!(f, "{binary} is not installed"),
+            GithubError::Git(e) => write!(f, "git failed: {e}"),
+            GithubError::Io(e) => write!(f, "file error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for GithubError {}
+
+impl From<hubcaps::Error> for GithubError {
+    fn from(e: hubcaps::Error) -> Self {
+        match e {
+            hubcaps::Error::RateLimit { .. } => GithubError::RateLimited,
+            hubcaps::Error::Fault { code, .. } if code.as_u16() == 401 => GithubError::Auth,
+            hubcaps::Error::Fault { code, .. } if code.as_u16() == 404 => {
+                GithubError::NotFound("resource".to_owned())
+            }
+            e => GithubError::Network(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for GithubError {
+    fn from(e: std::io::Error) -> Self {
+        GithubError::Io(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for GithubError {
+    fn from(e: reqwest::Error) -> Self {
+        GithubError::Network(e.to_string())
+    }
+}
+
+/// Number of repositories of an organisation that are scraped at the same time.
+const MAX_CONCURRENT_REPOS: usize = 4;
+/// Number of files of a repository that are downloaded at the same time.
+const MAX_CONCURRENT_FILES: usize = 8;
+
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 512 * 1024;
+
+/// Same heuristic as git, a NUL byte in the first 8000 bytes marks a file as binary.
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Files that are almost never useful as context: dependencies, build output, lockfiles and media.
+const DEFAULT_IGNORES: &[&str] = &[
+    "**/.git/**",
+    "**/node_modules/**",
+    "**/vendor/**",
+    "**/third_party/**",
+    "**/dist/**",
+    "**/target/**",
+    "**/*.lock",
+    "**/package-lock.json",
+    "**/pnpm-lock.yaml",
+    "**/go.sum",
+    "**/*.min.{js,css}",
+    "**/*.map",
+    "**/*.{png,jpg,jpeg,gif,bmp,ico,webp,svg,pdf}",
+    "**/*.{zip,gz,tgz,tar,jar,exe,dll,so,dylib,bin,wasm}",
+    "**/*.{woff,woff2,ttf,otf,eot}",
+    "**/*.{mp3,mp4,wav,webm,mov}",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GithubTarget {
+    Repo(String, String),
+    Org(String),
+    /// A local path or a remote that is read with plain git, see [`local`].
+    Git(String),
+}
+
+/// Extracts the repository or organisation a GitHub url points to, local paths and other git
+/// remotes become [`GithubTarget::Git`].
+pub fn parse_github_url(url: &str) -> Result<GithubTarget, GithubError> {
+    if local::is_git_source(url) {
+        return local::resolve(url).map(GithubTarget::Git);
+    }
+
+    let regex = Regex::new(r"(?m)github\.com/([\w\-_.]+)(?:/([\w\-_.]+))?").unwrap();
+    let captures = regex
+        .captures(url)
+        .ok_or(GithubError::UnsupportedUrl(url.to_owned()))?;
+    let owner = captures[1].to_owned();
+    match captures.get(2) {
+        Some(org) if owner == "orgs" => Ok(GithubTarget::Org(org.as_str().to_owned())),
+        Some(repo) => Ok(GithubTarget::Repo(
+            owner,
+            repo.as_str().trim_end_matches(".git").to_owned(),
+        )),
+        None => Ok(GithubTarget::Org(owner)),
+    }
+}
+
+/// Selects which repositories of an organisation get scraped.
+#[derive(Debug, Clone, Default)]
+pub struct OrgFilter {
+    pub include_archived: bool,
+    pub include_forks: bool,
+    /// Repository name globs, everything is included when empty.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Primary languages to keep, compared case insensitively. Everything is kept when empty.
+    pub languages: Vec<String>,
+}
+
+impl OrgFilter {
+    fn matcher(&self) -> Result<OrgMatcher, GithubError> {
+        Ok(OrgMatcher {
+            filter: self.clone(),
+            include: glob_set(&self.include)?,
+            exclude: glob_set(&self.exclude)?,
+        })
+    }
+}
+
+/// Selects which files of a repository get downloaded.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    /// Path globs, everything is included when empty.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Skip [`DEFAULT_IGNORES`] and files marked `linguist-vendored` or `linguist-generated`.
+    pub default_ignores: bool,
+    pub max_file_size: u64,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        FileFilter {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            default_ignores: true,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+impl FileFilter {
+    fn matcher(&self, gitattributes: Option<&str>) -> Result<FileMatcher, GithubError> {
+        let attributes = gitattributes.map(parse_gitattributes).unwrap_or_default();
+        let mut ignored: Vec<String> = Vec::new();
+        if self.default_ignores {
+            ignored.extend(DEFAULT_IGNORES.iter().map(|glob| glob.to_string()));
+        }
+
+        Ok(FileMatcher {
+            include: glob_set(&self.include)?,
+            exclude: glob_set(&self.exclude)?,
+            ignored: glob_set(&ignored)?,
+            vendored: glob_set(&attributes.vendored)?,
+            generated: glob_set(&attributes.generated)?,
+            not_vendored: glob_set(&attributes.not_vendored)?,
+            filter: self.clone(),
+        })
+    }
+
+    /// Identifies the filter a [`RepoManifest`] was synced with.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "include={:?} exclude={:?} default_ignores={} max_file_size={}",
+            self.include, self.exclude, self.default_ignores, self.max_file_size
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScrapeArgs {
+    pub org: OrgFilter,
+    pub files: FileFilter,
+    /// Clone GitHub repositories with git instead of going through the API.
+    pub clone: bool,
+    /// Also ingest issues, pull requests and discussions of repositories.
+    pub threads: Option<ThreadOptions>,
+}
+
+/// Parses command arguments like `--forks --include=api-* --path=src/** --max-size=100000`.
+pub fn parse_scrape_args(args: &str) -> Result<ScrapeArgs, GithubError> {
+    let mut scrape_args = ScrapeArgs::default();
+    let ScrapeArgs {
+        org,
+        files,
+        clone,
+        threads,
+    } = &mut scrape_args;
+    for arg in args.split_whitespace() {
+        match arg.split_once('=') {
+            Some(("--include", glob)) => org.include.push(glob.to_owned()),
+            Some(("--exclude", glob)) => org.exclude.push(glob.to_owned()),
+            Some(("--language", language)) => org.languages.push(language.to_owned()),
+            Some(("--path", glob)) => files.include.push(glob.to_owned()),
+            Some(("--skip", glob)) => files.exclude.push(glob.to_owned()),
+            Some(("--max-size", size)) => {
+                files.max_file_size = size
+                    .parse()
+                    .map_err(|_| GithubError::InvalidFilter(arg.to_owned()))?;
+            }
+            None if arg == "--archived" => org.include_archived = true,
+            None if arg == "--forks" => org.include_forks = true,
+            None if arg == "--no-default-ignores" => files.default_ignores = false,
+            Some(("--max-threads", count)) => {
+                threads
+                    .get_or_insert_with(ThreadOptions::default)
+                    .max_threads = count
+                    .parse()
+                    .map_err(|_| GithubError::InvalidFilter(arg.to_owned()))?;
+            }
+            None if arg == "--clone" => *clone = true,
+            None if arg == "--threads" => {
+                threads.get_or_insert_with(ThreadOptions::default);
+            }
+            _ => return Err(GithubError::InvalidFilter(arg.to_owned())),
+        }
+    }
+    Ok(scrape_args)
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, GithubError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| GithubError::InvalidFilter(e.to_string()))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| GithubError::InvalidFilter(e.to_string()))
+}
+
+struct OrgMatcher {
+    filter: OrgFilter,
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl OrgMatcher {
+    fn matches(&self, name: &str, archived: bool, fork: bool, language: Option<&str>) -> bool {
+        if archived && !self.filter.include_archived {
+            return false;
+        }
+        if fork && !self.filter.include_forks {
+            return false;
+        }
+        if !self.filter.include.is_empty() && !self.include.is_match(name) {
+            return false;
+        }
+        if self.exclude.is_match(name) {
+            return false;
+        }
+        if self.filter.languages.is_empty() {
+            return true;
+        }
+        language.map_or(false, |language| {
+            self.filter
+                .languages
+                .iter()
+                .any(|l| l.eq_ignore_ascii_case(language))
+        })
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct LinguistAttributes {
+    vendored: Vec<String>,
+    generated: Vec<String>,
+    /// Paths explicitly marked `-linguist-vendored`, these override the default ignore list.
+    not_vendored: Vec<String>,
+}
+
+/// Extracts the linguist overrides from a `.gitattributes` file.
+fn parse_gitattributes(content: &str) -> LinguistAttributes {
+    let mut attributes = LinguistAttributes::default();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let Some(pattern) = parts.next() else {
+            continue;
+        };
+        let glob = attribute_glob(pattern);
+        for attribute in parts {
+            match attribute {
+                "linguist-vendored" | "linguist-vendored=true" => {
+                    attributes.vendored.push(glob.clone())
+                }
+                "-linguist-vendored" | "linguist-vendored=false" => {
+                    attributes.not_vendored.push(glob.clone())
+                }
+                "linguist-generated" | "linguist-generated=true" => {
+                    attributes.generated.push(glob.clone())
+                }
+                _ => {}
+            }
+        }
+    }
+    attributes
+}
+
+/// Converts a gitattributes pattern into a glob relative to the repository root.
+fn attribute_glob(pattern: &str) -> String {
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    let mut glob = pattern.trim_start_matches('/').to_owned();
+    if glob.ends_with('/') {
+        glob.push_str("**");
+    }
+    if anchored {
+        glob
+    } else {
+        format!("**/{glob}")
+    }
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_SNIFF_LEN).any(|&b| b == 0)
+}
+
+/// Turns downloaded bytes into text, or the reason the file can't be used.
+fn decode_file(bytes: Vec<u8>, max_file_size: u64) -> Result<String, SkipReason> {
+    if bytes.len() as u64 > max_file_size {
+        return Err(SkipReason::TooLarge(bytes.len() as u64));
+    }
+    if is_binary(&bytes) {
+        return Err(SkipReason::Binary);
+    }
+    String::from_utf8(bytes).map_err(|_| SkipReason::InvalidUtf8)
+}
+
+struct FileMatcher {
+    filter: FileFilter,
+    include: GlobSet,
+    exclude: GlobSet,
+    ignored: GlobSet,
+    vendored: GlobSet,
+    generated: GlobSet,
+    not_vendored: GlobSet,
+}
+
+impl FileMatcher {
+    /// Returns why a file should not be downloaded, `path` is relative to the repository root.
+    fn check(&self, path: &str, size: u64) -> Option<SkipReason> {
+        if !self.filter.include.is_empty() && !self.include.is_match(path) {
+            return Some(SkipReason::Excluded);
+        }
+        if self.exclude.is_match(path) {
+            return Some(SkipReason::Excluded);
+        }
+        if self.filter.default_ignores {
+            if self.generated.is_match(path) {
+                return Some(SkipReason::Generated);
+            }
+            if self.vendored.is_match(path) {
+                return Some(SkipReason::Vendored);
+            }
+            if self.ignored.is_match(path) && !self.not_vendored.is_match(path) {
+                return Some(SkipReason::Ignored);
+            }
+        }
+        if size > self.filter.max_file_size {
+            return Some(SkipReason::TooLarge(size));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    Excluded,
+    Ignored,
+    Vendored,
+    Generated,
+    TooLarge(u64),
+    Binary,
+    InvalidUtf8,
+    Failed(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Excluded => write!(f, "excluded by filter"),
+            SkipReason::Ignored => write!(f, "in the default ignore list"),
+            SkipReason::Vendored => write!(f, "vendored"),
+            SkipReason::Generated => write!(f, "generated"),
+            SkipReason::TooLarge(size) => write!(f, "too large ({size} bytes)"),
+            SkipReason::Binary => write!(f, "binary"),
+            SkipReason::InvalidUtf8 => write!(f, "not valid UTF-8"),
+            SkipReason::Failed(e) => write!(f, "download failed: {e}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+/// What was ingested from a repository, used to only fetch what changed on the next sync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RepoManifest {
+    pub owner: String,
+    pub name: String,
+    pub branch: String,
+    pub commit: String,
+    /// Blob SHA of every processed file by path.
+    pub files: HashMap<String, String>,
+    /// [`FileFilter::fingerprint`] of the sync, empty for manifests stored before it was kept.
+    #[serde(default)]
+    pub filter: String,
+}
+
+impl RepoManifest {
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+}
+
+/// Changes to a repository between the previous and the current sync.
+#[derive(Debug, Clone)]
+pub struct RepoDiff {
+    pub repo: String,
+    pub from: Option<String>,
+    pub to: String,
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Urls of files whose embeddings are outdated and have no replacement.
+    pub stale_sources: Vec<String>,
+}
+
+fn short_sha(sha: &str) -> &str {
+    match sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => &sha[..sha.len().min(7)],
+        false => sha,
+    }
+}
+
+impl fmt::Display for RepoDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.from {
+            None => write!(
+                f,
+                "{}: ingested {} files at {}",
+                self.repo,
+                self.added,
+                short_sha(&self.to)
+            ),
+            Some(from) if *from == self.to => {
+                write!(f, "{}: up to date at {}", self.repo, short_sha(&self.to))
+            }
+            Some(from) => write!(
+                f,
+                "{} {}..{}: {} added, {} changed, {} removed, {} unchanged",
+                self.repo,
+                short_sha(from),
+                short_sha(&self.to),
+                self.added,
+                self.changed,
+                self.removed,
+                self.unchanged
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct TreeDiff {
+    added: Vec<String>,
+    changed: Vec<String>,
+    removed: Vec<String>,
+    unchanged: usize,
+}
+
+/// Splits off a manifest that was synced with another filter: files it skipped may be included
+/// now and the other way around, so every file is checked again. Returns the manifest to diff
+/// against and the one whose files have to be dropped unless they are fetched again.
+fn refiltered<'a>(
+    previous: Option<&'a RepoManifest>,
+    filter: &FileFilter,
+) -> (Option<&'a RepoManifest>, Option<&'a RepoManifest>) {
+    match previous {
+        Some(manifest) if manifest.filter != filter.fingerprint() => (None, Some(manifest)),
+        previous => (previous, None),
+    }
+}
+
+/// Compares the blob SHAs of a tree against the previously ingested ones.
+fn diff_tree(previous: Option<&RepoManifest>, blobs: &HashMap<String, String>) -> TreeDiff {
+    let empty = HashMap::new();
+    let previous = previous.map_or(&empty, |manifest| &manifest.files);
+
+    let mut diff = TreeDiff::default();
+    for (path, sha) in blobs {
+        match previous.get(path) {
+            None => diff.added.push(path.clone()),
+            Some(previous_sha) if previous_sha != sha => diff.changed.push(path.clone()),
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+    diff.removed = previous
+        .keys()
+        .filter(|path| !blobs.contains_key(*path))
+        .cloned()
+        .collect();
+
+    diff.added.sort();
+    diff.changed.sort();
+    diff.removed.sort();
+    diff
+}
+
+/// The files that were scraped and the ones that were left out.
+#[derive(Debug, Clone, Default)]
+pub struct ScrapeReport {
+    pub files: Vec<GitHubFile>,
+    pub skipped: Vec<SkippedFile>,
+    pub diffs: Vec<RepoDiff>,
+    pub manifests: Vec<RepoManifest>,
+}
+
+impl ScrapeReport {
+    fn skip(&mut self, path: &str, reason: SkipReason) {
+        trace!("Skipping {}: {}", path, reason);
+        self.skipped.push(SkippedFile {
+            path: path.to_owned(),
+            reason,
+        });
+    }
+
+    fn merge(&mut self, other: ScrapeReport) {
+        self.files.extend(other.files);
+        self.skipped.extend(other.skipped);
+        self.diffs.extend(other.diffs);
+        self.manifests.extend(other.manifests);
+    }
+
+    /// Short overview of why files were skipped, `None` when nothing was skipped.
+    pub fn skipped_summary(&self) -> Option<String> {
+        if self.skipped.is_empty() {
+            return None;
+        }
+
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for skipped in &self.skipped {
+            let reason = match &skipped.reason {
+                SkipReason::TooLarge(_) => "too large".to_owned(),
+                SkipReason::Failed(_) => "failed to download".to_owned(),
+                reason => reason.to_string(),
+            };
+            match counts.iter_mut().find(|(r, _)| *r == reason) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((reason, 1)),
+            }
+        }
+
+        let counts: Vec<String> = counts
+            .iter()
+            .map(|(reason, count)| format!("{count} {reason}"))
+            .collect();
+        Some(format!(
+            "Skipped {} files: {}",
+            self.skipped.len(),
+            counts.join(", ")
+        ))
+    }
+}
+
+pub enum GithubScraperMessage {
+    /// Scrapes a branch, only downloading files that changed since the given manifest.
+    ScrapeRepo(
+        String,
+        String,
+        String,
+        FileFilter,
+        Option<RepoManifest>,
+        RpcReplyPort<Result<ScrapeReport, GithubError>>,
+    ),
+    /// Scrapes the default branch of every repository in an organisation that passes the filter.
+    ScrapeOrg(
+        String,
+        OrgFilter,
+        FileFilter,
+        Vec<RepoManifest>,
+        RpcReplyPort<Result<ScrapeReport, GithubError>>,
+    ),
+    /// Reads a local checkout, bare clone or plain git remote from disk instead of the API.
+    ScrapeGit(
+        String,
+        FileFilter,
+        Option<RepoManifest>,
+        RpcReplyPort<Result<ScrapeReport, GithubError>>,
+    ),
+    /// Fetches issues, pull requests and discussions of a repository with their comments.
+    ScrapeThreads(
+        String,
+        String,
+        ThreadOptions,
+        RpcReplyPort<Result<Vec<GitHubThread>, GithubError>>,
+    ),
+}
+
+impl ractor::Message for GithubScraperMessage {}
+
+pub struct GithubScraperState {
+    github: Github,
+    threads: ThreadClient,
+}
+
+pub struct GithubScraperActor;
+
+impl GithubScraperActor {
+    async fn download(
+        repo: &Repository,
+        path: &str,
+        reference: &str,
+    ) -> Result<Vec<u8>, GithubError> {
+        debug!("downloading file {}", path);
+
+        let file = repo.content().file(&format!("/{path}"), reference).await?;
+        let bytes = reqwest::get(&file.download_url)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec();
+
+        Ok(bytes)
+    }
+
+    async fn fetch_github_file_contents(
+        repo: &Repository,
+        info: &GitHubRepo,
+        path: &str,
+        commit: &str,
+        max_file_size: u64,
+    ) -> Result<Result<GitHubFile, SkipReason>, GithubError> {
+        let bytes = Self::download(repo, path, commit).await?;
+        Ok(decode_file(bytes, max_file_size)
+            .map(|content| GitHubFile::new(info, path, info.file_url(path), "github", content)))
+    }
+
+    async fn fetch_all_github_contents(
+        repo: (&str, &str),
+        branch: &str,
+        filter: &FileFilter,
+        previous: Option<&RepoManifest>,
+        state: &GithubScraperState,
+    ) -> Result<ScrapeReport, GithubError> {
+        let (owner, name) = repo;
+        let repo = state.github.repo(owner, name);
+        let mut branch = branch;
+        if branch == "default" {
+            if repo.branches().get("master").await.is_ok() {
+                branch = "master";
+            }
+
+            if repo.branches().get("main").await.is_ok() {
+                branch = "main";
+            }
+        }
+
+        if branch == "default" {
+            return Err(GithubError::NotFound(format!(
+                "default branch of {}/{}",
+                owner, name
+            )));
+        }
+
+        let commit = repo.branches().get(branch).await?.commit.sha;
+        let info = GitHubRepo {
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+            branch: branch.to_owned(),
+        };
+        // a manifest of another branch has nothing in common with this one
+        let previous = previous.filter(|manifest| manifest.branch == branch);
+        let (previous, refiltered) = refiltered(previous, filter);
+
+        let mut report = ScrapeReport::default();
+        let mut diff = RepoDiff {
+            repo: format!("{owner}/{name}"),
+            from: previous.map(|manifest| manifest.commit.clone()),
+            to: commit.clone(),
+            added: 0,
+            changed: 0,
+            removed: 0,
+            unchanged: 0,
+            stale_sources: Vec::new(),
+        };
+
+        if let Some(previous) = previous.filter(|manifest| manifest.commit == commit) {
+            debug!("{}/{} is up to date at {}", owner, name, commit);
+            diff.unchanged = previous.files.len();
+            report.diffs.push(diff);
+            report.manifests.push(previous.clone());
+            return Ok(report);
+        }
+
+        let tree = repo.git().tree(commit.as_str(), true).await?;
+        if tree.truncated {
+            warn!(
+                "Tree of {}/{} is too large, only {} entries were listed",
+                owner,
+                name,
+                tree.tree.len()
+            );
+        }
+
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        let mut blobs: HashMap<String, String> = HashMap::new();
+        for object in tree.tree {
+            if object.content_type == "blob" {
+                sizes.insert(object.path.clone(), object.size.unwrap_or(0) as u64);
+                blobs.insert(object.path, object.sha);
+            }
+        }
+        debug!("Found {} files", blobs.len());
+
+        let tree_diff = diff_tree(previous, &blobs);
+        diff.added = tree_diff.added.len();
+        diff.changed = tree_diff.changed.len();
+        diff.removed = tree_diff.removed.len();
+        diff.unchanged = tree_diff.unchanged;
+        diff.stale_sources = tree_diff
+            .removed
+            .iter()
+            .map(|path| info.file_url(path))
+            .collect();
+
+        let gitattributes = match blobs.get(".gitattributes") {
+            Some(_) if filter.default_ignores => {
+                match Self::download(&repo, ".gitattributes", &commit).await {
+                    Ok(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+                    Err(e) => {
+                        warn!("Failed to read .gitattributes: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let matcher = filter.matcher(gitattributes.as_deref())?;
+
+        let mut manifest = RepoManifest {
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+            branch: branch.to_owned(),
+            commit: commit.clone(),
+            filter: filter.fingerprint(),
+            files: blobs
+                .iter()
+                .filter(|(path, sha)| {
+                    previous.and_then(|manifest| manifest.files.get(*path)) == Some(*sha)
+                })
+                .map(|(path, sha)| (path.clone(), sha.clone()))
+                .collect(),
+        };
+
+        let mut tasks = vec![];
+
+        for file_path in tree_diff.added.iter().chain(&tree_diff.changed) {
+            if let Some(reason) = matcher.check(file_path, sizes[file_path]) {
+                manifest
+                    .files
+                    .insert(file_path.clone(), blobs[file_path].clone());
+                report.skip(file_path, reason);
+                continue;
+            }
+
+            let repo_arc = Arc::new(&repo);
+            let info = &info;
+            let commit = &commit;
+            let task = async move {
+                let result = Self::fetch_github_file_contents(
+                    &repo_arc,
+                    info,
+                    file_path,
+                    commit,
+                    filter.max_file_size,
+                )
+                .await;
+                (file_path, result)
+            };
+            tasks.push(task);
+        }
+
+        let results: Vec<_> = stream::iter(tasks)
+            .buffer_unordered(MAX_CONCURRENT_FILES)
+            .collect()
+            .await;
+
+        for (file_path, result) in results {
+            match result {
+                Ok(Ok(file)) => {
+                    manifest
+                        .files
+                        .insert(file_path.clone(), blobs[file_path].clone());
+                    report.files.push(file);
+                }
+                Ok(Err(reason)) => {
+                    manifest
+                        .files
+                        .insert(file_path.clone(), blobs[file_path].clone());
+                    report.skip(file_path, reason);
+                }
+                // the remaining files would fail the same way
+                Err(e @ (GithubError::Auth | GithubError::RateLimited)) => return Err(e),
+                // not recorded in the manifest so the next sync retries it
+                Err(e) => report.skip(file_path, SkipReason::Failed(e.to_string())),
+            }
+        }
+
+        // changed files that are now skipped should not keep their old content around
+        let fetched: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+        diff.stale_sources.extend(
+            tree_diff
+                .changed
+                .iter()
+                .chain(
+                    refiltered
+                        .into_iter()
+                        .flat_map(|manifest| manifest.files.keys()),
+                )
+                .filter(|path| !fetched.contains(&path.as_str()))
+                .map(|path| info.file_url(path)),
+        );
+
+        report.diffs.push(diff);
+        report.manifests.push(manifest);
+        Ok(report)
+    }
+
+    async fn fetch_org_contents(
+        org: &str,
+        filter: &OrgFilter,
+        file_filter: &FileFilter,
+        previous: &[RepoManifest],
+        state: &GithubScraperState,
+    ) -> Result<ScrapeReport, GithubError> {
+        let matcher = filter.matcher()?;
+        let repos: Vec<Repo> = state
+            .github
+            .org_repos(org)
+            .iter(&OrgRepoListOptions::default())
+            .try_collect()
+            .await
+            .map_err(|e| match GithubError::from(e) {
+                GithubError::NotFound(_) => GithubError::NotFound(format!("organisation {org}")),
+                e => e,
+            })?;
+
+        let repos: Vec<Repo> = repos
+            .into_iter()
+            .filter(|repo| {
+                matcher.matches(
+                    &repo.name,
+                    repo.archived,
+                    repo.fork,
+                    repo.language.as_deref(),
+                )
+            })
+            .collect();
+        info!("Scraping {} repositories of {}", repos.len(), org);
+
+        let results: Vec<(String, Result<ScrapeReport, GithubError>)> = stream::iter(repos)
+            .map(|repo| async move {
+                let manifest = previous
+                    .iter()
+                    .find(|manifest| manifest.owner == org && manifest.name == repo.name);
+                let report = Self::fetch_all_github_contents(
+                    (org, &repo.name),
+                    &repo.default_branch,
+                    file_filter,
+                    manifest,
+                    state,
+                )
+                .await;
+                (repo.name, report)
+            })
+            .buffer_unordered(MAX_CONCURRENT_REPOS)
+            .collect()
+            .await;
+
+        let mut report = ScrapeReport::default();
+        for (name, result) in results {
+            match result {
+                Ok(repo_report) => report.merge(repo_report),
+                // the remaining repositories would fail the same way
+                Err(e @ (GithubError::Auth | GithubError::RateLimited)) => return Err(e),
+                Err(e) => warn!("Skipping {}/{}: {}", org, name, e),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl Actor for GithubScraperActor {
+    type Msg = GithubScraperMessage;
+    type State = GithubScraperState;
+    type Arguments = String;
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        args: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let github = Github::new("github.com", Credentials::Token(args.clone()))?;
+        let threads = ThreadClient::new(GITHUB_API_URL, args);
+
+        Ok(GithubScraperState { github, threads })
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        msg: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match msg {
+            GithubScraperMessage::ScrapeRepo(owner, repo, branch, filter, previous, port) => {
+                info!("Scraping {}/{} on branch {}", owner, repo, branch);
+                let path = (owner.as_str(), repo.as_str());
+                let report = Self::fetch_all_github_contents(
+                    path,
+                    &branch,
+                    &filter,
+                    previous.as_ref(),
+                    state,
+                )
+                .await;
+                match &report {
+                    Ok(report) => debug!(
+                        "Collected {} files from {}/{} on branch {}, skipped {}",
+                        report.files.len(),
+                        owner,
+                        repo,
+                        branch,
+                        report.skipped.len()
+                    ),
+                    Err(e) => warn!("Failed to scrape {}/{}: {}", owner, repo, e),
+                }
+                let _ = port.send(report);
+            }
+            GithubScraperMessage::ScrapeOrg(org, filter, file_filter, previous, port) => {
+                info!("Scraping organisation {} with {:?}", org, filter);
+                let report =
+                    Self::fetch_org_contents(&org, &filter, &file_filter, &previous, state).await;
+                match &report {
+                    Ok(report) => debug!(
+                        "Collected {} files from {}, skipped {}",
+                        report.files.len(),
+                        org,
+                        report.skipped.len()
+                    ),
+                    Err(e) => warn!("Failed to scrape organisation {}: {}", org, e),
+                }
+                let _ = port.send(report);
+            }
+            GithubScraperMessage::ScrapeGit(source, filter, previous, port) => {
+                info!("Scraping {} with git", source);
+                let task_source = source.clone();
+                let report = tokio::task::spawn_blocking(move || {
+                    local::scrape(&task_source, &filter, previous.as_ref())
+                })
+                .await
+                .unwrap_or_else(|e| Err(GithubError::Git(e.to_string())));
+                match &report {
+                    Ok(report) => debug!(
+                        "Collected {} files from {}, skipped {}",
+                        report.files.len(),
+                        source,
+                        report.skipped.len()
+                    ),
+                    Err(e) => warn!("Failed to scrape {}: {}", source, e),
+                }
+                let _ = port.send(report);
+            }
+            GithubScraperMessage::ScrapeThreads(owner, repo, options, port) => {
+                info!("Scraping threads of {}/{}", owner, repo);
+                let threads = state.threads.threads(&owner, &repo, &options).await;
+                if let Err(e) = &threads {
+                    warn!("Failed to scrape threads of {}/{}: {}", owner, repo, e);
+                }
+                let _ = port.send(threads);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_url() {
+        assert_eq!(
+            parse_github_url("https://github.com/alvariumhex/andrena").unwrap(),
+            GithubTarget::Repo("alvariumhex".to_owned(), "andrena".to_owned())
+        );
+        assert_eq!(
+            parse_github_url("https://github.com/guillaume-be/rust-bert.git/tree/master").unwrap(),
+            GithubTarget::Repo("guillaume-be".to_owned(), "rust-bert".to_owned())
+        );
+        assert_eq!(
+            parse_github_url("https://github.com/rust-lang").unwrap(),
+            GithubTarget::Org("rust-lang".to_owned())
+        );
+        assert_eq!(
+            parse_github_url("https://github.com/orgs/rust-lang/repositories").unwrap(),
+            GithubTarget::Org("rust-lang".to_owned())
+        );
+
+        assert!(matches!(
+            parse_github_url("https://gitlab.com/owner"),
+            Err(GithubError::UnsupportedUrl(_))
+        ));
+        assert_eq!(
+            parse_github_url("https://gitlab.com/owner/repo.git").unwrap(),
+            GithubTarget::Git("https://gitlab.com/owner/repo.git".to_owned())
+        );
+        // outside LOCAL_REPO_ROOT or missing, whichever way the tests set it
+        assert!(parse_github_url("/srv/repos/andrena").is_err());
+    }
+
+    #[test]
+    fn org_filter() {
+        let matcher = OrgFilter::default().matcher().unwrap();
+        assert!(matcher.matches("api", false, false, None));
+        assert!(!matcher.matches("api", true, false, None));
+        assert!(!matcher.matches("api", false, true, None));
+
+        let filter =
+            parse_scrape_args("--forks --include=api-* --exclude=*-legacy --language=Rust")
+                .unwrap()
+                .org;
+        let matcher = filter.matcher().unwrap();
+        assert!(matcher.matches("api-gateway", false, true, Some("rust")));
+        assert!(!matcher.matches("api-legacy", false, false, Some("Rust")));
+        assert!(!matcher.matches("web", false, false, Some("Rust")));
+        assert!(!matcher.matches("api-client", false, false, Some("Go")));
+        assert!(!matcher.matches("api-client", false, false, None));
+
+        assert!(matches!(
+            parse_scrape_args("--everything"),
+            Err(GithubError::InvalidFilter(_))
+        ));
+
+        let args = parse_scrape_args("--threads").unwrap();
+        assert_eq!(args.threads.unwrap().max_threads, 200);
+        let args = parse_scrape_args("--max-threads=20").unwrap();
+        assert_eq!(args.threads.unwrap().max_threads, 20);
+        assert!(parse_scrape_args("").unwrap().threads.is_none());
+    }
+
+    #[test]
+    fn file_filter() {
+        let matcher = FileFilter::default().matcher(None).unwrap();
+        assert_eq!(matcher.check("src/main.rs", 100), None);
+        assert_eq!(matcher.check("Cargo.lock", 100), Some(SkipReason::Ignored));
+        assert_eq!(
+            matcher.check("web/node_modules/react/index.js", 100),
+            Some(SkipReason::Ignored)
+        );
+        assert_eq!(
+            matcher.check("docs/logo.png", 100),
+            Some(SkipReason::Ignored)
+        );
+        assert_eq!(
+            matcher.check("src/main.rs", DEFAULT_MAX_FILE_SIZE + 1),
+            Some(SkipReason::TooLarge(DEFAULT_MAX_FILE_SIZE + 1))
+        );
+
+        let filter = parse_scrape_args("--path=src/** --skip=**/tests/** --max-size=10 --clone")
+            .unwrap()
+            .files;
+        let matcher = filter.matcher(None).unwrap();
+        assert_eq!(matcher.check("src/lib.rs", 10), None);
+        assert_eq!(matcher.check("README.md", 10), Some(SkipReason::Excluded));
+        assert_eq!(
+            matcher.check("src/tests/lib.rs", 10),
+            Some(SkipReason::Excluded)
+        );
+        assert_eq!(
+            matcher.check("src/lib.rs", 11),
+            Some(SkipReason::TooLarge(11))
+        );
+    }
+
+    #[test]
+    fn gitattributes() {
+        let attributes = parse_gitattributes(
+            "# linguist overrides\n\
+             /static/ linguist-vendored\n\
+             *.pb.go linguist-generated=true text\n\
+             vendor/ours/** -linguist-vendored\n",
+        );
+        assert_eq!(
+            attributes,
+            LinguistAttributes {
+                vendored: vec!["static/**".to_owned()],
+                generated: vec!["**/*.pb.go".to_owned()],
+                not_vendored: vec!["vendor/ours/**".to_owned()],
+            }
+        );
+
+        let matcher = FileFilter::default()
+            .matcher(Some(
+                "/static/ linguist-vendored\n*.pb.go linguist-generated\nvendor/ours/** -linguist-vendored",
+            ))
+            .unwrap();
+        assert_eq!(
+            matcher.check("static/app.js", 10),
+            Some(SkipReason::Vendored)
+        );
+        assert_eq!(
+            matcher.check("api/v1/user.pb.go", 10),
+            Some(SkipReason::Generated)
+        );
+        assert_eq!(matcher.check("vendor/ours/lib.go", 10), None);
+        assert_eq!(
+            matcher.check("vendor/theirs/lib.go", 10),
+            Some(SkipReason::Ignored)
+        );
+
+        let matcher = FileFilter {
+            default_ignores: false,
+            ..FileFilter::default()
+        }
+        .matcher(Some("*.pb.go linguist-generated"))
+        .unwrap();
+        assert_eq!(matcher.check("api/v1/user.pb.go", 10), None);
+    }
+
+    #[test]
+    fn tree_diff() {
+        let previous = RepoManifest {
+            owner: "alvariumhex".to_owned(),
+            name: "andrena".to_owned(),
+            branch: "main".to_owned(),
+            commit: "1111111".to_owned(),
+            filter: FileFilter::default().fingerprint(),
+            files: HashMap::from([
+                ("src/main.rs".to_owned(), "a".to_owned()),
+                ("src/graph.rs".to_owned(), "b".to_owned()),
+                ("README.md".to_owned(), "c".to_owned()),
+            ]),
+        };
+        let blobs = HashMap::from([
+            ("src/main.rs".to_owned(), "a".to_owned()),
+            ("src/graph.rs".to_owned(), "d".to_owned()),
+            ("src/store.rs".to_owned(), "e".to_owned()),
+        ]);
+
+        assert_eq!(
+            diff_tree(Some(&previous), &blobs),
+            TreeDiff {
+                added: vec!["src/store.rs".to_owned()],
+                changed: vec!["src/graph.rs".to_owned()],
+                removed: vec!["README.md".to_owned()],
+                unchanged: 1,
+            }
+        );
+        assert_eq!(diff_tree(None, &blobs).added.len(), 3);
+
+        let diff = RepoDiff {
+            repo: "alvariumhex/andrena".to_owned(),
+            from: Some(previous.commit.clone()),
+            to: "2222222222".to_owned(),
+            added: 1,
+            changed: 1,
+            removed: 1,
+            unchanged: 1,
+            stale_sources: vec![],
+        };
+        assert_eq!(
+            diff.to_string(),
+            "alvariumhex/andrena 1111111..2222222: 1 added, 1 changed, 1 removed, 1 unchanged"
+        );
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert!(!is_binary("fn main() {}".as_bytes()));
+    }
+}
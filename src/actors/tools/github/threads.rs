@@ -0,0 +1,754 @@
+use std::collections::HashMap;
+
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+use crate::graph::Graph;
+
+use super::{
+    super::embeddings::{Embeddable, Embedding},
+    GitHubRepo, GithubError,
+};
+
+/// Root of the REST and GraphQL APIs, see [`ThreadClient::new`].
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+const PER_PAGE: usize = 100;
+
+/// `fixes #12`, `Closes #7` or a plain mention like `see #3`.
+static REFERENCE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(?:\b(close[sd]?|fix(?:e[sd])?|resolve[sd]?):?\s+)?#(\d+)\b").unwrap()
+});
+
+const DISCUSSIONS_QUERY: &str = r#"
+query($owner: String!, $name: String!, $first: Int!, $after: String) {
+  repository(owner: $owner, name: $name) {
+    discussions(first: $first, after: $after, orderBy: {field: UPDATED_AT, direction: DESC}) {
+      pageInfo { hasNextPage endCursor }
+      nodes {
+        number title url body closed
+        author { login }
+        labels(first: 20) { nodes { name } }
+        comments(first: 50) { nodes { body author { login } } }
+      }
+    }
+  }
+}"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadKind {
+    Issue,
+    PullRequest,
+    Discussion,
+}
+
+impl ThreadKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ThreadKind::Issue => "Issue",
+            ThreadKind::PullRequest => "Pull request",
+            ThreadKind::Discussion => "Discussion",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadOptions {
+    pub issues: bool,
+    pub pull_requests: bool,
+    pub discussions: bool,
+    /// Most recently updated threads to fetch per kind.
+    pub max_threads: usize,
+}
+
+impl Default for ThreadOptions {
+    fn default() -> Self {
+        ThreadOptions {
+            issues: true,
+            pull_requests: true,
+            discussions: true,
+            max_threads: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadComment {
+    pub author: String,
+    pub body: String,
+    /// File a pull request review comment was left on.
+    pub path: Option<String>,
+}
+
+/// An issue, pull request or discussion together with its conversation.
+#[derive(Debug, Clone)]
+pub struct GitHubThread {
+    pub kind: ThreadKind,
+    pub number: u64,
+    pub title: String,
+    /// Browser url of the thread, also its graph vertex.
+    pub url: String,
+    pub author: String,
+    pub state: String,
+    pub labels: Vec<String>,
+    pub body: String,
+    pub comments: Vec<ThreadComment>,
+    /// Urls of the threads this one closes with `fixes #12` and friends.
+    pub closes: Vec<String>,
+    /// Urls of the other threads mentioned with `#12`.
+    pub mentions: Vec<String>,
+    /// Repository relative paths changed by a pull request.
+    pub files: Vec<String>,
+    pub repo: GitHubRepo,
+}
+
+impl Embeddable for GitHubThread {
+    fn human_readable_source(&self) -> String {
+        self.url.clone()
+    }
+
+    fn short_description(&self) -> String {
+        format!("{} #{}: {}", self.kind.name(), self.number, self.title)
+    }
+
+    fn long_description(&self) -> String {
+        let mut description = format!("{} ({})", self.short_description(), self.state);
+        if !self.labels.is_empty() {
+            description.push_str(&format!("\nlabels: {}", self.labels.join(", ")));
+        }
+        description.push_str(&format!("\n\n{}: {}", self.author, self.body));
+        for comment in &self.comments {
+            match &comment.path {
+                Some(path) => description.push_str(&format!(
+                    "\n\n{} on {}: {}",
+                    comment.author, path, comment.body
+                )),
+                None => description.push_str(&format!("\n\n{}: {}", comment.author, comment.body)),
+            }
+        }
+        description
+    }
+
+    fn get_chunks(&self, size: usize) -> Vec<String> {
+        let header = format!(
+            "{}\nsource: {}\n",
+            self.short_description(),
+            self.human_readable_source()
+        );
+        self.long_description()
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .chunks(size)
+            .map(|chunk| format!("{}{}", header, chunk.join(" ")))
+            .collect::<Vec<String>>()
+    }
+}
+
+impl GitHubThread {
+    pub fn embeddings(&self, size: usize) -> Vec<Embedding> {
+        self.get_chunks(size)
+            .into_iter()
+            .map(|content| Embedding {
+                content,
+                vector: vec![],
+                graph_vertex: self.url.clone(),
+            })
+            .collect()
+    }
+
+    pub fn add_to_graph(&self, graph: &mut Graph) {
+        for issue in &self.closes {
+            graph.add_edge(self.url.clone(), "closes".to_owned(), issue.clone());
+        }
+
+        for thread in &self.mentions {
+            graph.add_edge(self.url.clone(), "mentions".to_owned(), thread.clone());
+        }
+
+        for path in &self.files {
+            graph.add_edge(
+                self.url.clone(),
+                "changes".to_owned(),
+                self.repo.file_url(path),
+            );
+        }
+
+        let mut metadata = HashMap::new();
+        metadata.insert("kind".to_owned(), self.kind.name().to_owned());
+        metadata.insert("number".to_owned(), self.number.to_string());
+        metadata.insert("title".to_owned(), self.title.clone());
+        metadata.insert("state".to_owned(), self.state.clone());
+        metadata.insert("author".to_owned(), self.author.clone());
+        metadata.insert("labels".to_owned(), self.labels.join(", "));
+        metadata.insert(
+            "repo".to_owned(),
+            format!("{}/{}", self.repo.owner, self.repo.name),
+        );
+        metadata.insert("content".to_owned(), self.body.clone());
+
+        graph.add_or_replace_vertex(self.url.clone(), metadata);
+    }
+
+    /// Fills `closes` and `mentions` from `#12` references in the conversation, resolving the
+    /// numbers against the fetched threads so pull requests link to `/pull/12`.
+    fn resolve_references(&mut self, urls: &HashMap<u64, String>) {
+        let text = std::iter::once(&self.body).chain(self.comments.iter().map(|c| &c.body));
+        for cap in text.flat_map(|text| REFERENCE.captures_iter(text)) {
+            let Ok(number) = cap[2].parse::<u64>() else {
+                continue;
+            };
+            if number == self.number {
+                continue;
+            }
+
+            let url = urls.get(&number).cloned().unwrap_or(format!(
+                "https://github.com/{}/{}/issues/{}",
+                self.repo.owner, self.repo.name, number
+            ));
+            let references = match cap.get(1) {
+                Some(_) => &mut self.closes,
+                None => &mut self.mentions,
+            };
+            if !references.contains(&url) {
+                references.push(url);
+            }
+        }
+        self.mentions.retain(|url| !self.closes.contains(url));
+    }
+}
+
+#[derive(Deserialize)]
+struct RepoResponse {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct UserResponse {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct LabelResponse {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct IssueResponse {
+    number: u64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    html_url: String,
+    user: Option<UserResponse>,
+    #[serde(default)]
+    labels: Vec<LabelResponse>,
+    comments: u64,
+    /// Only present when the issue is a pull request.
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct CommentResponse {
+    body: Option<String>,
+    user: Option<UserResponse>,
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PullFileResponse {
+    filename: String,
+}
+
+#[derive(Deserialize)]
+struct GraphResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphErrorResponse>,
+}
+
+#[derive(Deserialize)]
+struct GraphErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct DiscussionsData {
+    repository: Option<DiscussionsRepository>,
+}
+
+#[derive(Deserialize)]
+struct DiscussionsRepository {
+    discussions: Connection<DiscussionNode>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection<T> {
+    page_info: Option<PageInfo>,
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiscussionNode {
+    number: u64,
+    title: String,
+    url: String,
+    body: String,
+    closed: bool,
+    author: Option<UserResponse>,
+    labels: Option<Connection<LabelResponse>>,
+    comments: Connection<DiscussionCommentNode>,
+}
+
+#[derive(Deserialize)]
+struct DiscussionCommentNode {
+    body: String,
+    author: Option<UserResponse>,
+}
+
+fn login(user: Option<UserResponse>) -> String {
+    user.map_or("ghost".to_owned(), |u| u.login)
+}
+
+/// Reads issue and pull request threads over REST and discussions over GraphQL, which hubcaps
+/// does not cover.
+pub struct ThreadClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl ThreadClient {
+    pub fn new(base_url: &str, token: String) -> ThreadClient {
+        ThreadClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            token,
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        what: &str,
+    ) -> Result<T, GithubError> {
+        let response = request
+            .bearer_auth(&self.token)
+            .header("User-Agent", "andrena")
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await?;
+
+        let exhausted = response
+            .headers()
+            .get("x-ratelimit-remaining")
+            .map_or(false, |remaining| remaining == "0");
+        match response.status().as_u16() {
+            403 | 429 if exhausted => return Err(GithubError::RateLimited),
+            401 | 403 => return Err(GithubError::Auth),
+            404 | 410 => return Err(GithubError::NotFound(what.to_owned())),
+            _ => {}
+        }
+
+        Ok(response.error_for_status()?.json::<T>().await?)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, GithubError> {
+        let request = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query);
+        self.send(request, path).await
+    }
+
+    /// Fetches pages until one comes back short or `limit` items were collected.
+    async fn get_all<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+        limit: usize,
+    ) -> Result<Vec<T>, GithubError> {
+        let mut results = Vec::new();
+        for page in 1.. {
+            let mut page_query = query.to_vec();
+            page_query.push(("per_page", PER_PAGE.to_string()));
+            page_query.push(("page", page.to_string()));
+
+            let items: Vec<T> = self.get(path, &page_query).await?;
+            let last = items.len() < PER_PAGE;
+            results.extend(items);
+            if last || results.len() >= limit {
+                break;
+            }
+        }
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    async fn comments(&self, path: String) -> Result<Vec<ThreadComment>, GithubError> {
+        let comments: Vec<CommentResponse> = self.get_all(&path, &[], usize::MAX).await?;
+        Ok(comments
+            .into_iter()
+            .map(|c| ThreadComment {
+                author: login(c.user),
+                body: c.body.unwrap_or_default(),
+                path: c.path,
+            })
+            .collect())
+    }
+
+    async fn discussions(
+        &self,
+        repo: &GitHubRepo,
+        limit: usize,
+    ) -> Result<Vec<GitHubThread>, GithubError> {
+        let mut threads = Vec::new();
+        let mut after: Option<String> = None;
+        while threads.len() < limit {
+            let body = json!({
+                "query": DISCUSSIONS_QUERY,
+                "variables": {
+                    "owner": repo.owner,
+                    "name": repo.name,
+                    "first": (limit - threads.len()).min(PER_PAGE),
+                    "after": after,
+                },
+            });
+            let request = self
+                .http
+                .post(format!("{}/graphql", self.base_url))
+                .json(&body);
+            let response: GraphResponse<DiscussionsData> =
+                self.send(request, "discussions").await?;
+            if let Some(error) = response.errors.first() {
+                return Err(GithubError::Network(error.message.clone()));
+            }
+            let Some(discussions) = response
+                .data
+                .and_then(|data| data.repository)
+                .map(|repository| repository.discussions)
+            else {
+                return Err(GithubError::NotFound(format!(
+                    "{}/{}",
+                    repo.owner, repo.name
+                )));
+            };
+
+            threads.extend(discussions.nodes.into_iter().map(|node| {
+                GitHubThread {
+                    kind: ThreadKind::Discussion,
+                    number: node.number,
+                    title: node.title,
+                    url: node.url,
+                    author: login(node.author),
+                    state: match node.closed {
+                        true => "closed".to_owned(),
+                        false => "open".to_owned(),
+                    },
+                    labels: node
+                        .labels
+                        .map(|labels| labels.nodes.into_iter().map(|l| l.name).collect())
+                        .unwrap_or_default(),
+                    body: node.body,
+                    comments: node
+                        .comments
+                        .nodes
+                        .into_iter()
+                        .map(|c| ThreadComment {
+                            author: login(c.author),
+                            body: c.body,
+                            path: None,
+                        })
+                        .collect(),
+                    closes: Vec::new(),
+                    mentions: Vec::new(),
+                    files: Vec::new(),
+                    repo: repo.clone(),
+                }
+            }));
+
+            match discussions.page_info {
+                Some(page) if page.has_next_page => after = page.end_cursor,
+                _ => break,
+            }
+        }
+        Ok(threads)
+    }
+
+    /// Fetches the most recently updated threads of a repository with their comments, labels
+    /// and, for pull requests, the files they change.
+    pub async fn threads(
+        &self,
+        owner: &str,
+        name: &str,
+        options: &ThreadOptions,
+    ) -> Result<Vec<GitHubThread>, GithubError> {
+        let base = format!("/repos/{owner}/{name}");
+        let info: RepoResponse = self.get(&base, &[]).await?;
+        let repo = GitHubRepo {
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+            branch: info.default_branch,
+        };
+
+        let mut threads = Vec::new();
+        if options.issues || options.pull_requests {
+            // the issues endpoint lists pull requests as well
+            let issues: Vec<IssueResponse> = self
+                .get_all(
+                    &format!("{base}/issues"),
+                    &[
+                        ("state", "all".to_owned()),
+                        ("sort", "updated".to_owned()),
+                        ("direction", "desc".to_owned()),
+                    ],
+                    options.max_threads * 2,
+                )
+                .await?;
+
+            let mut counts = HashMap::new();
+            for issue in issues {
+                let kind = match issue.pull_request {
+                    Some(_) => ThreadKind::PullRequest,
+                    None => ThreadKind::Issue,
+                };
+                let wanted = match kind {
+                    ThreadKind::PullRequest => options.pull_requests,
+                    _ => options.issues,
+                };
+                let count = counts.entry(kind.name()).or_insert(0);
+                if !wanted || *count >= options.max_threads {
+                    continue;
+                }
+                *count += 1;
+
+                let mut comments = match issue.comments {
+                    0 => Vec::new(),
+                    _ => {
+                        self.comments(format!("{base}/issues/{}/comments", issue.number))
+                            .await?
+                    }
+                };
+                let mut files = Vec::new();
+                if kind == ThreadKind::PullRequest {
+                    comments.extend(
+                        self.comments(format!("{base}/pulls/{}/comments", issue.number))
+                            .await?,
+                    );
+                    let changed: Vec<PullFileResponse> = self
+                        .get_all(
+                            &format!("{base}/pulls/{}/files", issue.number),
+                            &[],
+                            usize::MAX,
+                        )
+                        .await?;
+                    files = changed.into_iter().map(|f| f.filename).collect();
+                }
+
+                threads.push(GitHubThread {
+                    kind,
+                    number: issue.number,
+                    title: issue.title,
+                    url: issue.html_url,
+                    author: login(issue.user),
+                    state: issue.state,
+                    labels: issue.labels.into_iter().map(|l| l.name).collect(),
+                    body: issue.body.unwrap_or_default(),
+                    comments,
+                    closes: Vec::new(),
+                    mentions: Vec::new(),
+                    files,
+                    repo: repo.clone(),
+                });
+            }
+        }
+
+        if options.discussions {
+            match self.discussions(&repo, options.max_threads).await {
+                Ok(discussions) => threads.extend(discussions),
+                // discussions are often disabled or need a token with more scopes
+                Err(e @ GithubError::RateLimited) => return Err(e),
+                Err(e) => debug!("Skipping discussions of {}/{}: {}", owner, name, e),
+            }
+        }
+
+        let urls: HashMap<u64, String> = threads
+            .iter()
+            .map(|thread| (thread.number, thread.url.clone()))
+            .collect();
+        for thread in &mut threads {
+            thread.resolve_references(&urls);
+        }
+
+        info!("Fetched {} threads from {}/{}", threads.len(), owner, name);
+        Ok(threads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    async fn mock_github() -> MockServer {
+        let server = MockServer::start().await;
+        let get = |route: &str, body: serde_json::Value| {
+            Mock::given(method("GET"))
+                .and(path(route.to_owned()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        };
+
+        get("/repos/o/r", json!({"default_branch": "main"}))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/repos/o/r/issues"))
+            .and(query_param("state", "all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {
+                    "number": 2,
+                    "title": "Retry failed uploads",
+                    "body": "Fixes #1, see also #3",
+                    "state": "closed",
+                    "html_url": "https://github.com/o/r/pull/2",
+                    "user": {"login": "dev"},
+                    "labels": [{"name": "bug"}],
+                    "comments": 0,
+                    "pull_request": {"url": "https://api.github.com/repos/o/r/pulls/2"}
+                },
+                {
+                    "number": 1,
+                    "title": "Uploads fail on flaky networks",
+                    "body": null,
+                    "state": "open",
+                    "html_url": "https://github.com/o/r/issues/1",
+                    "user": {"login": "reporter"},
+                    "labels": [],
+                    "comments": 1
+                }
+            ])))
+            .mount(&server)
+            .await;
+        get(
+            "/repos/o/r/issues/1/comments",
+            json!([{"body": "Happens on every train ride", "user": {"login": "reporter"}}]),
+        )
+        .mount(&server)
+        .await;
+        get(
+            "/repos/o/r/pulls/2/comments",
+            json!([{"body": "Why three retries?", "user": {"login": "reviewer"}, "path": "src/upload.rs"}]),
+        )
+        .mount(&server)
+        .await;
+        get(
+            "/repos/o/r/pulls/2/files",
+            json!([{"filename": "src/upload.rs"}]),
+        )
+        .mount(&server)
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": {"repository": {"discussions": {
+                    "pageInfo": {"hasNextPage": false, "endCursor": null},
+                    "nodes": [{
+                        "number": 3,
+                        "title": "Upload strategy",
+                        "url": "https://github.com/o/r/discussions/3",
+                        "body": "Should we retry at all?",
+                        "closed": false,
+                        "author": {"login": "lead"},
+                        "labels": {"nodes": []},
+                        "comments": {"nodes": [{"body": "Yes, see #2", "author": null}]}
+                    }]
+                }}}
+            })))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn fetch_threads() {
+        let server = mock_github().await;
+        let client = ThreadClient::new(&server.uri(), "token".to_owned());
+
+        let threads = client
+            .threads("o", "r", &ThreadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(threads.len(), 3);
+
+        let pull = &threads[0];
+        assert_eq!(pull.kind, ThreadKind::PullRequest);
+        assert_eq!(pull.closes, vec!["https://github.com/o/r/issues/1"]);
+        assert_eq!(pull.mentions, vec!["https://github.com/o/r/discussions/3"]);
+        assert_eq!(pull.files, vec!["src/upload.rs"]);
+        assert_eq!(pull.comments[0].path.as_deref(), Some("src/upload.rs"));
+
+        let issue = &threads[1];
+        assert_eq!(issue.kind, ThreadKind::Issue);
+        assert_eq!(issue.comments.len(), 1);
+
+        let discussion = &threads[2];
+        assert_eq!(discussion.kind, ThreadKind::Discussion);
+        assert_eq!(discussion.comments[0].author, "ghost");
+        assert_eq!(discussion.mentions, vec!["https://github.com/o/r/pull/2"]);
+
+        let embeddings = pull.embeddings(300);
+        assert_eq!(embeddings.len(), 1);
+        assert_eq!(embeddings[0].graph_vertex, pull.url);
+        assert!(embeddings[0]
+            .content
+            .starts_with("Pull request #2: Retry failed uploads"));
+        assert!(embeddings[0]
+            .content
+            .contains("reviewer on src/upload.rs: Why three retries?"));
+
+        let mut graph = Graph::new();
+        for thread in &threads {
+            thread.add_to_graph(&mut graph);
+        }
+        assert_eq!(graph.vertices.len(), 3);
+        let edges = graph.get_edges_from(&pull.url);
+        assert_eq!(edges.len(), 3);
+        assert!(edges
+            .iter()
+            .any(|e| e.relation == "changes"
+                && e.to == "https://github.com/o/r/blob/main/src/upload.rs"));
+        assert_eq!(graph.get_edges_to(&issue.url)[0].relation, "closes");
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(403).insert_header("x-ratelimit-remaining", "0"))
+            .mount(&server)
+            .await;
+        let client = ThreadClient::new(&server.uri(), "token".to_owned());
+
+        let result = client.threads("o", "r", &ThreadOptions::default()).await;
+        assert!(matches!(result, Err(GithubError::RateLimited)));
+    }
+}
@@ -1,3 +1,4 @@
+pub mod confluence;
 pub mod embeddings;
 pub mod github;
 pub mod google_query;
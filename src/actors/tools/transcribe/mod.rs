@@ -0,0 +1,774 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    process::Command,
+    sync::Arc,
+};
+
+use super::embeddings::{strip_time_anchor, Embeddable};
+use futures::{stream, StreamExt};
+use log::{error, info, warn};
+use ractor::{Actor, ActorProcessingErr, ActorRef, Message, RpcReplyPort};
+use reqwest::{header::LOCATION, redirect, Url};
+use rustube::{Id, VideoFetcher};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+pub mod segments;
+pub mod subtitles;
+pub mod whisper;
+
+pub use self::whisper::{backend_from_env, OpenAiWhisper, TranscriptionBackend, WhisperCpp};
+
+/// A stretch of speech, times are seconds from the start of the audio.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TranscriptSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// Metadata key naming the [`TranscriptSource`] of a transcript.
+pub const TRANSCRIPT_SOURCE_METADATA: &str = "transcript_source";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TranscriptSource {
+    /// Captions uploaded with the video.
+    Captions,
+    /// Captions the video platform generated.
+    AutoCaptions,
+    /// The audio was transcribed by a [`TranscriptionBackend`].
+    SpeechRecognition,
+}
+
+impl fmt::Display for TranscriptSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptSource::Captions => write!(f, "captions"),
+            TranscriptSource::AutoCaptions => write!(f, "auto-generated captions"),
+            TranscriptSource::SpeechRecognition => write!(f, "speech recognition"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub segments: Vec<TranscriptSegment>,
+    pub source: TranscriptSource,
+}
+
+/// Consecutive segments embedded together, see [`TranscriptionResult::timed_chunks`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptChunk {
+    pub start: f64,
+    pub end: f64,
+    pub content: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscriptionResult {
+    pub url: String,
+    pub segments: Vec<TranscriptSegment>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl TranscriptionResult {
+    pub fn text(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| segment.text.trim())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// Groups the segments into chunks of about `size` words, each ending with the metadata and
+    /// a link to the moment it starts at.
+    pub fn timed_chunks(&self, size: usize) -> Vec<TranscriptChunk> {
+        let mut chunks = Vec::new();
+        let mut words: Vec<&str> = Vec::new();
+        let (mut start, mut end) = (0.0, 0.0);
+        for (piece_start, piece_end, piece) in split_segments(&self.segments, size) {
+            if words.is_empty() {
+                start = piece_start;
+            }
+            end = piece_end;
+            words.extend(piece);
+            if words.len() >= size {
+                chunks.push(self.chunk(start, end, &words));
+                words.clear();
+            }
+        }
+        if !words.is_empty() {
+            chunks.push(self.chunk(start, end, &words));
+        }
+        chunks
+    }
+
+    fn chunk(&self, start: f64, end: f64, words: &[&str]) -> TranscriptChunk {
+        let metadata = format!(
+            "\ntitle: {}\n url: {}, \n time: {} - {}, \n description: {}",
+            self.metadata.get("title").unwrap_or(&"No Title".to_owned()),
+            time_anchored(&self.url, start),
+            segments::timestamp(start),
+            segments::timestamp(end),
+            self.short_description(),
+        );
+        TranscriptChunk {
+            start,
+            end,
+            content: words.join(" ") + &metadata,
+        }
+    }
+}
+
+/// The words of each segment, segments longer than `size` words are split with their time
+/// shared out evenly.
+fn split_segments(segments: &[TranscriptSegment], size: usize) -> Vec<(f64, f64, Vec<&str>)> {
+    let mut pieces = Vec::new();
+    for segment in segments {
+        let words: Vec<&str> = segment.text.split_whitespace().collect();
+        let per_word = (segment.end - segment.start) / words.len().max(1) as f64;
+        for (i, chunk) in words.chunks(size.max(1)).enumerate() {
+            let start = segment.start + (i * size) as f64 * per_word;
+            let end = (start + chunk.len() as f64 * per_word).min(segment.end);
+            pieces.push((start, end, chunk.to_vec()));
+        }
+    }
+    pieces
+}
+
+/// Direct downloads larger than this are refused.
+pub const MAX_DOWNLOAD_BYTES: u64 = 512 * 1024 * 1024;
+/// Redirects followed by a direct download.
+const MAX_REDIRECTS: usize = 5;
+
+/// Files with these extensions are downloaded directly, other urls go through yt-dlp.
+pub const MEDIA_EXTENSIONS: [&str; 11] = [
+    "mp3", "wav", "m4a", "mp4", "ogg", "oga", "opus", "flac", "webm", "mov", "mkv",
+];
+
+/// The extension of the media file `url` links to directly, query and fragment ignored.
+pub fn media_extension(url: &str) -> Option<&'static str> {
+    let name = media_file_name(url)?;
+    let (_, extension) = name.rsplit_once('.')?;
+    MEDIA_EXTENSIONS
+        .into_iter()
+        .find(|media| media.eq_ignore_ascii_case(extension))
+}
+
+fn media_file_name(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let name = url.path_segments()?.last()?;
+    Some(name.to_owned()).filter(|name| !name.is_empty())
+}
+
+/// Direct downloads have to use https and a public address, chat users must not reach into the
+/// network the bot runs in. The checked addresses are the ones connected to.
+async fn public_addresses(url: Url) -> Result<Vec<SocketAddr>, TranscribeError> {
+    let unsupported = || TranscribeError::UnsupportedUrl(url.to_string());
+    if url.scheme() != "https" {
+        return Err(unsupported());
+    }
+
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = match (host_ip(&url), url.host_str()) {
+        (Some(ip), _) => vec![SocketAddr::new(ip, port)],
+        (None, Some(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| TranscribeError::Network(e.to_string()))?
+            .collect(),
+        (None, None) => return Err(unsupported()),
+    };
+    match !addresses.is_empty() && addresses.iter().all(|address| is_public(address.ip())) {
+        true => Ok(addresses),
+        false => Err(unsupported()),
+    }
+}
+
+/// The host of `url` when it is an address instead of a domain.
+fn host_ip(url: &Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // unique local fc00::/7 and link local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// `url` linking to `seconds` into the video, replacing a time it already had.
+pub fn time_anchored(url: &str, seconds: f64) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, format!("#{fragment}")),
+        None => (url, String::new()),
+    };
+    let url = strip_time_anchor(url);
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}t={}{fragment}", seconds.max(0.0) as u64)
+}
+
+impl Embeddable for TranscriptionResult {
+    fn human_readable_source(&self) -> String {
+        self.url.clone()
+    }
+
+    fn long_description(&self) -> String {
+        self.metadata
+            .get("description")
+            .unwrap_or(&String::new())
+            .clone()
+    }
+
+    fn short_description(&self) -> String {
+        self.metadata
+            .get("description")
+            .unwrap_or(&String::new())
+            .clone()
+    }
+
+    fn get_chunks(&self, size: usize) -> Vec<String> {
+        self.timed_chunks(size)
+            .into_iter()
+            .map(|chunk| chunk.content)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum TranscribeError {
+    Network(String),
+    Auth,
+    RateLimited,
+    UnsupportedUrl(String),
+    BinaryMissing(String),
+    Download(String),
+    Io(String),
+    Misconfigured(String),
+}
+
+impl fmt::Display for TranscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscribeError::Network(e) => write!(f, "network error: {e}"),
+            TranscribeError::Auth => write!(f, "the transcription service rejected the API key"),
+            TranscribeError::RateLimited => {
+                write!(
+                    f,
+                    "the transcription service is rate limiting us, try again later"
+                )
+            }
+            TranscribeError::UnsupportedUrl(url) => write!(f, "{url} is not a supported url"),
+            TranscribeError::BinaryMissing(binary) => write!(f, "{binary} is not installed"),
+            TranscribeError::Download(e) => write!(f, "failed to download media: {e}"),
+            TranscribeError::Io(e) => write!(f, "file error: {e}"),
+            TranscribeError::Misconfigured(e) => write!(f, "transcription is misconfigured: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TranscribeError {}
+
+impl From<io::Error> for TranscribeError {
+    fn from(e: io::Error) -> Self {
+        TranscribeError::Io(e.to_string())
+    }
+}
+
+/// Runs an external program, reporting a missing binary separately from other failures.
+fn run_binary(command: &mut Command) -> Result<std::process::Output, TranscribeError> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command.output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => TranscribeError::BinaryMissing(program),
+        _ => TranscribeError::Io(e.to_string()),
+    })
+}
+
+pub enum TranscribeToolMessage {
+    Transcribe(String, RpcReplyPort<Result<Transcript, TranscribeError>>),
+    Metadata(
+        String,
+        RpcReplyPort<Result<HashMap<String, String>, TranscribeError>>,
+    ),
+}
+
+impl Message for TranscribeToolMessage {}
+
+pub struct TranscribeToolState {
+    backend: Arc<dyn TranscriptionBackend>,
+}
+pub struct TranscribeTool;
+
+impl TranscribeTool {
+    /// Uses the captions of the video when it has some, transcribing is slow and costs money.
+    async fn transcribe(
+        state: &TranscribeToolState,
+        url: &str,
+    ) -> Result<Transcript, TranscribeError> {
+        let extension = media_extension(url);
+        if extension.is_none() {
+            match subtitles::fetch_captions(url) {
+                Ok(Some(transcript)) => return Ok(transcript),
+                Ok(None) => info!("No captions for {}, transcribing the audio", url),
+                Err(e) => warn!("Failed to fetch captions for {}: {}", url, e),
+            }
+        }
+
+        // rand file name
+        let file_name = format!("{}.{}", rand::random::<u64>(), extension.unwrap_or("webm"));
+        let downloaded = match extension {
+            Some(_) => match Url::parse(url) {
+                Ok(url) => {
+                    let file = Path::new(&file_name);
+                    Self::download(url, file, MAX_DOWNLOAD_BYTES, public_addresses).await
+                }
+                Err(_) => Err(TranscribeError::UnsupportedUrl(url.to_owned())),
+            },
+            None => Self::download_with_yt_dlp(url, Path::new(&file_name)),
+        };
+        let response = match downloaded {
+            Ok(()) => Self::transcribe_file(state, Path::new(&file_name))
+                .await
+                .map(|segments| Transcript {
+                    segments,
+                    source: TranscriptSource::SpeechRecognition,
+                }),
+            Err(e) => Err(e),
+        };
+
+        // cleanup file
+        if let Err(e) = std::fs::remove_file(&file_name) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove {}: {}", file_name, e);
+            }
+        }
+
+        response
+    }
+
+    fn download_with_yt_dlp(url: &str, file: &Path) -> Result<(), TranscribeError> {
+        let output = run_binary(
+            Command::new("yt-dlp")
+                .arg("--no-check-certificate") // TODO dirty fix for self signed cert
+                .arg("-f")
+                .arg("bestaudio")
+                .arg("-o")
+                .arg(file)
+                .arg(url),
+        )?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("Unsupported URL") {
+                return Err(TranscribeError::UnsupportedUrl(url.to_owned()));
+            }
+            return Err(TranscribeError::Download(stderr.trim().to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Fetches a media file linked directly, such as a chat attachment. Every hop is checked
+    /// with `addresses` and connects to the addresses it returns. Stops once the file is larger
+    /// than `max_bytes`, the caller removes what was written.
+    async fn download<F, R>(
+        url: Url,
+        file: &Path,
+        max_bytes: u64,
+        addresses: F,
+    ) -> Result<(), TranscribeError>
+    where
+        F: Fn(Url) -> R,
+        R: Future<Output = Result<Vec<SocketAddr>, TranscribeError>>,
+    {
+        let too_large =
+            || TranscribeError::Download(format!("{url} is larger than {max_bytes} bytes"));
+        let download_error = |e: reqwest::Error| TranscribeError::Download(e.to_string());
+
+        // redirects are followed here, reqwest would resolve their hosts without the check
+        let mut hop = url.clone();
+        let mut redirects = 0;
+        let mut response = loop {
+            let pinned = addresses(hop.clone()).await?;
+            let mut client = reqwest::Client::builder()
+                .redirect(redirect::Policy::none())
+                .no_proxy();
+            if let Some(domain) = hop.domain() {
+                client = client.resolve_to_addrs(domain, &pinned);
+            }
+            let response = client
+                .build()
+                .map_err(|e| TranscribeError::Network(e.to_string()))?
+                .get(hop.clone())
+                .send()
+                .await
+                .map_err(download_error)?;
+            if !response.status().is_redirection() {
+                break response.error_for_status().map_err(download_error)?;
+            }
+
+            redirects += 1;
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| hop.join(location).ok());
+            match location {
+                Some(location) if redirects <= MAX_REDIRECTS => hop = location,
+                _ => {
+                    return Err(TranscribeError::Download(format!(
+                        "{url} does not redirect to a file"
+                    )))
+                }
+            }
+        };
+        if matches!(response.content_length(), Some(length) if length > max_bytes) {
+            return Err(too_large());
+        }
+
+        let mut output = tokio::fs::File::create(file).await?;
+        let mut written = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| TranscribeError::Download(e.to_string()))?
+        {
+            // the length header is optional and may lie
+            written += chunk.len() as u64;
+            if written > max_bytes {
+                return Err(too_large());
+            }
+            output.write_all(&chunk).await?;
+        }
+        output.flush().await?;
+        Ok(())
+    }
+
+    /// Audio longer than a segment or too big to upload is transcribed in overlapping parts,
+    /// see [`segments::stitch`].
+    async fn transcribe_file(
+        state: &TranscribeToolState,
+        audio: &Path,
+    ) -> Result<Vec<TranscriptSegment>, TranscribeError> {
+        let duration = match segments::probe_duration(audio) {
+            Ok(duration) => duration,
+            Err(e) => {
+                warn!(
+                    "Failed to probe {}, sending it whole: {}",
+                    audio.display(),
+                    e
+                );
+                return state.backend.transcribe(audio).await;
+            }
+        };
+        // videos and lossless audio can be too big even when they are short
+        let size = std::fs::metadata(audio)?.len();
+        if duration <= segments::SEGMENT_SECONDS + segments::OVERLAP_SECONDS
+            && size <= segments::MAX_UPLOAD_BYTES
+        {
+            return state.backend.transcribe(audio).await;
+        }
+
+        let parts = segments::split(audio, duration)?;
+        info!("Transcribing {} in {} parts", audio.display(), parts.len());
+        let transcripts: Vec<Result<(f64, Vec<TranscriptSegment>), TranscribeError>> =
+            stream::iter(&parts)
+                .map(|part| async move {
+                    let segments = state.backend.transcribe(&part.path).await?;
+                    Ok((part.start, segments))
+                })
+                .buffered(segments::CONCURRENT_SEGMENTS)
+                .collect()
+                .await;
+        segments::remove(&parts);
+
+        let transcripts = transcripts.into_iter().collect::<Result<Vec<_>, _>>()?;
+        Ok(segments::stitch(transcripts))
+    }
+
+    async fn metadata(url: &str) -> Result<HashMap<String, String>, TranscribeError> {
+        if let Some(name) = media_extension(url).and_then(|_| media_file_name(url)) {
+            return Ok(HashMap::from([("title".to_owned(), name)]));
+        }
+
+        let Ok(id) = Id::from_raw(url) else {
+            return Err(TranscribeError::UnsupportedUrl(url.to_owned()));
+        };
+
+        let descrambler = VideoFetcher::from_id(id.into_owned())
+            .map_err(|e| TranscribeError::Network(e.to_string()))?
+            .fetch()
+            .await
+            .map_err(|e| TranscribeError::Network(e.to_string()))?;
+
+        let mut metadata: HashMap<String, String> = HashMap::new();
+
+        metadata.insert("title".to_owned(), descrambler.video_title().clone());
+        metadata.insert(
+            "description".to_owned(),
+            descrambler.video_details().short_description.clone(),
+        );
+        metadata.insert(
+            "author".to_owned(),
+            descrambler.video_details().author.clone(),
+        );
+
+        Ok(metadata)
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for TranscribeTool {
+    type Arguments = ();
+    type Msg = TranscribeToolMessage;
+    type State = TranscribeToolState;
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        _: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        Ok(TranscribeToolState {
+            backend: backend_from_env()?,
+        })
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            TranscribeToolMessage::Transcribe(url, rpc) => {
+                info!("Transcribing: {}", url);
+                let result = Self::transcribe(state, &url).await;
+                if let Err(e) = &result {
+                    error!("Failed to transcribe {}: {}", url, e);
+                }
+                let _ = rpc.send(result);
+            }
+            TranscribeToolMessage::Metadata(url, port) => {
+                let result = Self::metadata(&url).await;
+                if let Err(e) = &result {
+                    warn!("Failed to fetch metadata for {}: {}", url, e);
+                }
+                let _ = port.send(result);
+            }
+        }
+        Ok(())
+    }
+}
+
+// tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ractor::call;
+    use tokio::{fs::File, io::AsyncWriteExt};
+
+    #[tokio::test]
+    #[ignore = "uses paying service"]
+    async fn transcribe_youtube_video() {
+        let (actor_ref, _) = Actor::spawn(None, TranscribeTool, ()).await.unwrap();
+        let rep = call!(
+            &actor_ref,
+            TranscribeToolMessage::Transcribe,
+            "https://www.youtube.com/shorts/CEV_zDWsxGA".to_owned()
+        )
+        .unwrap();
+        let transcript = TranscriptionResult {
+            url: String::new(),
+            segments: rep.unwrap().segments,
+            metadata: HashMap::new(),
+        }
+        .text();
+        assert!(transcript.starts_with("I can't tell"));
+
+        // save to file
+        let mut file = File::create("test_transctibe.txt").await.unwrap();
+        file.write_all(transcript.as_bytes()).await.unwrap();
+    }
+
+    #[test]
+    fn media_urls() {
+        assert_eq!(
+            media_extension(
+                "https://cdn.discordapp.com/attachments/1/2/voice-message.ogg?ex=65&is=64#x"
+            ),
+            Some("ogg")
+        );
+        assert_eq!(media_extension("https://example.com/Talk.MP4"), Some("mp4"));
+        assert_eq!(
+            media_extension("https://www.youtube.com/watch?v=CEV_zDWsxGA"),
+            None
+        );
+        assert_eq!(media_extension("https://example.com/notes.txt"), None);
+        assert_eq!(media_extension("not a url.mp3"), None);
+    }
+
+    /// Lets the mock server through, every other host is checked as usual.
+    async fn mock_or_public(url: Url) -> Result<Vec<SocketAddr>, TranscribeError> {
+        match url.host_str() {
+            Some("127.0.0.1") => Ok(Vec::new()),
+            _ => public_addresses(url).await,
+        }
+    }
+
+    #[tokio::test]
+    async fn download_limits() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        for url in [
+            "http://example.com/talk.mp3",
+            "https://localhost/talk.mp3",
+            "https://127.0.0.1/talk.mp3",
+            "https://10.0.0.8/talk.mp3",
+            "https://169.254.169.254/latest/meta-data.mp3",
+            "https://[::1]/talk.mp3",
+            "https://[fd00::1]/talk.mp3",
+        ] {
+            assert!(
+                matches!(
+                    public_addresses(Url::parse(url).unwrap()).await,
+                    Err(TranscribeError::UnsupportedUrl(_))
+                ),
+                "{url}"
+            );
+        }
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+
+        let server = MockServer::start().await;
+        let redirect =
+            |location: &str| ResponseTemplate::new(302).insert_header("location", location);
+        Mock::given(method("GET"))
+            .and(path("/talk.mp3"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0; 2048]))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/moved.mp3"))
+            .respond_with(redirect("/talk.mp3"))
+            .mount(&server)
+            .await;
+        let private = format!("https://localhost:{}/talk.mp3", server.address().port());
+        Mock::given(method("GET"))
+            .and(path("/private.mp3"))
+            .respond_with(redirect(&private))
+            .mount(&server)
+            .await;
+
+        let url = |file: &str| Url::parse(&format!("{}/{file}", server.uri())).unwrap();
+        let file = std::env::temp_dir().join(format!("andrena-download-{}", rand::random::<u64>()));
+        let download = |url: Url, max_bytes: u64| {
+            TranscribeTool::download(url, &file, max_bytes, mock_or_public)
+        };
+        assert!(download(url("moved.mp3"), 4096).await.is_ok());
+        assert_eq!(std::fs::metadata(&file).unwrap().len(), 2048);
+        assert!(matches!(
+            download(url("talk.mp3"), 1024).await,
+            Err(TranscribeError::Download(_))
+        ));
+        assert!(matches!(
+            download(url("private.mp3"), 4096).await,
+            Err(TranscribeError::UnsupportedUrl(_))
+        ));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn timed_chunks() {
+        let segment = |start: f64, end: f64, text: &str| TranscriptSegment {
+            start,
+            end,
+            text: text.to_owned(),
+        };
+        let result = TranscriptionResult {
+            url: "https://www.youtube.com/watch?v=CEV_zDWsxGA".to_owned(),
+            segments: vec![
+                segment(0.0, 2.0, "one two"),
+                segment(2.0, 5.0, "three"),
+                segment(65.0, 71.0, "four five six seven eight nine"),
+            ],
+            metadata: HashMap::from([("title".to_owned(), "Holt".to_owned())]),
+        };
+
+        let chunks = result.timed_chunks(3);
+        let times: Vec<(f64, f64)> = chunks.iter().map(|c| (c.start, c.end)).collect();
+        assert_eq!(times, vec![(0.0, 5.0), (65.0, 68.0), (68.0, 71.0)]);
+        assert!(chunks[1].content.starts_with("four five six\ntitle: Holt"));
+        assert!(chunks[1]
+            .content
+            .contains("url: https://www.youtube.com/watch?v=CEV_zDWsxGA&t=65"));
+        assert!(chunks[1].content.contains("time: 00:01:05 - 00:01:08"));
+
+        assert_eq!(
+            time_anchored("https://youtu.be/CEV_zDWsxGA?t=10", 42.9),
+            "https://youtu.be/CEV_zDWsxGA?t=42"
+        );
+        assert_eq!(
+            time_anchored("https://example.com/talk.mp4#player", 7.0),
+            "https://example.com/talk.mp4?t=7#player"
+        );
+    }
+
+    #[tokio::test]
+    async fn metadata() {
+        let (actor, _) = Actor::spawn(None, TranscribeTool, ()).await.unwrap();
+        let rep = call!(
+            &actor,
+            TranscribeToolMessage::Metadata,
+            "https://www.youtube.com/watch?v=CEV_zDWsxGA".to_owned()
+        )
+        .unwrap();
+
+        assert!(rep.is_ok());
+        assert!(
+            rep.unwrap().get("title").unwrap()
+                == "Another perfect insult from Holt #shorts | Brooklyn Nine-Nine"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_metadata() {
+        let (actor, _) = Actor::spawn(None, TranscribeTool, ()).await.unwrap();
+        let rep = call!(
+            &actor,
+            TranscribeToolMessage::Metadata,
+            "https://www.example.com".to_owned()
+        )
+        .unwrap();
+
+        assert!(matches!(rep, Err(TranscribeError::UnsupportedUrl(_))));
+    }
+}
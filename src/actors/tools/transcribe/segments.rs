@@ -0,0 +1,228 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::warn;
+
+use super::{run_binary, TranscribeError, TranscriptSegment};
+
+/// Long audio is cut into parts of this many seconds, small enough for the upload limit of the
+/// transcription API at the bitrate they are encoded with.
+pub const SEGMENT_SECONDS: f64 = 600.0;
+/// Parts overlap so words cut at a boundary are heard whole in one of them.
+pub const OVERLAP_SECONDS: f64 = 5.0;
+/// Files above this are re-encoded before they are uploaded, the transcription API takes at
+/// most 25 MB.
+pub const MAX_UPLOAD_BYTES: u64 = 24 * 1024 * 1024;
+/// Parts transcribed at the same time.
+pub const CONCURRENT_SEGMENTS: usize = 4;
+/// Words compared at a boundary when removing what both segments heard.
+const MAX_OVERLAP_WORDS: usize = 40;
+/// Words a part may start or end with that were cut off by the boundary.
+const CUT_WORDS: usize = 2;
+
+pub struct AudioPart {
+    /// Offset of the part in the audio, in seconds.
+    pub start: f64,
+    pub path: PathBuf,
+}
+
+/// Duration of the audio in seconds, read with `ffprobe`.
+pub fn probe_duration(audio: &Path) -> Result<f64, TranscribeError> {
+    let output = run_binary(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-show_entries", "format=duration"])
+            .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+            .arg(audio),
+    )?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.trim().parse().map_err(|_| {
+        TranscribeError::Io(format!(
+            "unknown duration of {}: {}",
+            audio.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    })
+}
+
+/// Offsets of the parts covering `duration` seconds.
+pub fn segment_starts(duration: f64) -> Vec<f64> {
+    let mut starts = vec![0.0];
+    let mut start = SEGMENT_SECONDS;
+    // the overlap of the previous part already covers a short tail
+    while start + OVERLAP_SECONDS < duration {
+        starts.push(start);
+        start += SEGMENT_SECONDS;
+    }
+    starts
+}
+
+/// Cuts `audio` into overlapping mono mp3 parts next to it, the caller removes them.
+pub fn split(audio: &Path, duration: f64) -> Result<Vec<AudioPart>, TranscribeError> {
+    let stem = audio
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut parts = Vec::new();
+    for (i, start) in segment_starts(duration).into_iter().enumerate() {
+        let path = audio.with_file_name(format!("{stem}-{i}.mp3"));
+        let output = run_binary(
+            Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-ss", &start.to_string()])
+                .args(["-t", &(SEGMENT_SECONDS + OVERLAP_SECONDS).to_string()])
+                .arg("-i")
+                .arg(audio)
+                .args(["-vn", "-ac", "1", "-ar", "16000", "-b:a", "48k"])
+                .arg(&path),
+        );
+        let failed = match output {
+            Ok(output) if output.status.success() => None,
+            Ok(output) => Some(TranscribeError::Io(
+                String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            )),
+            Err(e) => Some(e),
+        };
+        parts.push(AudioPart { start, path });
+        if let Some(e) = failed {
+            remove(&parts);
+            return Err(e);
+        }
+    }
+    Ok(parts)
+}
+
+pub fn remove(parts: &[AudioPart]) {
+    for part in parts {
+        if let Err(e) = std::fs::remove_file(&part.path) {
+            warn!("Failed to remove {}: {}", part.path.display(), e);
+        }
+    }
+}
+
+/// `hh:mm:ss` of an offset in seconds.
+pub fn timestamp(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Joins the transcripts of consecutive parts starting at the given offsets. What both parts
+/// heard in their overlap is kept once.
+pub fn stitch(transcripts: Vec<(f64, Vec<TranscriptSegment>)>) -> Vec<TranscriptSegment> {
+    let mut stitched: Vec<TranscriptSegment> = Vec::new();
+    for (offset, segments) in transcripts {
+        for mut segment in segments {
+            segment.start += offset;
+            segment.end += offset;
+            if let Some(previous) = stitched.last_mut() {
+                if segment.end <= previous.end {
+                    continue;
+                }
+                if segment.start < previous.end {
+                    let mut words: Vec<&str> = previous.text.split_whitespace().collect();
+                    let next: Vec<&str> = segment.text.split_whitespace().collect();
+                    if let Some((cut, skip)) = overlap(&words, &next) {
+                        words.truncate(words.len() - cut);
+                        let kept = words.join(" ");
+                        let text = next[skip..].join(" ");
+                        previous.text = kept;
+                        segment.text = text;
+                    }
+                }
+            }
+            if !segment.text.trim().is_empty() {
+                stitched.push(segment);
+            }
+        }
+    }
+    stitched
+}
+
+/// The longest run of words ending `previous` that also starts `next`, ignoring case,
+/// punctuation and a few words cut by the boundary. Returns how many words to cut from the end
+/// of `previous` and to skip at the start of `next`.
+fn overlap(previous: &[&str], next: &[&str]) -> Option<(usize, usize)> {
+    let normalize = |word: &str| -> String {
+        word.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let previous: Vec<String> = previous.iter().map(|w| normalize(w)).collect();
+    let next: Vec<String> = next.iter().map(|w| normalize(w)).collect();
+
+    let mut best: Option<(usize, usize, usize)> = None;
+    for cut in 0..=CUT_WORDS.min(previous.len()) {
+        for skip in 0..=CUT_WORDS.min(next.len()) {
+            let end = previous.len() - cut;
+            let longest = MAX_OVERLAP_WORDS.min(end).min(next.len() - skip);
+            // a single shared word is as likely to be chance
+            for length in (2..=longest).rev() {
+                if previous[end - length..end] == next[skip..skip + length] {
+                    if length > best.map_or(0, |(_, _, best)| best) {
+                        best = Some((cut, skip, length));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    best.map(|(cut, skip, length)| (cut, skip + length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start,
+            end,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn segments() {
+        assert_eq!(segment_starts(30.0), vec![0.0]);
+        assert_eq!(segment_starts(603.0), vec![0.0]);
+        assert_eq!(segment_starts(1300.0), vec![0.0, 600.0, 1200.0]);
+        assert_eq!(timestamp(3725.4), "01:02:05");
+    }
+
+    #[test]
+    fn stitch_overlap() {
+        let transcripts = vec![
+            (
+                0.0,
+                vec![
+                    segment(0.0, 4.0, "so the plan is"),
+                    segment(4.0, 605.0, "to ship the parser this we"),
+                ],
+            ),
+            (
+                600.0,
+                vec![
+                    segment(0.0, 2.0, "ship the"),
+                    segment(2.0, 8.0, "ek. Ship the parser this week, then the docs."),
+                    segment(8.0, 12.0, "Something else entirely."),
+                ],
+            ),
+        ];
+        assert_eq!(
+            stitch(transcripts),
+            vec![
+                segment(0.0, 4.0, "so the plan is"),
+                segment(4.0, 605.0, "to ship the parser this"),
+                segment(602.0, 608.0, "week, then the docs."),
+                segment(608.0, 612.0, "Something else entirely."),
+            ]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::{env, fs, path::Path, process::Command};
+
+use log::{info, warn};
+
+use super::{run_binary, TranscribeError, Transcript, TranscriptSegment, TranscriptSource};
+
+/// Caption languages asked from yt-dlp, the first file that parses is used.
+const SUBTITLE_LANGUAGES: &str = "en.*";
+
+/// Captions published with the video, falling back to automatic ones. `None` when it has
+/// neither, the audio has to be transcribed then.
+pub fn fetch_captions(url: &str) -> Result<Option<Transcript>, TranscribeError> {
+    for (flag, source) in [
+        ("--write-subs", TranscriptSource::Captions),
+        ("--write-auto-subs", TranscriptSource::AutoCaptions),
+    ] {
+        let dir = env::temp_dir().join(format!("andrena-subs-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir)?;
+        let segments = download_subtitles(url, flag, &dir);
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+
+        let segments = segments?;
+        if !segments.is_empty() {
+            info!("Using {} of {}", source, url);
+            return Ok(Some(Transcript { segments, source }));
+        }
+    }
+    Ok(None)
+}
+
+fn download_subtitles(
+    url: &str,
+    flag: &str,
+    dir: &Path,
+) -> Result<Vec<TranscriptSegment>, TranscribeError> {
+    let output = run_binary(
+        Command::new("yt-dlp")
+            .arg("--no-check-certificate") // TODO dirty fix for self signed cert
+            .args(["--skip-download", flag, "--sub-langs", SUBTITLE_LANGUAGES])
+            .args(["--sub-format", "vtt/srt", "-o"])
+            .arg(dir.join("subtitles"))
+            .arg(url),
+    )?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("Unsupported URL") {
+            return Err(TranscribeError::UnsupportedUrl(url.to_owned()));
+        }
+        return Err(TranscribeError::Download(stderr.trim().to_owned()));
+    }
+
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    files.sort();
+    for file in files {
+        let segments = parse_subtitles(&fs::read_to_string(&file)?);
+        if !segments.is_empty() {
+            return Ok(segments);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Parses WebVTT or SRT subtitles. Markup is stripped and lines repeated from the previous
+/// cue, as YouTube's auto-generated captions do while they scroll, are kept once.
+pub fn parse_subtitles(content: &str) -> Vec<TranscriptSegment> {
+    let content = content.replace("\r\n", "\n");
+    let mut segments = Vec::new();
+    let mut previous: Vec<String> = Vec::new();
+    for block in content.split("\n\n") {
+        // cue identifiers and the WEBVTT header come before the timing line
+        let mut lines = block.lines();
+        let Some((start, end)) = lines.by_ref().find_map(parse_timing) else {
+            continue;
+        };
+
+        let text: Vec<String> = lines
+            .map(clean_line)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let new: Vec<&str> = text
+            .iter()
+            .filter(|line| !previous.contains(line))
+            .map(String::as_str)
+            .collect();
+        if !new.is_empty() {
+            segments.push(TranscriptSegment {
+                start,
+                end,
+                text: new.join(" "),
+            });
+        }
+        previous = text;
+    }
+    segments
+}
+
+/// `00:01:02.500 --> 00:01:04.000 align:start`, SRT separates milliseconds with a comma.
+fn parse_timing(line: &str) -> Option<(f64, f64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_time(start.trim())?, parse_time(end)?))
+}
+
+/// `hh:mm:ss.mmm` or `mm:ss.mmm` in seconds.
+fn parse_time(time: &str) -> Option<f64> {
+    let time = time.replace(',', ".");
+    let mut parts = time.rsplit(':');
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let hours: f64 = match parts.next() {
+        Some(hours) => hours.parse().ok()?,
+        None => 0.0,
+    };
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// The text of a cue line without tags such as `<c>` or inline timestamps.
+fn clean_line(line: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start,
+            end,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn srt() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\nHello there.\r\n\r\n\
+                   2\r\n00:00:02,500 --> 00:01:04,000\r\nGeneral <i>Kenobi</i>!\r\nYou are a bold one.\r\n";
+        assert_eq!(
+            parse_subtitles(srt),
+            vec![
+                segment(1.0, 2.5, "Hello there."),
+                segment(2.5, 64.0, "General Kenobi! You are a bold one."),
+            ]
+        );
+    }
+
+    #[test]
+    fn auto_generated_vtt() {
+        let vtt = "WEBVTT\nKind: captions\nLanguage: en\n\n\
+                   00:00.000 --> 00:02.000 align:start position:0%\n \n\
+                   so<00:00:00.500><c> the</c><00:00:01.000><c> plan</c>\n\n\
+                   00:02.000 --> 00:02.010 align:start position:0%\n\
+                   so the plan\n \n\n\
+                   01:00:02.010 --> 01:00:04.000 align:start position:0%\n\
+                   so the plan\n\
+                   is to ship &amp; test\n";
+        assert_eq!(
+            parse_subtitles(vtt),
+            vec![
+                segment(0.0, 2.0, "so the plan"),
+                segment(3602.01, 3604.0, "is to ship & test"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use log::warn;
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use serde::Deserialize;
+
+use super::{run_binary, TranscribeError, TranscriptSegment};
+
+/// Transcribes audio files, see [`backend_from_env`] for how a deployment picks one.
+#[async_trait]
+pub trait TranscriptionBackend: Send + Sync {
+    async fn transcribe(&self, audio: &Path) -> Result<Vec<TranscriptSegment>, TranscribeError>;
+}
+
+/// Reads `TRANSCRIBE_BACKEND`, `openai` (the default) or `whisper.cpp`.
+pub fn backend_from_env() -> Result<Arc<dyn TranscriptionBackend>, TranscribeError> {
+    match env::var("TRANSCRIBE_BACKEND").as_deref() {
+        Err(_) | Ok("openai") => Ok(Arc::new(OpenAiWhisper::new())),
+        Ok("whisper.cpp") => Ok(Arc::new(WhisperCpp::from_env()?)),
+        Ok(other) => Err(TranscribeError::Misconfigured(format!(
+            "unknown TRANSCRIBE_BACKEND {other}"
+        ))),
+    }
+}
+
+/// Uploads the audio to OpenAI's `whisper-1`.
+pub struct OpenAiWhisper {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct VerboseTranscription {
+    text: String,
+    #[serde(default)]
+    duration: f64,
+    #[serde(default)]
+    segments: Vec<TranscriptSegment>,
+}
+
+impl OpenAiWhisper {
+    pub fn new() -> OpenAiWhisper {
+        OpenAiWhisper::with_base_url(
+            "https://api.openai.com/v1".to_owned(),
+            env::var("OPENAI_API_KEY").unwrap_or_default(),
+        )
+    }
+
+    pub fn with_base_url(base_url: String, api_key: String) -> OpenAiWhisper {
+        OpenAiWhisper {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl TranscriptionBackend for OpenAiWhisper {
+    async fn transcribe(&self, audio: &Path) -> Result<Vec<TranscriptSegment>, TranscribeError> {
+        let file_name = audio
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let form = Form::new()
+            .text("model", "whisper-1")
+            .text("response_format", "verbose_json")
+            .part("file", Part::bytes(fs::read(audio)?).file_name(file_name));
+
+        let response = self
+            .client
+            .post(format!("{}/audio/transcriptions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| TranscribeError::Network(e.to_string()))?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED => return Err(TranscribeError::Auth),
+            StatusCode::TOO_MANY_REQUESTS => return Err(TranscribeError::RateLimited),
+            status if !status.is_success() => {
+                let body = response.text().await.unwrap_or_default();
+                return Err(TranscribeError::Network(format!("{status}: {body}")));
+            }
+            _ => {}
+        }
+
+        let transcription: VerboseTranscription = response
+            .json()
+            .await
+            .map_err(|e| TranscribeError::Network(e.to_string()))?;
+        if transcription.segments.is_empty() && !transcription.text.trim().is_empty() {
+            return Ok(vec![TranscriptSegment {
+                start: 0.0,
+                end: transcription.duration,
+                text: transcription.text,
+            }]);
+        }
+        Ok(transcription.segments)
+    }
+}
+
+/// Runs a local [whisper.cpp](https://github.com/ggerganov/whisper.cpp) binary, audio never
+/// leaves the machine.
+#[derive(Clone)]
+pub struct WhisperCpp {
+    binary: String,
+    model: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct WhisperCppOutput {
+    transcription: Vec<WhisperCppSegment>,
+}
+
+#[derive(Deserialize)]
+struct WhisperCppSegment {
+    /// Milliseconds from the start of the audio.
+    offsets: WhisperCppOffsets,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct WhisperCppOffsets {
+    from: u64,
+    to: u64,
+}
+
+impl WhisperCpp {
+    pub fn new(binary: String, model: PathBuf) -> WhisperCpp {
+        WhisperCpp { binary, model }
+    }
+
+    /// `WHISPER_CPP_MODEL` is the path of a ggml model, `WHISPER_CPP_BINARY` defaults to
+    /// `whisper-cpp`.
+    pub fn from_env() -> Result<WhisperCpp, TranscribeError> {
+        let model = env::var("WHISPER_CPP_MODEL").map_err(|_| {
+            TranscribeError::Misconfigured("WHISPER_CPP_MODEL is not set".to_owned())
+        })?;
+        let binary = env::var("WHISPER_CPP_BINARY").unwrap_or("whisper-cpp".to_owned());
+        Ok(WhisperCpp::new(binary, PathBuf::from(model)))
+    }
+
+    fn run(&self, audio: &Path, output: &Path) -> Result<Vec<TranscriptSegment>, TranscribeError> {
+        // whisper.cpp only reads 16 kHz wav files
+        let wav = match is_whisper_wav(audio) {
+            true => None,
+            false => {
+                let wav = output.with_extension("wav");
+                let converted = run_binary(
+                    Command::new("ffmpeg")
+                        .args(["-y", "-loglevel", "error", "-i"])
+                        .arg(audio)
+                        .args(["-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le"])
+                        .arg(&wav),
+                )?;
+                if !converted.status.success() {
+                    return Err(TranscribeError::Io(
+                        String::from_utf8_lossy(&converted.stderr).trim().to_owned(),
+                    ));
+                }
+                Some(wav)
+            }
+        };
+
+        let result = run_binary(
+            Command::new(&self.binary)
+                .arg("-m")
+                .arg(&self.model)
+                .arg("-f")
+                .arg(wav.as_deref().unwrap_or(audio))
+                .args(["--output-json", "--output-file"])
+                .arg(output),
+        );
+        if let Some(wav) = wav {
+            if let Err(e) = fs::remove_file(&wav) {
+                warn!("Failed to remove {}: {}", wav.display(), e);
+            }
+        }
+
+        let result = result?;
+        if !result.status.success() {
+            return Err(TranscribeError::Io(
+                String::from_utf8_lossy(&result.stderr).trim().to_owned(),
+            ));
+        }
+        let json_file = output.with_extension("json");
+        let json = fs::read_to_string(&json_file)?;
+        if let Err(e) = fs::remove_file(&json_file) {
+            warn!("Failed to remove {}: {}", json_file.display(), e);
+        }
+        let output: WhisperCppOutput = serde_json::from_str(&json)
+            .map_err(|e| TranscribeError::Io(format!("invalid whisper.cpp output: {e}")))?;
+        Ok(output
+            .transcription
+            .into_iter()
+            .map(|segment| TranscriptSegment {
+                start: segment.offsets.from as f64 / 1000.0,
+                end: segment.offsets.to as f64 / 1000.0,
+                text: segment.text.trim().to_owned(),
+            })
+            .collect())
+    }
+}
+
+/// Whether `audio` is a wav file whisper.cpp reads as it is: 16 kHz mono 16 bit PCM.
+fn is_whisper_wav(audio: &Path) -> bool {
+    let mut header = Vec::new();
+    let read = File::open(audio).and_then(|file| file.take(4096).read_to_end(&mut header));
+    if read.is_err() || header.len() < 12 || &header[..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return false;
+    }
+
+    let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+    // chunks of an id and a size follow the RIFF header, fmt is usually the first
+    let mut offset = 12;
+    while offset + 8 <= header.len() {
+        let size = u32_at(offset + 4) as usize;
+        if &header[offset..offset + 4] == b"fmt " {
+            let fmt = offset + 8;
+            return fmt + 16 <= header.len()
+                && u16_at(fmt) == 1
+                && u16_at(fmt + 2) == 1
+                && u32_at(fmt + 4) == 16000
+                && u16_at(fmt + 14) == 16;
+        }
+        offset += 8 + size + size % 2;
+    }
+    false
+}
+
+#[async_trait]
+impl TranscriptionBackend for WhisperCpp {
+    async fn transcribe(&self, audio: &Path) -> Result<Vec<TranscriptSegment>, TranscribeError> {
+        let output = env::temp_dir().join(format!("andrena-whisper-{}", rand::random::<u64>()));
+        let backend = self.clone();
+        let audio = audio.to_owned();
+        tokio::task::spawn_blocking(move || backend.run(&audio, &output))
+            .await
+            .map_err(|e| TranscribeError::Io(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/silence.wav")
+    }
+
+    /// 44.1 kHz stereo, has to be converted for whisper.cpp.
+    fn cd_fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/silence-44k.wav")
+    }
+
+    #[tokio::test]
+    async fn openai_segments() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "text": "Hello there. General Kenobi.",
+                "duration": 4.2,
+                "segments": [
+                    {"id": 0, "start": 0.0, "end": 1.5, "text": " Hello there."},
+                    {"id": 1, "start": 1.5, "end": 4.2, "text": " General Kenobi."},
+                ],
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiWhisper::with_base_url(server.uri(), "key".to_owned());
+        let segments = backend.transcribe(&fixture()).await.unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].start, 1.5);
+        assert_eq!(segments[1].text, " General Kenobi.");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        let backend = OpenAiWhisper::with_base_url(server.uri(), "key".to_owned());
+        assert!(matches!(
+            backend.transcribe(&fixture()).await,
+            Err(TranscribeError::Auth)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn whisper_cpp_arguments() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // stands in for whisper.cpp, writes the arguments it got as the transcript
+        let dir = env::temp_dir().join(format!("andrena-whisper-test-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("whisper-cpp");
+        fs::write(
+            &binary,
+            r#"#!/bin/sh
+for last; do :; done
+printf '{"transcription":[{"offsets":{"from":0,"to":1500},"text":" %s"}]}' "$*" > "$last.json"
+"#,
+        )
+        .unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let backend = WhisperCpp::new(
+            binary.to_string_lossy().into_owned(),
+            PathBuf::from("ggml-base.en.bin"),
+        );
+        let segments = backend.transcribe(&fixture()).await.unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].end, 1.5);
+        assert!(segments[0].text.starts_with(&format!(
+            "-m ggml-base.en.bin -f {} --output-json",
+            fixture().display()
+        )));
+
+        assert!(!is_whisper_wav(&cd_fixture()));
+        match backend.transcribe(&cd_fixture()).await {
+            // whisper.cpp gets the resampled copy
+            Ok(segments) => assert!(!segments[0].text.contains("silence-44k.wav")),
+            Err(e) => {
+                assert!(matches!(e, TranscribeError::BinaryMissing(binary) if binary == "ffmpeg"))
+            }
+        }
+
+        let missing = WhisperCpp::new("andrena-missing-whisper".to_owned(), PathBuf::new());
+        assert!(matches!(
+            missing.transcribe(&fixture()).await,
+            Err(TranscribeError::BinaryMissing(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs whisper.cpp and WHISPER_CPP_MODEL"]
+    async fn whisper_cpp_fixture() {
+        let backend = WhisperCpp::from_env().unwrap();
+        assert!(backend.transcribe(&fixture()).await.is_ok());
+    }
+}
@@ -1,12 +1,46 @@
 use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs, Role};
-use log::info;
-use tiktoken_rs::{get_bpe_from_model, get_chat_completion_max_tokens};
+use tiktoken_rs::{cl100k_base, get_bpe_from_model, CoreBPE};
 
-use crate::actors::tools::embeddings::Embedding;
+use crate::{
+    actors::tools::embeddings::Embedding,
+    vector_index::{HnswIndex, VectorIndex},
+};
+
+/// Tokens kept free for the answer, history and documents are cut to leave at least this much.
+pub const RESPONSE_TOKENS: usize = 750;
+/// Share of the remaining window, in percent, documents keep even when the history could fill it.
+const DOCUMENT_SHARE: usize = 40;
+/// A document is dropped rather than cut below this many tokens.
+const MIN_DOCUMENT_TOKENS: usize = 64;
+/// Entries kept in the history, older ones never fit into a window anyway.
+const MAX_HISTORY: usize = 100;
+/// Framing of every chat message, see OpenAI's guide on counting chat tokens.
+const MESSAGE_TOKENS: usize = 4;
+/// Every reply is primed with these tokens.
+const REPLY_TOKENS: usize = 3;
+
+/// How a request fills the model's window, see [`GptContext::plan`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextPlan {
+    pub include_static_context: bool,
+    /// Index of the oldest history entry that fits.
+    pub history_start: usize,
+    /// Selected documents that fit, cut where needed, most relevant last.
+    pub documents: Vec<String>,
+    /// The latest history entry, cut when it doesn't fit on its own.
+    pub latest: Option<(String, String)>,
+    /// Tokens of the planned messages.
+    pub prompt_tokens: usize,
+    /// Tokens left for the answer, at least [`RESPONSE_TOKENS`] unless the fixed parts alone
+    /// don't fit.
+    pub max_tokens: usize,
+}
 
 pub struct GptContext {
+    /// Sent as the first message of every request.
+    pub system_prompt: Option<String>,
     pub static_context: Vec<String>,
-    pub embeddings: Vec<Embedding>,
+    pub embeddings: Box<dyn VectorIndex>,
     pub selected_embeddings: Vec<Embedding>,
     pub history: Vec<(String, String)>,
 }
@@ -14,9 +48,10 @@ pub struct GptContext {
 impl GptContext {
     pub fn new() -> GptContext {
         GptContext {
+            system_prompt: None,
             static_context: Vec::new(),
             history: Vec::new(),
-            embeddings: Vec::new(),
+            embeddings: Box::new(HnswIndex::new()),
             selected_embeddings: Vec::new(),
         }
     }
@@ -37,14 +72,115 @@ impl GptContext {
 
     pub fn push_history(&mut self, entry: (String, String)) {
         self.history.push(entry);
+        if self.history.len() > MAX_HISTORY {
+            self.history.drain(..self.history.len() - MAX_HISTORY);
+        }
     }
 
-    pub fn to_openai_chat_history(
+    /// Splits `window` tokens between the system prompt, static context, selected documents,
+    /// history and the answer. `reserved` tokens are kept free for what is sent besides the
+    /// messages, such as function definitions.
+    ///
+    /// The system prompt, static context and latest message are always sent. Documents are cut
+    /// or dropped, least relevant first, down to their share of what is left, the history gets
+    /// the rest and loses its oldest entries first.
+    pub fn plan(
         &self,
+        model: &str,
+        window: usize,
+        reserved: usize,
         include_static_context: bool,
-    ) -> Vec<ChatCompletionRequestMessage> {
-        let mut chat: Vec<ChatCompletionRequestMessage> = Vec::new();
+    ) -> ContextPlan {
+        let counter = TokenCounter::new(model);
+
+        let mut fixed = REPLY_TOKENS;
+        if let Some(prompt) = &self.system_prompt {
+            fixed += counter.message(None, prompt);
+        }
         if include_static_context {
+            for h in &self.static_context {
+                fixed += counter.message(Some("system"), h);
+            }
+        }
+
+        let mut free = window.saturating_sub(fixed + reserved + RESPONSE_TOKENS);
+        let mut latest_tokens = 0;
+        let latest = self.history.last().map(|(author, content)| {
+            let framing = counter.message(Some(author), "");
+            let content = counter.truncate(content, free.saturating_sub(framing));
+            latest_tokens = counter.message(Some(author), &content);
+            free = free.saturating_sub(latest_tokens);
+            (author.clone(), content)
+        });
+
+        let earlier = &self.history[..self.history.len().saturating_sub(1)];
+        let history_tokens: Vec<usize> = earlier
+            .iter()
+            .map(|(author, content)| counter.message(Some(author), content))
+            .collect();
+
+        // documents may use whatever the history leaves, but never less than their share
+        let history_limit = free - free * DOCUMENT_SHARE / 100;
+        let mut document_budget = free - history_tokens.iter().sum::<usize>().min(history_limit);
+        let mut documents = Vec::new();
+        for embedding in self.selected_embeddings.iter().rev() {
+            let tokens = counter.message(Some("documentation"), &embedding.content);
+            if tokens <= document_budget {
+                document_budget -= tokens;
+                documents.push(embedding.content.clone());
+                continue;
+            }
+
+            let framing = counter.message(Some("documentation"), "");
+            if document_budget >= framing + MIN_DOCUMENT_TOKENS {
+                documents.push(counter.truncate(&embedding.content, document_budget - framing));
+            }
+            break;
+        }
+        documents.reverse();
+        let document_tokens: usize = documents
+            .iter()
+            .map(|d| counter.message(Some("documentation"), d))
+            .sum();
+
+        let mut history_budget = free - document_tokens;
+        let mut history_start = earlier.len();
+        for tokens in history_tokens.iter().rev() {
+            if *tokens > history_budget {
+                break;
+            }
+            history_budget -= tokens;
+            history_start -= 1;
+        }
+
+        let prompt_tokens = fixed
+            + latest_tokens
+            + document_tokens
+            + history_tokens[history_start..].iter().sum::<usize>();
+        ContextPlan {
+            include_static_context,
+            history_start,
+            documents,
+            latest,
+            prompt_tokens,
+            max_tokens: window.saturating_sub(prompt_tokens + reserved),
+        }
+    }
+
+    /// The messages of `plan`, see [`GptContext::plan`].
+    pub fn to_openai_chat_history(&self, plan: &ContextPlan) -> Vec<ChatCompletionRequestMessage> {
+        let mut chat: Vec<ChatCompletionRequestMessage> = Vec::new();
+        if let Some(prompt) = &self.system_prompt {
+            chat.push(
+                ChatCompletionRequestMessageArgs::default()
+                    .role(Role::System)
+                    .content(prompt)
+                    .build()
+                    .unwrap(),
+            );
+        }
+
+        if plan.include_static_context {
             for h in &self.static_context {
                 chat.push(
                     ChatCompletionRequestMessageArgs::default()
@@ -57,7 +193,8 @@ impl GptContext {
             }
         }
 
-        for h in &self.history {
+        let earlier = &self.history[..self.history.len().saturating_sub(1)];
+        for h in earlier.iter().skip(plan.history_start) {
             chat.push(
                 ChatCompletionRequestMessageArgs::default()
                     .role(Role::User)
@@ -68,26 +205,23 @@ impl GptContext {
             );
         }
 
-        chat.pop();
-
-        for h in &self.selected_embeddings {
+        for document in &plan.documents {
             chat.push(
                 ChatCompletionRequestMessageArgs::default()
                     .role(Role::User)
                     .name("documentation")
-                    .content(h.content.clone())
+                    .content(document)
                     .build()
                     .unwrap(),
             );
         }
 
-        if !self.history.is_empty() {
-            let last_history = self.history.last().unwrap();
+        if let Some((author, content)) = &plan.latest {
             chat.push(
                 ChatCompletionRequestMessageArgs::default()
                     .role(Role::User)
-                    .name(last_history.0.clone())
-                    .content(last_history.1.clone())
+                    .name(author.clone())
+                    .content(content.clone())
                     .build()
                     .unwrap(),
             );
@@ -96,22 +230,6 @@ impl GptContext {
         chat
     }
 
-    pub fn manage_tokens(&mut self, model: &str) {
-        let mut token_count =
-            get_chat_completion_max_tokens(model, &self.to_openai_chat_history(true))
-                .expect("Failed to get max tokens");
-        while token_count < 750 {
-            info!("Reached max token count, removing oldest message from context");
-            assert!(
-                !self.history.is_empty(),
-                "History is empty but token count was reached"
-            );
-            self.history.remove(0);
-            token_count = get_chat_completion_max_tokens(model, &self.to_openai_chat_history(true))
-                .expect("Failed to get max tokens");
-        }
-    }
-
     pub fn clear_embeddings(&mut self) {
         self.embeddings.clear();
     }
@@ -119,11 +237,15 @@ impl GptContext {
     pub fn calculate_tokens(&self, model: &str) -> usize {
         let mut tokens = 0;
         let bpe = get_bpe_from_model(model).unwrap();
+        if let Some(prompt) = &self.system_prompt {
+            tokens += bpe.encode_ordinary(prompt).len();
+        }
+
         for h in &self.static_context {
             tokens += bpe.encode_ordinary(h).len();
         }
 
-        for h in &self.embeddings {
+        for h in self.embeddings.embeddings() {
             tokens += bpe.encode_ordinary(&h.content).len();
         }
 
@@ -134,3 +256,127 @@ impl GptContext {
         tokens
     }
 }
+
+/// Counts tokens the way the model's tokenizer does, or estimates them when it can't be loaded.
+struct TokenCounter(Option<CoreBPE>);
+
+impl TokenCounter {
+    fn new(model: &str) -> TokenCounter {
+        TokenCounter(get_bpe_from_model(model).or_else(|_| cl100k_base()).ok())
+    }
+
+    fn count(&self, text: &str) -> usize {
+        match &self.0 {
+            Some(bpe) => bpe.encode_ordinary(text).len(),
+            None => (text.chars().count() + 3) / 4,
+        }
+    }
+
+    fn message(&self, name: Option<&str>, content: &str) -> usize {
+        MESSAGE_TOKENS + name.map_or(0, |name| self.count(name) + 1) + self.count(content)
+    }
+
+    /// Cuts `text` to at most `tokens` tokens.
+    fn truncate(&self, text: &str, tokens: usize) -> String {
+        let mut text = text.to_owned();
+        loop {
+            let count = self.count(&text);
+            if count <= tokens {
+                return text;
+            }
+            // shrink proportionally, a bit more each round so it always ends
+            let chars = text.chars().count();
+            let keep = (chars * tokens / count).min(chars.saturating_sub(chars / 10 + 1));
+            text = text.chars().take(keep).collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(content: &str) -> Embedding {
+        Embedding {
+            vector: Vec::new(),
+            graph_vertex: String::new(),
+            content: content.to_owned(),
+        }
+    }
+
+    #[test]
+    fn system_prompt_first() {
+        let mut context = GptContext::new();
+        context.static_context = vec!["static".to_owned()];
+        context.push_history(("user".to_owned(), "hello".to_owned()));
+        let plan = context.plan("gpt-3.5-turbo", 4096, 0, false);
+        assert_eq!(context.to_openai_chat_history(&plan).len(), 1);
+
+        context.system_prompt = Some("Answer like a pirate".to_owned());
+        for include_static_context in [false, true] {
+            let plan = context.plan("gpt-3.5-turbo", 4096, 0, include_static_context);
+            let chat = context.to_openai_chat_history(&plan);
+            assert_eq!(chat[0].role, Role::System);
+            assert_eq!(chat[0].content, "Answer like a pirate");
+        }
+    }
+
+    #[test]
+    fn plan_budget() {
+        let mut context = GptContext::new();
+        context.system_prompt = Some("Answer like a pirate".to_owned());
+        for i in 0..50 {
+            context.push_history(("user".to_owned(), format!("message {i} ").repeat(20)));
+        }
+        context.selected_embeddings = vec![
+            embedding(&"least relevant ".repeat(200)),
+            embedding(&"most relevant ".repeat(200)),
+        ];
+
+        // everything fits
+        let plan = context.plan("gpt-3.5-turbo", 32768, 0, false);
+        assert_eq!(plan.history_start, 0);
+        assert_eq!(plan.documents.len(), 2);
+        assert_eq!(
+            context.to_openai_chat_history(&plan).len(),
+            1 + context.history.len() + 2
+        );
+        assert_eq!(plan.max_tokens, 32768 - plan.prompt_tokens);
+
+        // the least relevant document goes first, then the oldest history
+        let plan = context.plan("gpt-3.5-turbo", 2048, 100, false);
+        assert_eq!(plan.documents, vec!["most relevant ".repeat(200)]);
+        assert!(plan.history_start > 0);
+        assert_eq!(plan.latest, context.history.last().cloned());
+        assert!(plan.max_tokens >= RESPONSE_TOKENS);
+        assert_eq!(plan.prompt_tokens + plan.max_tokens + 100, 2048);
+        let chat = context.to_openai_chat_history(&plan);
+        assert_eq!(
+            chat.len(),
+            1 + context.history.len() - plan.history_start + 1
+        );
+        assert_eq!(chat[chat.len() - 2].name.as_deref(), Some("documentation"));
+
+        // documents are cut to fit, but keep their share against a long history
+        let plan = context.plan("gpt-3.5-turbo", 1500, 0, false);
+        assert_eq!(plan.documents.len(), 1);
+        assert!(plan.documents[0].len() < "most relevant ".repeat(200).len());
+        assert!(plan.documents[0].starts_with("most relevant"));
+
+        // a window too small for anything still gives a plan
+        let plan = context.plan("gpt-3.5-turbo", 10, 0, true);
+        assert!(plan.documents.is_empty());
+        assert_eq!(plan.history_start, context.history.len() - 1);
+        assert_eq!(plan.max_tokens, 0);
+    }
+
+    #[test]
+    fn history_is_capped() {
+        let mut context = GptContext::new();
+        for i in 0..MAX_HISTORY + 5 {
+            context.push_history(("user".to_owned(), i.to_string()));
+        }
+        assert_eq!(context.history.len(), MAX_HISTORY);
+        assert_eq!(context.history[0].1, "5");
+    }
+}
@@ -0,0 +1,430 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Default maximum number of lines in a chunk.
+pub const MAX_CHUNK_LINES: usize = 40;
+/// Default number of lines a chunk repeats from the end of the previous one.
+pub const CHUNK_OVERLAP_LINES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    TypeScript,
+    Go,
+    Markdown,
+    Plain,
+}
+
+impl Language {
+    pub fn from_path(path: &str) -> Language {
+        let extension = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+        match extension.to_lowercase().as_str() {
+            "rs" => Language::Rust,
+            "py" | "pyi" => Language::Python,
+            "ts" | "tsx" | "js" | "jsx" | "mjs" | "cjs" => Language::TypeScript,
+            "go" => Language::Go,
+            "md" | "markdown" => Language::Markdown,
+            _ => Language::Plain,
+        }
+    }
+
+    fn symbol_separator(&self) -> &'static str {
+        match self {
+            Language::Rust => "::",
+            _ => ".",
+        }
+    }
+}
+
+/// A consecutive range of lines of a file, line numbers start at 1 and are inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeChunk {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub symbol: Option<String>,
+    pub content: String,
+}
+
+impl CodeChunk {
+    /// `path:start-end`, the way answers should cite the chunk.
+    pub fn citation(&self) -> String {
+        format!("{}:{}-{}", self.path, self.start_line, self.end_line)
+    }
+
+    /// Line anchor understood by GitHub, e.g. `#L120-L180`.
+    pub fn line_anchor(&self) -> String {
+        format!("#L{}-L{}", self.start_line, self.end_line)
+    }
+
+    /// The chunk prefixed with its citation and symbol, this is what gets embedded.
+    pub fn with_header(&self) -> String {
+        match &self.symbol {
+            Some(symbol) => format!("{} ({})\n{}", self.citation(), symbol, self.content),
+            None => format!("{}\n{}", self.citation(), self.content),
+        }
+    }
+}
+
+/// Start of a syntactic unit the chunker prefers to split on.
+#[derive(Debug, PartialEq)]
+struct Boundary {
+    line: usize,
+    symbol: String,
+}
+
+static RUST_ITEM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|default|extern\s+"[^"]*")\s+)*(fn|struct|enum|union|trait|mod|type|macro_rules!)\s*([A-Za-z_][A-Za-z0-9_]*)"#).unwrap()
+});
+static RUST_IMPL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:unsafe\s+)?impl\b(?:\s*<.*?>)?\s+(.+)$").unwrap());
+static PYTHON_ITEM: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:async\s+)?(def|class)\s+([A-Za-z_][A-Za-z0-9_]*)").unwrap());
+static TS_ITEM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(function\*?|class|interface|enum|type|namespace)\s+([A-Za-z_$][A-Za-z0-9_$]*)").unwrap()
+});
+static TS_ARROW: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:export\s+)?(?:const|let)\s+([A-Za-z_$][A-Za-z0-9_$]*)\s*(?::[^=]+)?=\s*(?:async\s+)?(?:\([^)]*\)|[A-Za-z_$][A-Za-z0-9_$]*)\s*(?::[^=]+)?=>").unwrap()
+});
+static TS_METHOD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:(?:public|private|protected|static|readonly|async|override)\s+)*([A-Za-z_$][A-Za-z0-9_$]*)\s*(?:<[^>]*>)?\([^)]*\)\s*(?::[^{]*)?\{\s*$").unwrap()
+});
+static GO_FUNC: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^func\s+(?:\(\s*(?:[A-Za-z_][A-Za-z0-9_]*\s+)?\*?([A-Za-z_][A-Za-z0-9_]*)[^)]*\)\s*)?([A-Za-z_][A-Za-z0-9_]*)").unwrap()
+});
+static GO_TYPE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^type\s+([A-Za-z_][A-Za-z0-9_]*)").unwrap());
+static MARKDOWN_HEADING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(#{1,6})\s+(.+?)\s*#*\s*$").unwrap());
+
+const TS_KEYWORDS: &[&str] = &["if", "for", "while", "switch", "catch", "with", "return"];
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn find_boundary(language: Language, line: &str) -> Option<(String, bool)> {
+    let trimmed = line.trim();
+    match language {
+        Language::Rust => {
+            if let Some(captures) = RUST_IMPL.captures(trimmed) {
+                // `impl<T> Trait for Type<T> {` is named after the type
+                let target = captures[1].trim_end_matches('{').trim();
+                let target = target.split(" for ").last().unwrap_or(target);
+                let name: String = target
+                    .trim_start_matches('&')
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == ':')
+                    .collect();
+                return Some((name, true));
+            }
+            RUST_ITEM.captures(trimmed).map(|captures| {
+                let container = matches!(&captures[1], "mod" | "trait");
+                (captures[2].to_owned(), container)
+            })
+        }
+        Language::Python => PYTHON_ITEM
+            .captures(trimmed)
+            .map(|captures| (captures[2].to_owned(), &captures[1] == "class")),
+        Language::TypeScript => {
+            if let Some(captures) = TS_ITEM.captures(trimmed) {
+                let container = matches!(&captures[1], "class" | "namespace");
+                return Some((captures[2].to_owned(), container));
+            }
+            if let Some(captures) = TS_ARROW.captures(trimmed) {
+                return Some((captures[1].to_owned(), false));
+            }
+            if indentation(line) == 0 {
+                return None;
+            }
+            TS_METHOD
+                .captures(trimmed)
+                .filter(|captures| !TS_KEYWORDS.contains(&&captures[1]))
+                .map(|captures| (captures[1].to_owned(), false))
+        }
+        Language::Go => {
+            if let Some(captures) = GO_FUNC.captures(line) {
+                let symbol = match captures.get(1) {
+                    Some(receiver) => format!("{}.{}", receiver.as_str(), &captures[2]),
+                    None => captures[2].to_owned(),
+                };
+                return Some((symbol, false));
+            }
+            GO_TYPE
+                .captures(line)
+                .map(|captures| (captures[1].to_owned(), false))
+        }
+        Language::Markdown => MARKDOWN_HEADING
+            .captures(line)
+            .map(|captures| (captures[2].to_owned(), false)),
+        Language::Plain => None,
+    }
+}
+
+/// Whether a line belongs to the item below it, like doc comments, attributes and decorators.
+fn is_item_prefix(language: Language, line: &str) -> bool {
+    let trimmed = line.trim_start();
+    match language {
+        Language::Rust => trimmed.starts_with("///") || trimmed.starts_with("#["),
+        Language::Python => trimmed.starts_with('@'),
+        Language::TypeScript => {
+            trimmed.starts_with('@')
+                || trimmed.starts_with("/**")
+                || trimmed.starts_with("* ")
+                || trimmed.starts_with("*/")
+                || trimmed == "*"
+        }
+        Language::Go => trimmed.starts_with("//"),
+        Language::Markdown | Language::Plain => false,
+    }
+}
+
+fn find_boundaries(language: Language, lines: &[&str]) -> Vec<Boundary> {
+    let mut boundaries: Vec<Boundary> = Vec::new();
+    // open containers as (indent, symbol)
+    let mut containers: Vec<(usize, String)> = Vec::new();
+    let mut in_fence = false;
+
+    for (index, line) in lines.iter().enumerate() {
+        if language == Language::Markdown && line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence || line.trim().is_empty() {
+            continue;
+        }
+
+        let indent = indentation(line);
+        containers.retain(|(container_indent, _)| *container_indent < indent);
+
+        let Some((symbol, container)) = find_boundary(language, line) else {
+            continue;
+        };
+
+        let symbol = match containers.last() {
+            Some((_, parent)) => format!("{}{}{}", parent, language.symbol_separator(), symbol),
+            None => symbol,
+        };
+
+        // attach doc comments and attributes to the item they describe
+        let mut start = index;
+        while start > 0 && is_item_prefix(language, lines[start - 1]) {
+            start -= 1;
+        }
+        if let Some(previous) = boundaries.last() {
+            start = start.max(previous.line + 1);
+        }
+
+        if container {
+            containers.push((indent, symbol.clone()));
+        }
+        boundaries.push(Boundary {
+            line: start,
+            symbol,
+        });
+    }
+
+    boundaries
+}
+
+/// Splits a file into chunks along items, functions and headings.
+///
+/// Consecutive small items are merged up to `max_lines`, items longer than that are cut into
+/// windows. Every chunk but the first repeats the last `overlap` lines of the previous one.
+pub fn chunk_file(path: &str, content: &str, max_lines: usize, overlap: usize) -> Vec<CodeChunk> {
+    let language = Language::from_path(path);
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    let max_lines = max_lines.max(1);
+    let overlap = overlap.min(max_lines - 1);
+
+    // segments of (start, end exclusive, symbol)
+    let mut segments: Vec<(usize, usize, Option<String>)> = Vec::new();
+    let boundaries = find_boundaries(language, &lines);
+    let mut start = 0;
+    let mut symbol: Option<String> = None;
+    for boundary in boundaries {
+        if boundary.line > start {
+            segments.push((start, boundary.line, symbol.take()));
+        }
+        start = boundary.line;
+        symbol = Some(boundary.symbol);
+    }
+    segments.push((start, lines.len(), symbol));
+
+    // merge small neighbours, split large segments
+    let mut ranges: Vec<(usize, usize, Option<String>)> = Vec::new();
+    for (start, end, symbol) in segments {
+        match ranges.last_mut() {
+            Some(last) if last.1 == start && end - last.0 <= max_lines => {
+                last.1 = end;
+                if last.2.is_none() {
+                    last.2 = symbol;
+                }
+            }
+            _ => {
+                let mut window_start = start;
+                while window_start < end {
+                    let window_end = (window_start + max_lines).min(end);
+                    ranges.push((window_start, window_end, symbol.clone()));
+                    window_start = window_end;
+                }
+            }
+        }
+    }
+
+    ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, (start, end, symbol))| {
+            let start = if index == 0 {
+                start
+            } else {
+                start.saturating_sub(overlap)
+            };
+            let content = lines[start..end].join("\n");
+            if content.trim().is_empty() {
+                return None;
+            }
+            Some(CodeChunk {
+                path: path.to_owned(),
+                start_line: start + 1,
+                end_line: end,
+                symbol,
+                content,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUST_SOURCE: &str = r#"use std::fmt;
+
+/// A point.
+#[derive(Debug)]
+pub struct Point {
+    x: i32,
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.x)
+    }
+
+    pub async fn reset(&mut self) {
+        self.x = 0;
+    }
+}
+"#;
+
+    #[test]
+    fn language_from_path() {
+        assert_eq!(Language::from_path("src/main.rs"), Language::Rust);
+        assert_eq!(Language::from_path("web/App.tsx"), Language::TypeScript);
+        assert_eq!(Language::from_path("README.md"), Language::Markdown);
+        assert_eq!(Language::from_path("Makefile"), Language::Plain);
+    }
+
+    #[test]
+    fn rust_boundaries() {
+        let lines: Vec<&str> = RUST_SOURCE.lines().collect();
+        let boundaries: Vec<(usize, String)> = find_boundaries(Language::Rust, &lines)
+            .into_iter()
+            .map(|b| (b.line + 1, b.symbol))
+            .collect();
+        assert_eq!(
+            boundaries,
+            vec![
+                (3, "Point".to_owned()),
+                (9, "Point".to_owned()),
+                (10, "Point::fmt".to_owned()),
+                (14, "Point::reset".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_on_items() {
+        let chunks = chunk_file("src/point.rs", RUST_SOURCE, 6, 0);
+        let ranges: Vec<(usize, usize, Option<&str>)> = chunks
+            .iter()
+            .map(|c| (c.start_line, c.end_line, c.symbol.as_deref()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (1, 2, None),
+                (3, 8, Some("Point")),
+                (9, 13, Some("Point")),
+                (14, 17, Some("Point::reset")),
+            ]
+        );
+        // indentation is preserved
+        assert!(chunks[2].content.contains("\n        write!"));
+        assert_eq!(chunks[3].citation(), "src/point.rs:14-17");
+        assert_eq!(chunks[3].line_anchor(), "#L14-L17");
+    }
+
+    #[test]
+    fn overlap_and_windows() {
+        let content = (1..=25)
+            .map(|i| format!("line {i}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let chunks = chunk_file("notes.txt", &content, 10, 2);
+        let ranges: Vec<(usize, usize)> =
+            chunks.iter().map(|c| (c.start_line, c.end_line)).collect();
+        assert_eq!(ranges, vec![(1, 10), (9, 20), (19, 25)]);
+        assert!(chunks[1].content.starts_with("line 9\n"));
+    }
+
+    #[test]
+    fn python_and_go_symbols() {
+        let python = "import os\n\n\nclass Repo:\n    @property\n    def name(self):\n        return 'andrena'\n";
+        let lines: Vec<&str> = python.lines().collect();
+        let symbols: Vec<(usize, String)> = find_boundaries(Language::Python, &lines)
+            .into_iter()
+            .map(|b| (b.line + 1, b.symbol))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![(4, "Repo".to_owned()), (5, "Repo.name".to_owned())]
+        );
+
+        let go = "package main\n\n// Run starts the server.\nfunc (s *Server) Run() error {\n\treturn nil\n}\n";
+        let lines: Vec<&str> = go.lines().collect();
+        let boundaries = find_boundaries(Language::Go, &lines);
+        assert_eq!(boundaries[0].line + 1, 3);
+        assert_eq!(boundaries[0].symbol, "Server.Run");
+    }
+
+    #[test]
+    fn typescript_symbols() {
+        let ts = "export class Client {\n  constructor(private url: string) {\n  }\n\n  async fetch(id: number): Promise<Page> {\n    if (id) {\n    }\n  }\n}\n\nexport const parse = (raw: string) => raw.trim();\n";
+        let lines: Vec<&str> = ts.lines().collect();
+        let symbols: Vec<String> = find_boundaries(Language::TypeScript, &lines)
+            .into_iter()
+            .map(|b| b.symbol)
+            .collect();
+        assert_eq!(
+            symbols,
+            vec!["Client", "Client.constructor", "Client.fetch", "parse"]
+        );
+    }
+
+    #[test]
+    fn markdown_headings() {
+        let markdown =
+            "# Andrena\n\nIntro\n\n## Setup\n\n```sh\n# not a heading\n```\n\n## Usage\nRun it\n";
+        let chunks = chunk_file("README.md", markdown, 6, 0);
+        let symbols: Vec<Option<&str>> = chunks.iter().map(|c| c.symbol.as_deref()).collect();
+        assert_eq!(symbols, vec![Some("Andrena"), Some("Setup"), Some("Usage")]);
+        assert!(chunks[1].content.contains("# not a heading"));
+    }
+}
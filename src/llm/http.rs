@@ -0,0 +1,404 @@
+use async_openai::types::CreateChatCompletionRequest;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    Completion, CompletionBackend, CompletionError, FunctionCall, FunctionDefinition,
+    FunctionExchange,
+};
+
+/// Talks to any server implementing the OpenAI `/chat/completions` endpoint.
+pub struct HttpBackend {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: Option<String>,
+    functions: bool,
+}
+
+// only the fields we need, local servers tend to leave out the rest of the OpenAI schema
+#[derive(Deserialize)]
+struct HttpCompletionResponse {
+    choices: Vec<HttpChoice>,
+}
+
+#[derive(Deserialize)]
+struct HttpChoice {
+    message: HttpChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct HttpChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    function_call: Option<FunctionCall>,
+}
+
+#[derive(Deserialize)]
+struct HttpStreamChunk {
+    choices: Vec<HttpStreamChoice>,
+}
+
+#[derive(Deserialize)]
+struct HttpStreamChoice {
+    delta: HttpStreamDelta,
+}
+
+#[derive(Deserialize)]
+struct HttpStreamDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    function_call: Option<HttpFunctionCallDelta>,
+}
+
+#[derive(Deserialize)]
+struct HttpFunctionCallDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+/// Collects a completion from the server-sent events of a streamed response.
+#[derive(Default)]
+struct EventStream {
+    buffer: Vec<u8>,
+    content: String,
+    function_name: String,
+    function_arguments: String,
+    done: bool,
+}
+
+impl EventStream {
+    /// Feeds a chunk of the response body, returns the content deltas it completed.
+    fn feed(&mut self, bytes: &[u8]) -> Result<Vec<String>, CompletionError> {
+        self.buffer.extend_from_slice(bytes);
+        let mut deltas = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim();
+            if data == "[DONE]" {
+                self.done = true;
+                continue;
+            }
+
+            let chunk: HttpStreamChunk =
+                serde_json::from_str(data).map_err(|e| CompletionError::Request(e.to_string()))?;
+            for choice in chunk.choices {
+                if let Some(call) = choice.delta.function_call {
+                    self.function_name.push_str(&call.name.unwrap_or_default());
+                    self.function_arguments
+                        .push_str(&call.arguments.unwrap_or_default());
+                }
+                if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                    self.content.push_str(&content);
+                    deltas.push(content);
+                }
+            }
+        }
+        Ok(deltas)
+    }
+
+    fn finish(self) -> Result<Completion, CompletionError> {
+        if !self.function_name.is_empty() {
+            return Ok(Completion::FunctionCall(FunctionCall {
+                name: self.function_name,
+                arguments: self.function_arguments,
+            }));
+        }
+        match self.done || !self.content.is_empty() {
+            true => Ok(Completion::Message(self.content)),
+            false => Err(CompletionError::EmptyResponse),
+        }
+    }
+}
+
+impl HttpBackend {
+    pub fn new(base_url: String, api_key: Option<String>, model: Option<String>) -> HttpBackend {
+        HttpBackend {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            api_key,
+            model,
+            functions: false,
+        }
+    }
+
+    /// Sends function definitions along, most local servers reject requests containing them.
+    pub fn with_functions(mut self) -> HttpBackend {
+        self.functions = true;
+        self
+    }
+
+    fn post(&self, body: &serde_json::Value) -> reqwest::RequestBuilder {
+        let builder = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(body);
+        match &self.api_key {
+            Some(api_key) => builder.bearer_auth(api_key),
+            None => builder,
+        }
+    }
+
+    /// The request with the model override applied, functions are left out unless enabled.
+    fn body(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+    ) -> Result<serde_json::Value, CompletionError> {
+        let mut request = request;
+        if let Some(model) = &self.model {
+            request.model = model.clone();
+        }
+
+        let functions = if self.functions { functions } else { &[] };
+        request_body(request, functions, exchanges)
+    }
+
+    async fn send(&self, body: serde_json::Value) -> Result<Completion, CompletionError> {
+        let response = self
+            .post(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| CompletionError::Request(e.to_string()))?
+            .json::<HttpCompletionResponse>()
+            .await
+            .map_err(|e| CompletionError::Request(e.to_string()))?;
+
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .ok_or(CompletionError::EmptyResponse)?;
+        match (message.function_call, message.content) {
+            (Some(call), _) => Ok(Completion::FunctionCall(call)),
+            (None, Some(content)) => Ok(Completion::Message(content)),
+            (None, None) => Err(CompletionError::EmptyResponse),
+        }
+    }
+}
+
+/// The request as JSON, with the function exchanges appended as `assistant` and `function`
+/// messages.
+fn request_body(
+    request: CreateChatCompletionRequest,
+    functions: &[FunctionDefinition],
+    exchanges: &[FunctionExchange],
+) -> Result<serde_json::Value, CompletionError> {
+    let mut body =
+        serde_json::to_value(request).map_err(|e| CompletionError::Request(e.to_string()))?;
+    if let Some(messages) = body["messages"].as_array_mut() {
+        for exchange in exchanges {
+            messages.push(json!({
+                "role": "assistant",
+                "content": null,
+                "function_call": exchange.call,
+            }));
+            messages.push(json!({
+                "role": "function",
+                "name": exchange.call.name,
+                "content": exchange.result,
+            }));
+        }
+    }
+    if !functions.is_empty() {
+        body["functions"] = json!(functions);
+    }
+    Ok(body)
+}
+
+#[async_trait]
+impl CompletionBackend for HttpBackend {
+    async fn complete(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<String, CompletionError> {
+        match self.complete_with_functions(request, &[], &[]).await? {
+            Completion::Message(content) => Ok(content),
+            Completion::FunctionCall(_) => Err(CompletionError::EmptyResponse),
+        }
+    }
+
+    async fn complete_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+    ) -> Result<Completion, CompletionError> {
+        self.send(self.body(request, functions, exchanges)?).await
+    }
+
+    async fn stream_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+        deltas: UnboundedSender<String>,
+    ) -> Result<Completion, CompletionError> {
+        let mut body = self.body(request, functions, exchanges)?;
+        body["stream"] = json!(true);
+
+        let mut response = self
+            .post(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| CompletionError::Request(e.to_string()))?;
+
+        let mut events = EventStream::default();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| CompletionError::Request(e.to_string()))?
+        {
+            for delta in events.feed(&chunk)? {
+                let _ = deltas.send(delta);
+            }
+        }
+        events.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{
+        ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+    };
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn request() -> CreateChatCompletionRequest {
+        CreateChatCompletionRequestArgs::default()
+            .model("gpt-3.5-turbo")
+            .messages(vec![ChatCompletionRequestMessageArgs::default()
+                .role(Role::User)
+                .content("what is in the graph?")
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn function_calls() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "functions": [{"name": "lookup_graph"}],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{"message": {
+                    "role": "assistant",
+                    "content": null,
+                    "function_call": {"name": "lookup_graph", "arguments": "{\"id\":\"a\"}"},
+                }}],
+            })))
+            .mount(&server)
+            .await;
+
+        let functions = vec![FunctionDefinition {
+            name: "lookup_graph".to_owned(),
+            description: "Looks up a vertex".to_owned(),
+            parameters: json!({"type": "object", "properties": {}}),
+        }];
+        let backend = HttpBackend::new(server.uri(), None, None).with_functions();
+        let completion = backend
+            .complete_with_functions(request(), &functions, &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            completion,
+            Completion::FunctionCall(FunctionCall {
+                name: "lookup_graph".to_owned(),
+                arguments: "{\"id\":\"a\"}".to_owned(),
+            })
+        );
+
+        let exchanges = vec![FunctionExchange {
+            call: FunctionCall {
+                name: "lookup_graph".to_owned(),
+                arguments: "{}".to_owned(),
+            },
+            result: "nothing".to_owned(),
+        }];
+        let body = request_body(request(), &[], &exchanges).unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["function_call"]["name"], "lookup_graph");
+        assert_eq!(messages[2]["role"], "function");
+        assert_eq!(messages[2]["content"], "nothing");
+        assert!(body.get("functions").is_none());
+    }
+
+    #[tokio::test]
+    async fn streams_deltas() {
+        let server = MockServer::start().await;
+        let events = [
+            r#"data: {"choices":[{"delta":{"role":"assistant"}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":"Hel"}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":"lo ✓"}}]}"#,
+            "data: [DONE]",
+        ];
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                events.map(|e| format!("{e}\n\n")).concat(),
+                "text/event-stream",
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = HttpBackend::new(server.uri(), None, None);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let completion = backend
+            .stream_with_functions(request(), &[], &[], sender)
+            .await
+            .unwrap();
+        assert_eq!(completion, Completion::Message("Hello ✓".to_owned()));
+        assert_eq!(receiver.recv().await.unwrap(), "Hel");
+        assert_eq!(receiver.recv().await.unwrap(), "lo ✓");
+
+        // events split anywhere, also inside a character
+        let mut events = EventStream::default();
+        let call = |call: serde_json::Value| {
+            format!(
+                "data: {}\n\n",
+                json!({"choices": [{"delta": {"function_call": call}}]})
+            )
+        };
+        let body = [
+            call(json!({"name": "lookup_graph", "arguments": "{\"id"})),
+            call(json!({"arguments": "\":\"é\"}"})),
+            "data: [DONE]\n\n".to_owned(),
+        ]
+        .concat();
+        for chunk in body.as_bytes().chunks(7) {
+            assert!(events.feed(chunk).unwrap().is_empty());
+        }
+        assert_eq!(
+            events.finish().unwrap(),
+            Completion::FunctionCall(FunctionCall {
+                name: "lookup_graph".to_owned(),
+                arguments: "{\"id\":\"é\"}".to_owned(),
+            })
+        );
+    }
+}
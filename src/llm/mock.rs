@@ -0,0 +1,133 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use async_openai::types::CreateChatCompletionRequest;
+use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{Completion, CompletionBackend, CompletionError, FunctionDefinition, FunctionExchange};
+
+/// Deterministic in-process backend, replies with queued responses and echoes the last message
+/// or function result once those run out. Streams answers word by word.
+pub struct MockBackend {
+    responses: Mutex<VecDeque<Completion>>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend {
+            responses: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn with_responses(responses: Vec<String>) -> MockBackend {
+        MockBackend::with_completions(responses.into_iter().map(Completion::Message).collect())
+    }
+
+    /// Queued function calls stay queued until a request offers functions.
+    pub fn with_completions(completions: Vec<Completion>) -> MockBackend {
+        MockBackend {
+            responses: Mutex::new(completions.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl CompletionBackend for MockBackend {
+    async fn complete(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<String, CompletionError> {
+        match self.complete_with_functions(request, &[], &[]).await? {
+            Completion::Message(content) => Ok(content),
+            Completion::FunctionCall(_) => Err(CompletionError::EmptyResponse),
+        }
+    }
+
+    async fn complete_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+    ) -> Result<Completion, CompletionError> {
+        {
+            let mut responses = self.responses.lock().unwrap();
+            match responses.front() {
+                Some(Completion::FunctionCall(_)) if functions.is_empty() => {}
+                Some(_) => return Ok(responses.pop_front().unwrap()),
+                None => {}
+            }
+        }
+
+        if let Some(exchange) = exchanges.last() {
+            return Ok(Completion::Message(format!("echo: {}", exchange.result)));
+        }
+        request
+            .messages
+            .last()
+            .map(|message| Completion::Message(format!("echo: {}", message.content)))
+            .ok_or(CompletionError::EmptyResponse)
+    }
+
+    async fn stream_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+        deltas: UnboundedSender<String>,
+    ) -> Result<Completion, CompletionError> {
+        let completion = self
+            .complete_with_functions(request, functions, exchanges)
+            .await?;
+        if let Completion::Message(content) = &completion {
+            for word in content.split_inclusive(' ') {
+                let _ = deltas.send(word.to_owned());
+            }
+        }
+        Ok(completion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{
+        ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+    };
+
+    use super::*;
+
+    fn request(content: &str) -> CreateChatCompletionRequest {
+        CreateChatCompletionRequestArgs::default()
+            .model("mock")
+            .messages(vec![ChatCompletionRequestMessageArgs::default()
+                .role(Role::User)
+                .content(content)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn queued_then_echo() {
+        let backend = MockBackend::with_responses(vec!["first".to_owned()]);
+        assert_eq!(backend.complete(request("hi")).await.unwrap(), "first");
+        assert_eq!(backend.complete(request("hi")).await.unwrap(), "echo: hi");
+    }
+
+    #[tokio::test]
+    async fn streams_words() {
+        let backend = MockBackend::new();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let completion = backend
+            .stream_with_functions(request("hi there"), &[], &[], sender)
+            .await
+            .unwrap();
+        assert_eq!(completion, Completion::Message("echo: hi there".to_owned()));
+
+        let mut deltas = Vec::new();
+        while let Some(delta) = receiver.recv().await {
+            deltas.push(delta);
+        }
+        assert_eq!(deltas, vec!["echo: ", "hi ", "there"]);
+    }
+}
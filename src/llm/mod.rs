@@ -0,0 +1,174 @@
+use std::{env, fmt, sync::Arc};
+
+use async_openai::types::CreateChatCompletionRequest;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+pub mod http;
+pub mod mock;
+pub mod openai;
+
+pub use self::{http::HttpBackend, mock::MockBackend, openai::OpenAiBackend};
+
+/// Models prefixed with this are routed to an OpenAI compatible server such as llama.cpp or vLLM.
+pub const LOCAL_MODEL_PREFIX: &str = "local/";
+pub const MOCK_MODEL: &str = "mock";
+pub const OPENAI_MODELS: [&str; 3] = ["gpt-3.5-turbo", "gpt-4", "gpt-4-32k"];
+
+#[derive(Debug)]
+pub enum CompletionError {
+    Request(String),
+    EmptyResponse,
+}
+
+impl fmt::Display for CompletionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompletionError::Request(e) => write!(f, "request failed: {e}"),
+            CompletionError::EmptyResponse => write!(f, "no choices in response"),
+        }
+    }
+}
+
+impl std::error::Error for CompletionError {}
+
+/// A function the model may call, `parameters` is a JSON schema of its arguments.
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON object as written by the model, it is not guaranteed to be valid.
+    pub arguments: String,
+}
+
+/// A call the model made earlier in the conversation together with what the function returned.
+#[derive(Debug, Clone)]
+pub struct FunctionExchange {
+    pub call: FunctionCall,
+    pub result: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Completion {
+    Message(String),
+    FunctionCall(FunctionCall),
+}
+
+#[async_trait]
+pub trait CompletionBackend: Send + Sync {
+    async fn complete(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<String, CompletionError>;
+
+    /// Like [`CompletionBackend::complete`], but the model may answer with a call to one of
+    /// `functions`. The exchanges so far are replayed after the request messages. Backends
+    /// without function calling ignore the functions.
+    async fn complete_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        _functions: &[FunctionDefinition],
+        _exchanges: &[FunctionExchange],
+    ) -> Result<Completion, CompletionError> {
+        self.complete(request).await.map(Completion::Message)
+    }
+
+    /// Like [`CompletionBackend::complete_with_functions`], but sends the answer to `deltas`
+    /// piece by piece while it is generated. Backends that can't stream send it in one piece.
+    async fn stream_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+        deltas: UnboundedSender<String>,
+    ) -> Result<Completion, CompletionError> {
+        let completion = self
+            .complete_with_functions(request, functions, exchanges)
+            .await?;
+        if let Completion::Message(content) = &completion {
+            let _ = deltas.send(content.clone());
+        }
+        Ok(completion)
+    }
+}
+
+pub fn is_supported_model(model: &str) -> bool {
+    OPENAI_MODELS.contains(&model)
+        || model == MOCK_MODEL
+        || (model.starts_with(LOCAL_MODEL_PREFIX) && model.len() > LOCAL_MODEL_PREFIX.len())
+}
+
+/// Picks the backend that serves the given model name.
+pub fn backend_for_model(model: &str) -> Arc<dyn CompletionBackend> {
+    if model == MOCK_MODEL {
+        return Arc::new(MockBackend::new());
+    }
+
+    if let Some(local_model) = model.strip_prefix(LOCAL_MODEL_PREFIX) {
+        let base_url = env::var("LOCAL_LLM_URL").unwrap_or("http://localhost:8080/v1".to_owned());
+        return Arc::new(HttpBackend::new(
+            base_url,
+            env::var("LOCAL_LLM_API_KEY").ok(),
+            Some(local_model.to_owned()),
+        ));
+    }
+
+    Arc::new(OpenAiBackend::new())
+}
+
+/// Tokens the model reads and writes per request. Local models default to 4096, set
+/// `LOCAL_LLM_CONTEXT` for models with a larger window.
+pub fn context_window(model: &str) -> usize {
+    match model {
+        "gpt-4" => 8192,
+        "gpt-4-32k" => 32768,
+        _ if model.starts_with(LOCAL_MODEL_PREFIX) => env::var("LOCAL_LLM_CONTEXT")
+            .ok()
+            .and_then(|window| window.parse().ok())
+            .unwrap_or(4096),
+        _ => 4096,
+    }
+}
+
+/// tiktoken only knows OpenAI models, other models are approximated with the gpt-3.5 tokenizer.
+pub fn tokenizer_model(model: &str) -> &str {
+    if OPENAI_MODELS.contains(&model) {
+        model
+    } else {
+        "gpt-3.5-turbo"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_models() {
+        assert!(is_supported_model("gpt-4"));
+        assert!(is_supported_model("mock"));
+        assert!(is_supported_model("local/llama-2-13b"));
+        assert!(!is_supported_model("local/"));
+        assert!(!is_supported_model("gpt-5"));
+    }
+
+    #[test]
+    fn tokenizer_fallback() {
+        assert_eq!(tokenizer_model("gpt-4"), "gpt-4");
+        assert_eq!(tokenizer_model("local/llama-2-13b"), "gpt-3.5-turbo");
+    }
+
+    #[test]
+    fn context_windows() {
+        assert_eq!(context_window("gpt-3.5-turbo"), 4096);
+        assert_eq!(context_window("gpt-4-32k"), 32768);
+        assert_eq!(context_window(MOCK_MODEL), 4096);
+    }
+}
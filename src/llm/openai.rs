@@ -0,0 +1,81 @@
+use std::env;
+
+use async_openai::{types::CreateChatCompletionRequest, Client};
+use async_trait::async_trait;
+use log::debug;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    http::HttpBackend, Completion, CompletionBackend, CompletionError, FunctionDefinition,
+    FunctionExchange,
+};
+
+const OPENAI_API_URL: &str = "https://api.openai.com/v1";
+
+pub struct OpenAiBackend {
+    client: Client,
+    // async-openai does not know function calling yet, those requests and streams go through
+    // plain http
+    http: HttpBackend,
+}
+
+impl OpenAiBackend {
+    pub fn new() -> OpenAiBackend {
+        let api_key = env::var("OPENAI_API_KEY").unwrap_or_default();
+        OpenAiBackend {
+            client: Client::new().with_api_key(&api_key),
+            http: HttpBackend::new(OPENAI_API_URL.to_owned(), Some(api_key), None).with_functions(),
+        }
+    }
+}
+
+#[async_trait]
+impl CompletionBackend for OpenAiBackend {
+    async fn complete(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<String, CompletionError> {
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(|e| CompletionError::Request(e.to_string()))?;
+
+        if let Some(usage) = response.usage {
+            debug!("tokens: {}", usage.total_tokens);
+        }
+
+        response
+            .choices
+            .first()
+            .map(|choice| choice.message.content.clone())
+            .ok_or(CompletionError::EmptyResponse)
+    }
+
+    async fn complete_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+    ) -> Result<Completion, CompletionError> {
+        if functions.is_empty() && exchanges.is_empty() {
+            return self.complete(request).await.map(Completion::Message);
+        }
+        self.http
+            .complete_with_functions(request, functions, exchanges)
+            .await
+    }
+
+    async fn stream_with_functions(
+        &self,
+        request: CreateChatCompletionRequest,
+        functions: &[FunctionDefinition],
+        exchanges: &[FunctionExchange],
+        deltas: UnboundedSender<String>,
+    ) -> Result<Completion, CompletionError> {
+        self.http
+            .stream_with_functions(request, functions, exchanges, deltas)
+            .await
+    }
+}
@@ -2,7 +2,7 @@
 #![deny(unsafe_code)]
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     sync::{Arc, Mutex},
 };
 
@@ -11,37 +11,122 @@ use actors::{
     channel_sup::{ChannelSupervisor, ChannelSupervisorMessage},
     communication::discord::DiscordActor,
 };
-use atlas_confluence::Session;
 use graph::{Edge, Graph, Vertex};
-use log::{debug, error, info, warn};
+use log::{debug, error, info};
 use once_cell::sync::Lazy;
 use ractor::{call, Actor, ActorRef};
-use regex::Regex;
-use rocket::{http::Method, serde::json::Json};
+use rocket::{
+    http::{Method, Status},
+    serde::json::Json,
+};
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method as CorsMethod};
+use serde::{Deserialize, Serialize};
 use serenity::futures::StreamExt;
 use tokio::net::TcpListener;
 
 mod actors;
 mod ai_context;
+mod chunking;
 mod graph;
+mod llm;
+mod personas;
+mod store;
+mod vector_index;
 
 #[macro_use]
 extern crate rocket;
 
-#[get("/channel/<id>")]
-async fn channel(id: u64) -> Json<Vec<(String, String)>> {
+async fn fetch_channel(id: u64) -> ActorRef<ChannelMessage> {
     let channel_registry: ActorRef<ChannelSupervisorMessage> =
         ractor::registry::where_is("channel_sup".to_owned())
             .unwrap()
             .into();
 
-    let channel = call!(channel_registry, ChannelSupervisorMessage::FetchChannel, id).unwrap();
+    call!(channel_registry, ChannelSupervisorMessage::FetchChannel, id).unwrap()
+}
+
+#[get("/channel/<id>")]
+async fn channel(id: u64) -> Json<Vec<(String, String)>> {
+    let channel = fetch_channel(id).await;
     let history = call!(channel, ChannelMessage::GetHistory).unwrap();
 
     Json(history)
 }
 
+#[derive(Serialize)]
+struct SystemPrompt {
+    prompt: Option<String>,
+}
+
+/// Either a prompt or the name of a preset from the personas file.
+#[derive(Deserialize)]
+struct SystemPromptUpdate {
+    prompt: Option<String>,
+    preset: Option<String>,
+}
+
+#[get("/channel/<id>/persona")]
+async fn get_persona(id: u64) -> Json<SystemPrompt> {
+    let channel = fetch_channel(id).await;
+    let prompt = call!(channel, ChannelMessage::GetSystemPrompt).unwrap();
+
+    Json(SystemPrompt { prompt })
+}
+
+#[post("/channel/<id>/persona", data = "<update>")]
+async fn set_persona(
+    id: u64,
+    update: Json<SystemPromptUpdate>,
+) -> Result<Json<SystemPrompt>, (Status, String)> {
+    let prompt = match update.into_inner() {
+        SystemPromptUpdate {
+            prompt: Some(prompt),
+            preset: None,
+        } => prompt,
+        SystemPromptUpdate {
+            prompt: None,
+            preset: Some(preset),
+        } => match personas::preset(&preset) {
+            Ok(persona) => persona.prompt,
+            Err(e @ personas::PersonaError::UnknownPreset(_)) => {
+                return Err((Status::NotFound, e.to_string()))
+            }
+            Err(e) => return Err((Status::InternalServerError, e.to_string())),
+        },
+        _ => {
+            return Err((
+                Status::BadRequest,
+                "expected either a prompt or a preset".to_owned(),
+            ))
+        }
+    };
+
+    let channel = fetch_channel(id).await;
+    channel
+        .send_message(ChannelMessage::SetSystemPrompt(Some(prompt.clone())))
+        .unwrap();
+    Ok(Json(SystemPrompt {
+        prompt: Some(prompt),
+    }))
+}
+
+#[delete("/channel/<id>/persona")]
+async fn reset_persona(id: u64) -> Json<SystemPrompt> {
+    let channel = fetch_channel(id).await;
+    channel
+        .send_message(ChannelMessage::SetSystemPrompt(None))
+        .unwrap();
+
+    Json(SystemPrompt { prompt: None })
+}
+
+#[get("/personas")]
+async fn list_personas() -> Result<Json<Vec<personas::Persona>>, (Status, String)> {
+    personas::presets()
+        .map(Json)
+        .map_err(|e| (Status::InternalServerError, e.to_string()))
+}
+
 static GRAPH: Lazy<Arc<Mutex<Graph>>> = Lazy::new(|| Arc::new(Mutex::new(Graph::new())));
 
 #[get("/graph/vertices")]
@@ -115,10 +200,16 @@ async fn main() {
         let cors = CorsOptions::default()
             .allowed_origins(AllowedOrigins::all())
             .allowed_methods(
-                vec![Method::Get, Method::Post, Method::Patch, Method::Options]
-                    .into_iter()
-                    .map(|m| m.into())
-                    .collect::<HashSet<CorsMethod>>(),
+                vec![
+                    Method::Get,
+                    Method::Post,
+                    Method::Patch,
+                    Method::Delete,
+                    Method::Options,
+                ]
+                .into_iter()
+                .map(|m| m.into())
+                .collect::<HashSet<CorsMethod>>(),
             )
             .allowed_headers(AllowedHeaders::all())
             .allow_credentials(true)
@@ -126,7 +217,18 @@ async fn main() {
             .expect("Failed to build CORS");
 
         rocket::build()
-            .mount("/", routes![channel, graph_nodes, graph_edges])
+            .mount(
+                "/",
+                routes![
+                    channel,
+                    get_persona,
+                    set_persona,
+                    reset_persona,
+                    list_personas,
+                    graph_nodes,
+                    graph_edges
+                ],
+            )
             .mount("/", rocket_cors::catch_all_options_routes())
             .attach(cors)
             .launch()
@@ -134,109 +236,17 @@ async fn main() {
             .unwrap();
     });
 
-    extract_confluence().await;
-
     tokio::signal::ctrl_c()
         .await
         .expect("Failed to listen for ctrl-c");
 }
 
-async fn extract_confluence() {
-    let session = Session::new(
-        "hannah.witvrouwen@external.engie.com".to_string(),
-        "".to_string(),
-        "https://laborelec.atlassian.net/wiki".to_string(),
-    );
-
-    let spaces = session.get_spaces().await.expect("Failed to get spaces");
-    for space in spaces {
-        let pages = session
-            .get_pages_for_space(&space.key, None)
-            .await
-            .expect("Failed to get pages");
-
-        info!(
-            "Space({:?}): {:?} with {} pages",
-            space.key,
-            space.name,
-            pages.len()
-        );
-
-        for page in pages {
-            let mut graph = GRAPH.lock().unwrap();
-            let md = html2md::parse_html(&page.body.unwrap().view.unwrap().value);
-
-            // replace relative links with absolute links
-            let md = md.replace("(/wiki/", "(https://laborelec.atlassian.net/wiki/");
-
-            let regex =
-                Regex::new(r"(?m)\(https://laborelec\.atlassian\.net/wiki/.*/pages/(\d+)/?.*\)")
-                    .unwrap();
-            let result = regex.captures_iter(&md);
-            let link = page.links.clone()._self;
-            if page.children.is_some() {
-                for child in &page.children.unwrap().page.results {
-                    let link_to = format!(
-                        "https://laborelec.atlassian.net/wiki/rest/api/content/{}",
-                        child.id
-                    );
-                    info!("Child Link: {:?} -> {:?}", link, link_to);
-                    graph.add_edge(link.clone(), "child of".to_owned(), link_to)
-                }
-            }
-
-            for cap in result {
-                let id = cap.get(1).unwrap().as_str();
-                let link_to = format!(
-                    "https://laborelec.atlassian.net/wiki/rest/api/content/{}",
-                    id
-                );
-
-                info!("Link: {:?} -> {:?}", link, link_to);
-                graph.add_edge(link.clone(), "links to".to_owned(), link_to)
-            }
-
-            let mut metadata = HashMap::new();
-            metadata.insert("title".to_owned(), page.title.clone());
-            metadata.insert("space".to_owned(), space.name.clone());
-            metadata.insert("space_key".to_owned(), space.key.clone());
-            metadata.insert("id".to_owned(), page.id.clone());
-            metadata.insert("content".to_owned(), md.clone());
-
-            if page.links.clone().webui.is_some() {
-                let source = format!(
-                    "https://laborelec.atlassian.net/wiki{}",
-                    page.links.clone().webui.unwrap()
-                );
-                metadata.insert("source".to_owned(), source);
-            } else {
-                trace!("No source for page {:?}", page.id);
-                trace!("Links: {:?}", page.links);
-            }
-
-            graph.add_or_replace_vertex(link, metadata);
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
-    use regex::Regex;
-
     #[ctor::ctor]
     fn init() {
         pretty_env_logger::formatted_builder()
             // .filter(Some("andrena"), log::LevelFilter::Trace)
             .init();
     }
-
-    #[test]
-    fn match_url() {
-        let regex =
-            Regex::new(r"(?m)\(https://laborelec\.atlassian\.net/wiki/.*/pages/(\d+)/?.*\)")
-                .unwrap();
-        let test = "[](https://laborelec.atlassian.net/wiki/spaces/EP/pages/110985217/Proposed+common+solution+for+public+interface+of+transverse+components)";
-        let mut result = regex.captures_iter(test);
-        assert_eq!(result.next().unwrap().get(1).unwrap().as_str(), "110985217")
-    }
 }
@@ -0,0 +1,100 @@
+use std::{env, fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// Read when `PERSONAS_FILE` is not set.
+pub const DEFAULT_PERSONAS_FILE: &str = "personas.json";
+
+/// A named system prompt channels can switch to with `!persona use <name>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Persona {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub prompt: String,
+}
+
+#[derive(Debug)]
+pub enum PersonaError {
+    Io(String, io::Error),
+    Parse(String, serde_json::Error),
+    UnknownPreset(String),
+}
+
+impl fmt::Display for PersonaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersonaError::Io(path, e) => write!(f, "failed to read {path}: {e}"),
+            PersonaError::Parse(path, e) => write!(f, "invalid personas in {path}: {e}"),
+            PersonaError::UnknownPreset(name) => write!(f, "unknown persona {name}"),
+        }
+    }
+}
+
+impl std::error::Error for PersonaError {}
+
+/// The presets of the configured personas file, read on every call so edits apply without a
+/// restart.
+pub fn presets() -> Result<Vec<Persona>, PersonaError> {
+    let path = env::var("PERSONAS_FILE").unwrap_or(DEFAULT_PERSONAS_FILE.to_owned());
+    read_presets(Path::new(&path))
+}
+
+pub fn preset(name: &str) -> Result<Persona, PersonaError> {
+    presets()?
+        .into_iter()
+        .find(|persona| persona.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| PersonaError::UnknownPreset(name.to_owned()))
+}
+
+/// A missing file means there are no presets.
+pub fn read_presets(path: &Path) -> Result<Vec<Persona>, PersonaError> {
+    let display = path.display().to_string();
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| PersonaError::Parse(display, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(PersonaError::Io(display, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() {
+        let dir = env::temp_dir().join(format!("andrena-personas-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("personas.json");
+        assert_eq!(read_presets(&path).unwrap(), Vec::new());
+
+        fs::write(
+            &path,
+            r#"[{"name": "pirate", "prompt": "Answer like a pirate"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_presets(&path).unwrap(),
+            vec![Persona {
+                name: "pirate".to_owned(),
+                description: String::new(),
+                prompt: "Answer like a pirate".to_owned(),
+            }]
+        );
+
+        fs::write(&path, r#"[{"name": "pirate"}]"#).unwrap();
+        assert!(matches!(
+            read_presets(&path),
+            Err(PersonaError::Parse(_, _))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bundled_presets() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_PERSONAS_FILE);
+        assert!(!read_presets(&path).unwrap().is_empty());
+    }
+}
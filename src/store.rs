@@ -0,0 +1,128 @@
+use std::{env, fmt};
+
+use log::info;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+
+static DB: Lazy<sled::Db> = Lazy::new(|| {
+    if cfg!(test) {
+        return sled::Config::new()
+            .temporary(true)
+            .open()
+            .expect("Failed to open temporary database");
+    }
+
+    let path = env::var("ANDRENA_DB_PATH").unwrap_or("andrena.db".to_owned());
+    info!("Opening database at {}", path);
+    sled::open(path).expect("Failed to open database")
+});
+
+#[derive(Debug)]
+pub enum StoreError {
+    Database(sled::Error),
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Database(e) => write!(f, "database error: {e}"),
+            StoreError::Serialization(e) => write!(f, "serialization error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<sled::Error> for StoreError {
+    fn from(e: sled::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serialization(e)
+    }
+}
+
+/// A named collection of JSON serialized values inside the shared sled database.
+#[derive(Clone)]
+pub struct Store {
+    tree: sled::Tree,
+}
+
+impl Store {
+    pub fn open(name: &str) -> Result<Store, StoreError> {
+        Ok(Store {
+            tree: DB.open_tree(name)?,
+        })
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: impl AsRef<[u8]>) -> Result<Option<T>, StoreError> {
+        match self.tree.get(key)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert<T: Serialize>(&self, key: impl AsRef<[u8]>, value: &T) -> Result<(), StoreError> {
+        let bytes = serde_json::to_vec(value)?;
+        self.tree.insert(key.as_ref(), bytes)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: impl AsRef<[u8]>) -> Result<(), StoreError> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    pub fn scan_prefix<T: DeserializeOwned>(
+        &self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<Vec<(Vec<u8>, T)>, StoreError> {
+        let mut entries = Vec::new();
+        for entry in self.tree.scan_prefix(prefix) {
+            let (key, value) = entry?;
+            entries.push((key.to_vec(), serde_json::from_slice(&value)?));
+        }
+        Ok(entries)
+    }
+
+    pub fn keys(&self) -> Result<Vec<Vec<u8>>, StoreError> {
+        let mut keys = Vec::new();
+        for key in self.tree.iter().keys() {
+            keys.push(key?.to_vec());
+        }
+        Ok(keys)
+    }
+
+    pub async fn flush(&self) -> Result<(), StoreError> {
+        self.tree.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let store = Store::open("test_roundtrip").unwrap();
+        store
+            .insert("key", &vec!["a".to_owned(), "b".to_owned()])
+            .unwrap();
+
+        let value: Option<Vec<String>> = store.get("key").unwrap();
+        assert_eq!(value.unwrap(), vec!["a", "b"]);
+
+        store.insert("other", &vec!["c".to_owned()]).unwrap();
+        let entries: Vec<(Vec<u8>, Vec<String>)> = store.scan_prefix("ke").unwrap();
+        assert_eq!(entries.len(), 1);
+
+        store.remove("key").unwrap();
+        let value: Option<Vec<String>> = store.get("key").unwrap();
+        assert!(value.is_none());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::actors::tools::embeddings::Embedding;
+
+use super::{cosine_dist, VectorIndex};
+
+/// Exact index that compares the query against every stored embedding.
+#[derive(Default)]
+pub struct BruteForceIndex {
+    embeddings: Vec<Embedding>,
+}
+
+impl BruteForceIndex {
+    pub fn new() -> BruteForceIndex {
+        BruteForceIndex::default()
+    }
+}
+
+impl VectorIndex for BruteForceIndex {
+    fn insert(&mut self, embedding: Embedding) {
+        self.embeddings.push(embedding);
+    }
+
+    fn remove_source(&mut self, source: &str) -> usize {
+        let before = self.embeddings.len();
+        self.embeddings.retain(|e| e.source() != source);
+        before - self.embeddings.len()
+    }
+
+    fn query(&self, vector: &[f32], k: usize) -> Vec<(&Embedding, f32)> {
+        let mut sorted: Vec<(&Embedding, f32)> = self
+            .embeddings
+            .iter()
+            .map(|e| (e, cosine_dist(&e.vector, vector)))
+            .collect();
+        sorted.sort_by(|a, b| a.1.total_cmp(&b.1));
+        sorted.truncate(k);
+        sorted
+    }
+
+    fn embeddings(&self) -> Vec<&Embedding> {
+        self.embeddings.iter().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    fn clear(&mut self) {
+        self.embeddings.clear();
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashSet},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::actors::tools::embeddings::Embedding;
+
+use super::{cosine_dist, VectorIndex};
+
+/// Maximum number of neighbours per node on the upper layers, layer 0 keeps twice as many.
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 100;
+const EF_SEARCH: usize = 64;
+
+/// Hierarchical navigable small world graph (Malkov & Yashunin, 2016).
+///
+/// Removed embeddings are tombstoned so the graph stays navigable, the graph is rebuilt once
+/// more than half of the nodes are tombstones.
+pub struct HnswIndex {
+    nodes: Vec<Node>,
+    entry_point: Option<usize>,
+    max_level: usize,
+    removed: usize,
+    level_multiplier: f64,
+    rng: StdRng,
+}
+
+struct Node {
+    embedding: Embedding,
+    neighbours: Vec<Vec<usize>>,
+    removed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    id: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        HnswIndex {
+            nodes: Vec::new(),
+            entry_point: None,
+            max_level: 0,
+            removed: 0,
+            level_multiplier: 1.0 / (M as f64).ln(),
+            // seeded so an index built from the same embeddings always has the same shape
+            rng: StdRng::seed_from_u64(0x5eed),
+        }
+    }
+}
+
+impl HnswIndex {
+    pub fn new() -> HnswIndex {
+        HnswIndex::default()
+    }
+
+    fn distance(&self, vector: &[f32], id: usize) -> f32 {
+        cosine_dist(vector, &self.nodes[id].embedding.vector)
+    }
+
+    fn random_level(&mut self) -> usize {
+        let uniform: f64 = self.rng.gen_range(f64::EPSILON..1.0);
+        (-uniform.ln() * self.level_multiplier).floor() as usize
+    }
+
+    fn max_neighbours(level: usize) -> usize {
+        if level == 0 {
+            M * 2
+        } else {
+            M
+        }
+    }
+
+    /// Returns up to `ef` candidates closest to `vector` on `level`, sorted closest first.
+    fn search_level(
+        &self,
+        vector: &[f32],
+        entry_points: &[Candidate],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entry_points.iter().map(|c| c.id).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> =
+            entry_points.iter().copied().map(Reverse).collect();
+        let mut results: BinaryHeap<Candidate> = entry_points.iter().copied().collect();
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = results.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+            if candidate.distance > furthest && results.len() >= ef {
+                break;
+            }
+
+            for &neighbour in &self.nodes[candidate.id].neighbours[level] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+
+                let distance = self.distance(vector, neighbour);
+                let furthest = results.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+                if results.len() < ef || distance < furthest {
+                    let next = Candidate {
+                        distance,
+                        id: neighbour,
+                    };
+                    candidates.push(Reverse(next));
+                    results.push(next);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+
+    fn connect(&mut self, from: usize, to: usize, level: usize) {
+        self.nodes[from].neighbours[level].push(to);
+        if self.nodes[from].neighbours[level].len() <= Self::max_neighbours(level) {
+            return;
+        }
+
+        // too many connections, keep the closest ones
+        let vector = self.nodes[from].embedding.vector.clone();
+        let mut neighbours: Vec<Candidate> = self.nodes[from].neighbours[level]
+            .iter()
+            .map(|&id| Candidate {
+                distance: self.distance(&vector, id),
+                id,
+            })
+            .collect();
+        neighbours.sort();
+        neighbours.truncate(Self::max_neighbours(level));
+        self.nodes[from].neighbours[level] = neighbours.into_iter().map(|c| c.id).collect();
+    }
+
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.entry_point = None;
+        self.max_level = 0;
+        self.removed = 0;
+        for node in nodes.into_iter().filter(|n| !n.removed) {
+            self.insert(node.embedding);
+        }
+    }
+}
+
+impl VectorIndex for HnswIndex {
+    fn insert(&mut self, embedding: Embedding) {
+        let level = self.random_level();
+        let id = self.nodes.len();
+        let vector = embedding.vector.clone();
+        self.nodes.push(Node {
+            embedding,
+            neighbours: vec![Vec::new(); level + 1],
+            removed: false,
+        });
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(id);
+            self.max_level = level;
+            return;
+        };
+
+        let mut entry_points = vec![Candidate {
+            distance: self.distance(&vector, entry_point),
+            id: entry_point,
+        }];
+
+        for current in (level + 1..=self.max_level).rev() {
+            entry_points = self.search_level(&vector, &entry_points, 1, current);
+        }
+
+        for current in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_level(&vector, &entry_points, EF_CONSTRUCTION, current);
+            for neighbour in candidates.iter().take(Self::max_neighbours(current)) {
+                self.nodes[id].neighbours[current].push(neighbour.id);
+                self.connect(neighbour.id, id, current);
+            }
+            entry_points = candidates;
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(id);
+        }
+    }
+
+    fn remove_source(&mut self, source: &str) -> usize {
+        let mut count = 0;
+        for node in self.nodes.iter_mut() {
+            if !node.removed && node.embedding.source() == source {
+                node.removed = true;
+                count += 1;
+            }
+        }
+
+        self.removed += count;
+        if self.removed * 2 > self.nodes.len() {
+            self.rebuild();
+        }
+
+        count
+    }
+
+    fn query(&self, vector: &[f32], k: usize) -> Vec<(&Embedding, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+
+        let mut entry_points = vec![Candidate {
+            distance: self.distance(vector, entry_point),
+            id: entry_point,
+        }];
+
+        for level in (1..=self.max_level).rev() {
+            entry_points = self.search_level(vector, &entry_points, 1, level);
+        }
+
+        // tombstones still take up room in the result set, widen the search to make up for them
+        let ef = (k + self.removed).max(EF_SEARCH);
+        self.search_level(vector, &entry_points, ef, 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.id].removed)
+            .take(k)
+            .map(|c| (&self.nodes[c.id].embedding, c.distance))
+            .collect()
+    }
+
+    fn embeddings(&self) -> Vec<&Embedding> {
+        self.nodes
+            .iter()
+            .filter(|n| !n.removed)
+            .map(|n| &n.embedding)
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len() - self.removed
+    }
+
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.entry_point = None;
+        self.max_level = 0;
+        self.removed = 0;
+    }
+}
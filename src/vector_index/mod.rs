@@ -0,0 +1,132 @@
+use crate::actors::tools::embeddings::Embedding;
+
+pub mod brute_force;
+pub mod hnsw;
+
+pub use self::{brute_force::BruteForceIndex, hnsw::HnswIndex};
+
+/// Storage for embeddings that can answer nearest neighbour queries.
+///
+/// Distances are cosine distances, `0.0` means identical direction.
+pub trait VectorIndex: Send + Sync {
+    fn insert(&mut self, embedding: Embedding);
+
+    /// Removes every embedding whose [`Embedding::source`] is `source`, returns how many were
+    /// removed.
+    fn remove_source(&mut self, source: &str) -> usize;
+
+    /// Returns at most `k` embeddings ordered from closest to furthest.
+    fn query(&self, vector: &[f32], k: usize) -> Vec<(&Embedding, f32)>;
+
+    fn embeddings(&self) -> Vec<&Embedding>;
+
+    fn len(&self) -> usize;
+
+    fn clear(&mut self);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub fn cosine_dist(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    let mut a_dot_b: f32 = 0.0;
+    let mut a_mag: f32 = 0.0;
+    let mut b_mag: f32 = 0.0;
+
+    for (a, b) in vec_a.iter().zip(vec_b) {
+        a_dot_b += a * b;
+        a_mag += a * a;
+        b_mag += b * b;
+    }
+
+    let magnitude = a_mag.sqrt() * b_mag.sqrt();
+    if magnitude == 0.0 {
+        return 1.0;
+    }
+
+    1.0 - (a_dot_b / magnitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn random_embeddings(count: usize, dimensions: usize, rng: &mut StdRng) -> Vec<Embedding> {
+        (0..count)
+            .map(|i| Embedding {
+                vector: (0..dimensions).map(|_| rng.gen_range(-1.0..1.0)).collect(),
+                graph_vertex: format!("source-{}", i % 10),
+                content: i.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cosine_distance() {
+        assert!(cosine_dist(&[1.0, 0.0], &[1.0, 0.0]).abs() < 1e-6);
+        assert!((cosine_dist(&[1.0, 0.0], &[0.0, 1.0]) - 1.0).abs() < 1e-6);
+        assert!((cosine_dist(&[1.0, 0.0], &[-1.0, 0.0]) - 2.0).abs() < 1e-6);
+        assert_eq!(cosine_dist(&[0.0, 0.0], &[1.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn hnsw_recall_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut exact = BruteForceIndex::new();
+        let mut approximate = HnswIndex::new();
+        for embedding in random_embeddings(1000, 32, &mut rng) {
+            exact.insert(embedding.clone());
+            approximate.insert(embedding);
+        }
+
+        let mut found = 0;
+        let queries = random_embeddings(50, 32, &mut rng);
+        for query in &queries {
+            let expected: Vec<&str> = exact
+                .query(&query.vector, 10)
+                .iter()
+                .map(|(e, _)| e.content.as_str())
+                .collect();
+            let results = approximate.query(&query.vector, 10);
+            assert_eq!(results.len(), 10);
+            assert!(results.windows(2).all(|w| w[0].1 <= w[1].1));
+            found += results
+                .iter()
+                .filter(|(e, _)| expected.contains(&e.content.as_str()))
+                .count();
+        }
+
+        let recall = found as f32 / (queries.len() * 10) as f32;
+        assert!(recall > 0.9, "recall too low: {recall}");
+    }
+
+    #[test]
+    fn remove_source() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let embeddings = random_embeddings(200, 8, &mut rng);
+        let indexes: Vec<Box<dyn VectorIndex>> =
+            vec![Box::new(BruteForceIndex::new()), Box::new(HnswIndex::new())];
+
+        for mut index in indexes {
+            for embedding in embeddings.clone() {
+                index.insert(embedding);
+            }
+
+            assert_eq!(index.remove_source("source-3"), 20);
+            assert_eq!(index.remove_source("source-3"), 0);
+            assert_eq!(index.len(), 180);
+            assert_eq!(index.embeddings().len(), 180);
+            assert!(index
+                .query(&embeddings[3].vector, 200)
+                .iter()
+                .all(|(e, _)| e.graph_vertex != "source-3"));
+
+            index.clear();
+            assert!(index.is_empty());
+            assert!(index.query(&embeddings[0].vector, 5).is_empty());
+        }
+    }
+}